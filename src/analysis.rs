@@ -0,0 +1,303 @@
+use crate::game::Game;
+use crate::gamestate::{GameState, MoveKind};
+use crate::pgn::{format_san, PgnGame};
+use crate::piece::Color;
+use crate::search::{Engine, MATE_SCORE};
+
+/// Scores are capped at this many centipawns when measuring a loss, so that choosing a slower mate or
+/// giving back part of a winning advantage is not a blunder.
+const LOSS_CAP: i32 = 1000;
+
+/// The moves of the best line written as a variation after a bad move.
+const VARIATION_LENGTH: usize = 6;
+
+/// How bad a move is, by the centipawns it loses against the engine's best move.
+///
+/// * `GOOD`: Less than 50 centipawns lost.
+/// * `INACCURACY`: 50 to 99 centipawns lost, marked `?!`.
+/// * `MISTAKE`: 100 to 299 centipawns lost, marked `?`.
+/// * `BLUNDER`: 300 centipawns or more lost, marked `??`.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Judgment {
+    GOOD,
+    INACCURACY,
+    MISTAKE,
+    BLUNDER
+}
+
+/// The engine's opinion of one move of a game.
+///
+/// Properties:
+///
+/// * `san`: The move played.
+/// * `color`: The player who moved.
+/// * `eval_before`: The score before the move with the best play, in centipawns for White.
+/// * `eval_after`: The score after the move played, in centipawns for White.
+/// * `loss`: The centipawns the move lost for the player, 0 if it was the best move.
+/// * `judgment`: The class of the move by its loss.
+/// * `best`: The engine's move in SAN, if the position had any.
+/// * `best_line`: The line the engine expected after its move, in SAN and starting with it.
+#[derive(Clone, Debug)]
+pub struct MoveAnalysis {
+    pub san: String,
+    pub color: Color,
+    pub eval_before: i32,
+    pub eval_after: i32,
+    pub loss: i32,
+    pub judgment: Judgment,
+    pub best: Option<String>,
+    pub best_line: Vec<String>,
+}
+
+impl Judgment {
+/// It classifies a move by the centipawns it lost
+    pub fn from_loss(loss: i32) -> Judgment {
+        match loss {
+            l if l >= 300 => Judgment::BLUNDER,
+            l if l >= 100 => Judgment::MISTAKE,
+            l if l >= 50 => Judgment::INACCURACY,
+            _ => Judgment::GOOD
+        }
+    }
+
+/// The name of the class as written in comments, such as "Blunder"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Judgment::GOOD => "Good move",
+            Judgment::INACCURACY => "Inaccuracy",
+            Judgment::MISTAKE => "Mistake",
+            Judgment::BLUNDER => "Blunder"
+        }
+    }
+
+/// The annotation symbol of the class, empty for good moves
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Judgment::GOOD => "",
+            Judgment::INACCURACY => "?!",
+            Judgment::MISTAKE => "?",
+            Judgment::BLUNDER => "??"
+        }
+    }
+}
+
+/// It writes a score in centipawns for White as in PGN eval comments, such as "0.35" or "#-2" for a
+/// mate in two for Black
+pub fn format_eval(score: i32) -> String {
+    if score.abs() > MATE_SCORE - 1000 {
+        let plies = MATE_SCORE - score.abs();
+        format!("#{}", (plies + 1) / 2 * score.signum())
+    } else {
+        format!("{:.2}", score as f64 / 100.0)
+    }
+}
+
+/// It searches every position of a game and judges each move by how much worse it is than the engine's
+/// best move
+///
+/// Arguments:
+///
+/// * `engine`: The engine to search with.
+/// * `game`: The game to analyze.
+/// * `depth`: The depth of the search of each position.
+///
+/// Returns:
+///
+/// The analysis of every move, in the order they were played.
+pub fn analyze_game(engine: &Engine, game: &Game, depth: u32) -> Vec<MoveAnalysis> {
+    let mut gamestate: GameState = game.start;
+    gamestate.debug_flag = false;
+    // Scores from the search are for the player in turn, they are turned to White's view here
+    let for_white = |gamestate: &GameState, score: i32| if gamestate.current_player == Color::BLACK { -score } else { score };
+
+    let mut result = engine.search(&gamestate, depth);
+    let mut analysis: Vec<MoveAnalysis> = Vec::new();
+    for &mv in &game.moves {
+        let color = gamestate.current_player;
+        let eval_before = for_white(&gamestate, result.score);
+        let best = result.best_move_kind();
+        let mut best_line: Vec<String> = Vec::new();
+        let mut line_state = gamestate;
+        for (pc, tc) in engine.principal_variation(&gamestate, VARIATION_LENGTH) {
+            best_line.push(format_san(&mut line_state, MoveKind::BOARD(pc, tc)));
+            line_state.make_move(pc, tc);
+        }
+        let san = format_san(&mut gamestate, mv);
+        let best_san = best.map(|best| format_san(&mut gamestate, best));
+
+        gamestate.make_move_kind(mv);
+        result = engine.search(&gamestate, depth);
+        let eval_after = for_white(&gamestate, result.score);
+        let capped = |score: i32| score.clamp(-LOSS_CAP, LOSS_CAP);
+        let loss = if Some(mv) == best {
+            0
+        } else if color == Color::WHITE {
+            (capped(eval_before) - capped(eval_after)).max(0)
+        } else {
+            (capped(eval_after) - capped(eval_before)).max(0)
+        };
+        analysis.push(MoveAnalysis { san, color, eval_before, eval_after, loss, judgment: Judgment::from_loss(loss), best: best_san, best_line });
+    }
+    analysis
+}
+
+/// It writes the game as PGN with the analysis: every move gets its eval, bad moves get their symbol,
+/// a comment naming the best move and a variation with the best line
+///
+/// Arguments:
+///
+/// * `game`: The game analyzed.
+/// * `analysis`: The analysis of its moves from analyze_game.
+/// * `result`: The result tag and the result written after the moves.
+pub fn annotated_pgn(game: &Game, analysis: &[MoveAnalysis], result: &str) -> PgnGame {
+    let mut pgn = game.to_pgn(result);
+    let mut number = game.start.fullmove_number.max(1);
+    for (i, entry) in analysis.iter().enumerate() {
+        pgn.moves[i].push_str(entry.judgment.symbol());
+        let mut text = format!("{{ [%eval {}]", format_eval(entry.eval_after));
+        if entry.judgment != Judgment::GOOD {
+            text.push_str(&format!(" {}.", entry.judgment.name()));
+            if let Some(best) = &entry.best {
+                text.push_str(&format!(" {} was best.", best));
+            }
+        }
+        text.push_str(" }");
+        if entry.judgment != Judgment::GOOD && !entry.best_line.is_empty() {
+            let mut white = entry.color == Color::WHITE;
+            let mut line_number = number;
+            text.push_str(&format!(" ( {}{}", line_number, if white { "." } else { "..." }));
+            for san in &entry.best_line {
+                if white && line_number != number {
+                    text.push_str(&format!(" {}.", line_number));
+                }
+                text.push_str(&format!(" {}", san));
+                if !white {
+                    line_number += 1;
+                }
+                white = !white;
+            }
+            text.push_str(" )");
+        }
+        pgn.annotations.push(text);
+        if entry.color == Color::BLACK {
+            number += 1;
+        }
+    }
+    pgn
+}
+
+/// It prints the analysis as a table followed by the average centipawn loss and the number of
+/// inaccuracies, mistakes and blunders of each player
+pub fn print_analysis(game: &Game, analysis: &[MoveAnalysis]) {
+    let mut number = game.start.fullmove_number.max(1);
+    for entry in analysis {
+        let numbered = if entry.color == Color::WHITE { format!("{}.", number) } else { format!("{}...", number) };
+        if entry.color == Color::BLACK {
+            number += 1;
+        }
+        let note = match (&entry.judgment, &entry.best) {
+            (Judgment::GOOD, _) => String::new(),
+            (judgment, Some(best)) => format!("{}, best was {}", judgment.name(), best),
+            (judgment, None) => judgment.name().to_string()
+        };
+        println!("{:<7} {:<9} {:>7} -> {:>7}  {}", numbered, format!("{}{}", entry.san, entry.judgment.symbol()),
+            format_eval(entry.eval_before), format_eval(entry.eval_after), note);
+    }
+    for color in [Color::WHITE, Color::BLACK] {
+        let moves: Vec<&MoveAnalysis> = analysis.iter().filter(|entry| entry.color == color).collect();
+        let count = |judgment: Judgment| moves.iter().filter(|entry| entry.judgment == judgment).count();
+        let average = moves.iter().map(|entry| entry.loss as f64).sum::<f64>() / moves.len().max(1) as f64;
+        println!("{:?}: average centipawn loss {:.0}, {} inaccuracies, {} mistakes, {} blunders", color, average,
+            count(Judgment::INACCURACY), count(Judgment::MISTAKE), count(Judgment::BLUNDER));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(fen: &str, moves: &[&str]) -> Game {
+        let start = GameState::from_fen(fen).unwrap();
+        let mut game = Game::new(start);
+        let mut gamestate = start;
+        for text in moves {
+            let mv = crate::game::parse_move(&gamestate, text).unwrap();
+            gamestate.make_move_kind(mv);
+            game.record(mv);
+        }
+        game
+    }
+
+    fn entry(color: Color, judgment: Judgment, best: &str, best_line: &[&str]) -> MoveAnalysis {
+        MoveAnalysis {
+            san: String::new(),
+            color,
+            eval_before: 0,
+            eval_after: -150,
+            loss: 150,
+            judgment,
+            best: Some(best.to_string()),
+            best_line: best_line.iter().map(|san| san.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn judgment_boundaries() {
+        for (loss, judgment) in [(-20, Judgment::GOOD), (0, Judgment::GOOD), (49, Judgment::GOOD), (50, Judgment::INACCURACY), (99, Judgment::INACCURACY),
+            (100, Judgment::MISTAKE), (299, Judgment::MISTAKE), (300, Judgment::BLUNDER), (LOSS_CAP * 2, Judgment::BLUNDER)] {
+            assert_eq!(Judgment::from_loss(loss), judgment, "{}", loss);
+        }
+    }
+
+    #[test]
+    fn evals_are_written_in_pawns_or_as_mates() {
+        assert_eq!(format_eval(0), "0.00");
+        assert_eq!(format_eval(35), "0.35");
+        assert_eq!(format_eval(-120), "-1.20");
+        assert_eq!(format_eval(MATE_SCORE - 1), "#1");
+        assert_eq!(format_eval(MATE_SCORE - 3), "#2");
+        assert_eq!(format_eval(-(MATE_SCORE - 2)), "#-1");
+        assert_eq!(format_eval(-(MATE_SCORE - 4)), "#-2");
+        assert_eq!(format_eval(MATE_SCORE - 1000), "990.00");
+    }
+
+    #[test]
+    fn variations_are_numbered_after_white_and_black_moves() {
+        let game = game("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 7", &["a2a3", "a7a6", "h2h3"]);
+        let analysis = vec![
+            entry(Color::WHITE, Judgment::MISTAKE, "e4", &["e4", "e5", "Nf3"]),
+            entry(Color::BLACK, Judgment::BLUNDER, "e5", &["e5", "Nf3", "Nc6", "Bb5"]),
+            MoveAnalysis { judgment: Judgment::GOOD, loss: 0, ..entry(Color::WHITE, Judgment::GOOD, "h3", &["h3"]) },
+        ];
+        let pgn = annotated_pgn(&game, &analysis, "*");
+        assert_eq!(pgn.moves, vec!["a3?", "a6??", "h3"]);
+        assert_eq!(pgn.annotations, vec![
+            "{ [%eval -1.50] Mistake. e4 was best. } ( 7. e4 e5 8. Nf3 )",
+            "{ [%eval -1.50] Blunder. e5 was best. } ( 7... e5 8. Nf3 Nc6 9. Bb5 )",
+            "{ [%eval -1.50] }",
+        ]);
+        assert!(pgn.to_pgn().contains("7. a3? { [%eval -1.50] Mistake. e4 was best. } ( 7. e4 e5 8. Nf3 ) 7... a6??"));
+
+        // A game starting with Black's move numbers its first variation from there
+        let game = game_from_black();
+        let analysis = vec![entry(Color::BLACK, Judgment::INACCURACY, "e5", &["e5", "Nf3"])];
+        assert_eq!(annotated_pgn(&game, &analysis, "*").annotations, vec!["{ [%eval -1.50] Inaccuracy. e5 was best. } ( 3... e5 4. Nf3 )"]);
+    }
+
+    fn game_from_black() -> Game {
+        game("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3", &["a7a6"])
+    }
+
+    #[test]
+    fn hanging_the_queen_is_a_blunder() {
+        let game = game("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", &["d1d7"]);
+        let analysis = analyze_game(&Engine::new(), &game, 2);
+        assert_eq!(analysis.len(), 1);
+        assert_eq!(analysis[0].san, "Qd7+");
+        assert_eq!(analysis[0].color, Color::WHITE);
+        assert_eq!(analysis[0].judgment, Judgment::BLUNDER);
+        assert!(analysis[0].loss >= 300);
+        assert_ne!(analysis[0].best.as_deref(), Some("Qd7+"));
+        assert_eq!(analysis[0].best_line.first(), analysis[0].best.as_ref());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::gamestate::{GameState, BISHOP_DIRECTIONS, ROOK_DIRECTIONS};
+use crate::piece::{Color, PieceType};
+
+/// Three pieces on one line: a sliding piece, a piece in front of it and a target behind that piece.
+///
+/// Properties:
+///
+/// * `piece`: The piece in the middle, pinned or uncovering a check when it moves away.
+/// * `attacker`: The rook, bishop or queen behind the piece.
+/// * `target`: The king on the other end of the line.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct LineUp {
+    pub piece: usize,
+    pub attacker: usize,
+    pub target: usize,
+}
+
+/// It tells whether a sliding piece of the given type moves along a line in the given direction
+fn slides_along(piecetype: PieceType, direction: (isize, isize)) -> bool {
+    let diagonal = direction.0 != 0 && direction.1 != 0;
+    match piecetype {
+        PieceType::QUEEN => true,
+        PieceType::BISHOP => diagonal,
+        PieceType::ROOK(_) => !diagonal,
+        _ => false
+    }
+}
+
+/// It walks from `tile` in a direction and returns the first two pieces met
+fn first_two_pieces(gamestate: &GameState, tile: usize, direction: (isize, isize)) -> (Option<usize>, Option<usize>) {
+    let mut found: Vec<usize> = Vec::new();
+    let mut coord = tile;
+    while let Some(next) = GameState::step(coord, direction.0, direction.1) {
+        coord = next;
+        if gamestate.board[coord].is_some() {
+            found.push(coord);
+            if found.len() == 2 {
+                break;
+            }
+        }
+    }
+    (found.first().copied(), found.get(1).copied())
+}
+
+/// It finds the lines from the king of `king_color` where the first piece is of `piece_color` and the
+/// second is a sliding piece of the other color than the king, moving along that line
+fn line_ups(gamestate: &GameState, king_color: Color, piece_color: Color) -> Vec<LineUp> {
+    let king = match king_color {
+        Color::WHITE => gamestate.wkc,
+        Color::BLACK => gamestate.bkc,
+        Color::UNCOLORED => return Vec::new()
+    };
+    if king > 63 {
+        return Vec::new();
+    }
+    let mut v: Vec<LineUp> = Vec::new();
+    for direction in ROOK_DIRECTIONS.iter().chain(BISHOP_DIRECTIONS.iter()) {
+        if let (Some(piece), Some(attacker)) = first_two_pieces(gamestate, king, *direction) {
+            let front = gamestate.board[piece].map(|p| p.color);
+            let back = gamestate.board[attacker];
+            if front == Some(piece_color) && back.is_some_and(|p| p.color == king_color.opposite() && slides_along(p.piecetype, *direction)) {
+                v.push(LineUp { piece, attacker, target: king });
+            }
+        }
+    }
+    v
+}
+
+/// It finds the pieces of the given color that are pinned to their king
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to look at.
+/// * `color`: The color of the pinned pieces.
+///
+/// Returns:
+///
+/// A pin for every pinned piece, with the piece pinning it.
+pub fn pins(gamestate: &GameState, color: Color) -> Vec<LineUp> {
+    line_ups(gamestate, color, color)
+}
+
+/// It finds the pieces of the given color that give a discovered check by moving off their line, since
+/// a rook, bishop or queen of the same color stands behind them
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to look at.
+/// * `color`: The color of the checking side.
+///
+/// Returns:
+///
+/// A line-up for every piece that uncovers a check, with the piece giving the check and the king.
+pub fn discovered_checks(gamestate: &GameState, color: Color) -> Vec<LineUp> {
+    line_ups(gamestate, color.opposite(), color)
+}
+
+/// It returns the sliding pieces of the given color that attack `tile` through exactly one other piece
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to look at.
+/// * `tile`: The tile attacked through another piece.
+/// * `color`: The color of the attacking pieces.
+///
+/// Returns:
+///
+/// Pairs of (attacker, piece in between), ordered by the attacker's coordinate.
+pub fn xray_attackers_of(gamestate: &GameState, tile: usize, color: Color) -> Vec<(usize, usize)> {
+    let mut v: Vec<(usize, usize)> = Vec::new();
+    for direction in ROOK_DIRECTIONS.iter().chain(BISHOP_DIRECTIONS.iter()) {
+        if let (Some(between), Some(attacker)) = first_two_pieces(gamestate, tile, *direction) {
+            if gamestate.board[attacker].is_some_and(|p| p.color == color && slides_along(p.piecetype, *direction)) {
+                v.push((attacker, between));
+            }
+        }
+    }
+    v.sort_unstable();
+    v
+}
+
+/// It finds the pieces of the given color that can be won: pieces attacked by the other side that are
+/// not defended, or that are attacked by a piece worth less than themselves. Kings are left out.
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to look at.
+/// * `color`: The color of the pieces that may be lost.
+///
+/// Returns:
+///
+/// The coordinates of the hanging pieces in ascending order.
+pub fn hanging_pieces(gamestate: &GameState, color: Color) -> Vec<usize> {
+    (0..64).filter(|&tile| {
+        let piece = match gamestate.board[tile] {
+            Some(piece) if piece.color == color && !matches!(piece.piecetype, PieceType::KING(_)) => piece,
+            _ => return false
+        };
+        let attackers = gamestate.attackers_of(tile, color.opposite());
+        let cheapest = attackers.iter().filter_map(|&a| gamestate.board[a]).map(|p| p.piecetype.value()).min();
+        match cheapest {
+            None => false,
+            Some(value) => value < piece.piecetype.value() || gamestate.attackers_of(tile, color).is_empty()
+        }
+    }).collect()
+}
+
+/// It counts for every tile how many pieces of the given color attack it
+///
+/// Returns:
+///
+/// The number of attackers per tile, indexed like the board.
+pub fn attack_map(gamestate: &GameState, color: Color) -> [u8; 64] {
+    let mut map = [0u8; 64];
+    for tile in 0..64 {
+        if gamestate.board[tile].is_some_and(|p| p.color == color) {
+            for attacked in gamestate.attacks_from(tile) {
+                map[attacked] += 1;
+            }
+        }
+    }
+    map
+}
+
+/// It draws the attack maps of both players next to each other, White's side at the bottom, followed by
+/// the hanging pieces, pins and discovered checks of both players
+///
+/// Returns:
+///
+/// The heatmaps and lists as lines of text, each ended by a newline.
+pub fn render_threats(gamestate: &GameState) -> String {
+    let maps = [attack_map(gamestate, Color::WHITE), attack_map(gamestate, Color::BLACK)];
+    let mut text = format!("  {:<22}{}\n", "White attacks", "Black attacks");
+    for row in (0..8).rev() {
+        for map in &maps {
+            text.push_str(&format!("{} ", row + 1));
+            for file in 0..8 {
+                match map[row * 8 + file] {
+                    0 => text.push_str(". "),
+                    n => text.push_str(&format!("{} ", n))
+                }
+            }
+            text.push_str("    ");
+        }
+        text.truncate(text.trim_end().len());
+        text.push('\n');
+    }
+    text.push_str("  a b c d e f g h       a b c d e f g h\n");
+
+    let name = |tile: usize| GameState::coordinate_translator_usize(tile).to_lowercase();
+    let names = |tiles: Vec<usize>| if tiles.is_empty() { "none".to_string() } else { tiles.into_iter().map(name).collect::<Vec<String>>().join(" ") };
+    let line_ups = |v: Vec<LineUp>| if v.is_empty() { "none".to_string() } else {
+        v.iter().map(|l| format!("{} by {}", name(l.piece), name(l.attacker))).collect::<Vec<String>>().join(", ")
+    };
+    for color in [Color::WHITE, Color::BLACK] {
+        text.push_str(&format!("{:?}: hanging {}; pinned {}; discovered checks {}\n", color,
+            names(hanging_pieces(gamestate, color)), line_ups(pins(gamestate, color)), line_ups(discovered_checks(gamestate, color))));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    fn position(fen: &str) -> GameState {
+        GameState::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn pieces_are_pinned_along_files_and_diagonals() {
+        let gamestate = position("4k3/4r3/8/b7/8/2N5/4N3/4K3 w - - 0 1");
+        let mut pinned = pins(&gamestate, Color::WHITE);
+        pinned.sort_by_key(|pin| pin.piece);
+        assert_eq!(pinned, vec![
+            LineUp { piece: square("e2"), attacker: square("e7"), target: square("e1") },
+            LineUp { piece: square("c3"), attacker: square("a5"), target: square("e1") }
+        ]);
+        assert!(pins(&gamestate, Color::BLACK).is_empty());
+
+        // A second piece on the line means there is no pin
+        let gamestate = position("4k3/4r3/8/8/4P3/8/4N3/4K3 w - - 0 1");
+        assert!(pins(&gamestate, Color::WHITE).is_empty());
+        // Nor does a piece that cannot slide along the line
+        let gamestate = position("4k3/4b3/8/8/8/8/4N3/4K3 w - - 0 1");
+        assert!(pins(&gamestate, Color::WHITE).is_empty());
+    }
+
+    #[test]
+    fn moving_piece_off_the_line_uncovers_a_check() {
+        let gamestate = position("4k3/8/8/8/8/8/4N3/4R1K1 w - - 0 1");
+        assert_eq!(discovered_checks(&gamestate, Color::WHITE), vec![LineUp { piece: square("e2"), attacker: square("e1"), target: square("e8") }]);
+        assert!(discovered_checks(&gamestate, Color::BLACK).is_empty());
+        // The same line up with a black piece in between is a pin instead
+        let gamestate = position("4k3/8/8/8/8/8/4n3/4R2K b - - 0 1");
+        assert!(discovered_checks(&gamestate, Color::WHITE).is_empty());
+        assert_eq!(pins(&gamestate, Color::BLACK).len(), 1);
+    }
+
+    #[test]
+    fn sliding_pieces_attack_through_one_piece() {
+        let gamestate = position("4k3/8/8/8/3P4/8/1B6/R3K3 w - - 0 1");
+        assert_eq!(xray_attackers_of(&gamestate, square("e5"), Color::WHITE), vec![(square("b2"), square("d4"))]);
+        // A direct attack is not an x-ray
+        assert!(xray_attackers_of(&gamestate, square("c3"), Color::WHITE).is_empty());
+        // A rook does not attack along a diagonal
+        assert!(xray_attackers_of(&gamestate, square("c3"), Color::BLACK).is_empty());
+        let gamestate = position("4k3/8/8/8/8/8/R7/R3K3 w - - 0 1");
+        assert_eq!(xray_attackers_of(&gamestate, square("a8"), Color::WHITE), vec![(square("a1"), square("a2"))]);
+    }
+
+    #[test]
+    fn undefended_or_underpriced_attacked_pieces_hang() {
+        let gamestate = position("4k3/8/8/3n4/8/8/8/3RK3 w - - 0 1");
+        assert_eq!(hanging_pieces(&gamestate, Color::BLACK), vec![square("d5")]);
+        assert!(hanging_pieces(&gamestate, Color::WHITE).is_empty());
+
+        // Defended and attacked only by a rook, the knight is safe
+        let gamestate = position("4k3/8/4p3/3n4/8/8/8/3RK3 w - - 0 1");
+        assert!(hanging_pieces(&gamestate, Color::BLACK).is_empty());
+        // Attacked by a pawn it is lost even though it is defended
+        let gamestate = position("4k3/8/4p3/3n4/2P5/8/8/3RK3 w - - 0 1");
+        assert_eq!(hanging_pieces(&gamestate, Color::BLACK), vec![square("d5")]);
+        // The king is never hanging
+        let gamestate = position("4k3/8/8/8/8/8/8/R3K2r w - - 0 1");
+        assert_eq!(hanging_pieces(&gamestate, Color::WHITE), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn attack_map_counts_the_attackers_of_every_tile() {
+        let map = attack_map(&GameState::new(), Color::WHITE);
+        assert_eq!(map[square("e3")], 2);
+        assert_eq!(map[square("f3")], 3);
+        assert_eq!(map[square("a3")], 2);
+        assert_eq!(map[square("e4")], 0);
+        // Tiles of the own pieces count too, the queen covers the king
+        assert_eq!(map[square("e1")], 1);
+        assert!(map[32..].iter().all(|&count| count == 0));
+        let black = attack_map(&GameState::new(), Color::BLACK);
+        assert_eq!(black[square("f6")], 3);
+        assert!(black[..32].iter().all(|&count| count == 0));
+    }
+}
@@ -0,0 +1,425 @@
+use crate::gamestate::{GameState, MoveKind, POCKET_PIECES};
+use crate::piece::{ChessPiece, Color, PieceType};
+use crate::variant::VariantKind;
+use std::io::{ErrorKind, Read, Write};
+
+const WHITE: Color = Color::WHITE;
+const BLACK: Color = Color::BLACK;
+
+/// The piece types in the order of their 4-bit codes, White first. Rooks and kings that have not
+/// moved get codes of their own, so castling rights survive the encoding.
+const PIECE_CODES: [(PieceType, Color); 16] = [
+    (PieceType::PAWN(true), WHITE), (PieceType::KNIGHT, WHITE), (PieceType::BISHOP, WHITE), (PieceType::ROOK(true), WHITE), (PieceType::QUEEN, WHITE), (PieceType::KING(true), WHITE),
+    (PieceType::PAWN(true), BLACK), (PieceType::KNIGHT, BLACK), (PieceType::BISHOP, BLACK), (PieceType::ROOK(true), BLACK), (PieceType::QUEEN, BLACK), (PieceType::KING(true), BLACK),
+    (PieceType::ROOK(false), WHITE), (PieceType::ROOK(false), BLACK), (PieceType::KING(false), WHITE), (PieceType::KING(false), BLACK),
+];
+
+const FLAG_BLACK: u8 = 1;
+const FLAG_CHESS960: u8 = 2;
+const FLAG_CRAZYHOUSE: u8 = 4;
+const FLAG_EN_PASSANT: u8 = 8;
+
+/// It packs a position into bytes. The layout is:
+///
+/// * 8 bytes: a bitboard of the occupied tiles, a1 in the lowest bit, little-endian.
+/// * 1 byte per two occupied tiles: a 4-bit piece code per tile in the order of the bitboard, low
+///   nibble first.
+/// * 1 byte of flags: Black to move, Chess960, Crazyhouse, en passant possible, and the en passant file
+///   in bits 4 to 6.
+/// * 1 byte: the variant, in the order of `VariantKind::ALL`.
+/// * 1 byte halfmove clock and 2 bytes fullmove number, little-endian.
+/// * Three-check only: 1 byte with the checks given by White in the low and Black in the high nibble.
+/// * Crazyhouse only: 10 bytes of pocket counts, White's P N B R Q then Black's, followed by the
+///   8-byte bitboard of promoted pieces.
+///
+/// A standard position with 32 pieces takes 29 bytes.
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to pack.
+///
+/// Returns:
+///
+/// The packed position.
+pub fn encode_position(gamestate: &GameState) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(32);
+    let mut occupied: u64 = 0;
+    let mut codes: Vec<u8> = Vec::with_capacity(32);
+    for (coord, tile) in gamestate.board.iter().enumerate() {
+        if let Some(piece) = tile {
+            occupied |= 1 << coord;
+            codes.push(piece_code(*piece));
+        }
+    }
+    bytes.extend_from_slice(&occupied.to_le_bytes());
+    for pair in codes.chunks(2) {
+        bytes.push(pair[0] | pair.get(1).map_or(0, |code| code << 4));
+    }
+
+    let mut flags: u8 = 0;
+    if gamestate.current_player == BLACK {
+        flags |= FLAG_BLACK;
+    }
+    if gamestate.chess960 {
+        flags |= FLAG_CHESS960;
+    }
+    if gamestate.variant.rules().has_pockets() {
+        flags |= FLAG_CRAZYHOUSE;
+    }
+    if let Some(coord) = gamestate.en_passant {
+        flags |= FLAG_EN_PASSANT | (GameState::coord_x(coord) as u8) << 4;
+    }
+    bytes.push(flags);
+    bytes.push(VariantKind::ALL.iter().position(|&variant| variant == gamestate.variant).unwrap_or(0) as u8);
+    bytes.push(gamestate.halfmove_clock.min(255) as u8);
+    bytes.extend_from_slice(&(gamestate.fullmove_number.min(u16::MAX as u32) as u16).to_le_bytes());
+
+    if gamestate.variant == VariantKind::THREECHECK {
+        bytes.push(gamestate.checks_given[0].min(15) | gamestate.checks_given[1].min(15) << 4);
+    }
+    if gamestate.variant.rules().has_pockets() {
+        for pocket in gamestate.pockets {
+            bytes.extend_from_slice(&pocket);
+        }
+        bytes.extend_from_slice(&gamestate.promoted.to_le_bytes());
+    }
+    bytes
+}
+
+/// It unpacks a position packed by `encode_position`
+///
+/// Arguments:
+///
+/// * `bytes`: The packed position, extra bytes after it are ignored.
+///
+/// Returns:
+///
+/// The position and the number of bytes it took, or a message if the bytes are not a position.
+pub fn decode_position(bytes: &[u8]) -> Result<(GameState, usize), String> {
+    let mut reader = bytes;
+    let gamestate = read_position(&mut reader)?.ok_or("There is no position to read")?;
+    Ok((gamestate, bytes.len() - reader.len()))
+}
+
+/// Bit 15 of a packed move marks a drop.
+const MOVE_DROP: u16 = 1 << 15;
+
+/// It packs a move into 16 bits: the target tile in bits 0 to 5 and the start tile in bits 6 to 11.
+/// Bits 12 to 14 hold the promotion piece as in Polyglot (1 knight, 2 bishop, 3 rook, 4 queen), 0
+/// standing for a queen on moves to the last row. Drops set bit 15 and keep the dropped piece in
+/// bits 12 to 14 (0 pawn, 1 knight, 2 bishop, 3 rook, 4 queen).
+pub fn encode_move(mv: MoveKind) -> u16 {
+    match mv {
+        MoveKind::BOARD(pc, tc) => (pc as u16) << 6 | tc as u16,
+        MoveKind::PROMOTION(pc, tc, piecetype) => (GameState::pocket_index(piecetype).unwrap_or(0) as u16) << 12 | (pc as u16) << 6 | tc as u16,
+        MoveKind::DROP(piecetype, tc) => MOVE_DROP | (GameState::pocket_index(piecetype).unwrap_or(0) as u16) << 12 | tc as u16
+    }
+}
+
+/// It unpacks a move packed by `encode_move`, None if the bits are not a move
+pub fn decode_move(bits: u16) -> Option<MoveKind> {
+    let tc = (bits & 63) as usize;
+    let pc = (bits >> 6 & 63) as usize;
+    let piece = (bits >> 12 & 7) as usize;
+    if bits & MOVE_DROP != 0 {
+        return POCKET_PIECES.get(piece).map(|&piecetype| MoveKind::DROP(piecetype, tc));
+    }
+    match piece {
+        _ if pc == tc => None,
+        0 | 4 => Some(MoveKind::BOARD(pc, tc)),
+        1..=3 => Some(MoveKind::PROMOTION(pc, tc, POCKET_PIECES[piece])),
+        _ => None
+    }
+}
+
+/// Writes packed positions one after the other, for files with a large number of positions.
+pub struct PositionWriter<W: Write> {
+    writer: W,
+    pub count: usize,
+}
+
+impl<W: Write> PositionWriter<W> {
+/// It creates a writer on top of a file or any other byte sink, wrap files in a `BufWriter`
+    pub fn new(writer: W) -> PositionWriter<W> {
+        PositionWriter { writer, count: 0 }
+    }
+
+/// It appends a position
+    pub fn write(&mut self, gamestate: &GameState) -> Result<(), String> {
+        self.writer.write_all(&encode_position(gamestate)).map_err(|e| format!("Could not write position {}: {}", self.count + 1, e))?;
+        self.count += 1;
+        Ok(())
+    }
+
+/// It appends a position followed by a packed move, such as the move played or the best move
+    pub fn write_with_move(&mut self, gamestate: &GameState, mv: MoveKind) -> Result<(), String> {
+        self.write(gamestate)?;
+        self.writer.write_all(&encode_move(mv).to_le_bytes()).map_err(|e| format!("Could not write move {}: {}", self.count, e))
+    }
+
+/// It writes everything that is still buffered and returns the underlying writer
+    pub fn finish(mut self) -> Result<W, String> {
+        self.writer.flush().map_err(|e| format!("Could not write positions: {}", e))?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads packed positions one after the other without loading the whole file. As an iterator it
+/// yields each position, or an error after which it stops.
+pub struct PositionReader<R: Read> {
+    reader: R,
+    pub count: usize,
+    failed: bool,
+}
+
+impl<R: Read> PositionReader<R> {
+/// It creates a reader on top of a file or any other byte source, wrap files in a `BufReader`
+    pub fn new(reader: R) -> PositionReader<R> {
+        PositionReader { reader, count: 0, failed: false }
+    }
+
+/// It reads the next position
+///
+/// Returns:
+///
+/// The position, None at the end of the input, or a message if the input ends inside a position or
+/// is not a position.
+    pub fn read(&mut self) -> Result<Option<GameState>, String> {
+        let gamestate = read_position(&mut self.reader).map_err(|e| format!("Position {}: {}", self.count + 1, e))?;
+        if gamestate.is_some() {
+            self.count += 1;
+        }
+        Ok(gamestate)
+    }
+
+/// It reads the next position followed by a packed move, as written by `write_with_move`
+    pub fn read_with_move(&mut self) -> Result<Option<(GameState, MoveKind)>, String> {
+        let gamestate = match self.read()? {
+            Some(gamestate) => gamestate,
+            None => return Ok(None)
+        };
+        let mut bits = [0u8; 2];
+        read_exact(&mut self.reader, &mut bits).map_err(|e| format!("Move {}: {}", self.count, e))?;
+        let mv = decode_move(u16::from_le_bytes(bits)).ok_or(format!("Move {} is not a move", self.count))?;
+        Ok(Some((gamestate, mv)))
+    }
+}
+
+impl<R: Read> Iterator for PositionReader<R> {
+    type Item = Result<GameState, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.read().transpose();
+        self.failed = matches!(result, Some(Err(_)));
+        result
+    }
+}
+
+fn piece_code(piece: ChessPiece) -> u8 {
+    let piecetype = match piece.piecetype {
+        PieceType::PAWN(_) => PieceType::PAWN(true),
+        piecetype => piecetype
+    };
+    PIECE_CODES.iter().position(|&(t, c)| t == piecetype && c == piece.color).unwrap_or(0) as u8
+}
+
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<(), String> {
+    reader.read_exact(buffer).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => "The input ends inside a position".to_string(),
+        _ => e.to_string()
+    })
+}
+
+/// It reads one packed position, None if the input is already at its end
+fn read_position<R: Read>(reader: &mut R) -> Result<Option<GameState>, String> {
+    let mut occupied = [0u8; 8];
+    // A clean end of the input may only come before the first byte
+    match reader.read(&mut occupied[..1]) {
+        Ok(0) => return Ok(None),
+        Ok(_) => {},
+        Err(e) => return Err(e.to_string())
+    }
+    read_exact(reader, &mut occupied[1..])?;
+    let occupied = u64::from_le_bytes(occupied);
+    let mut codes = vec![0u8; (occupied.count_ones() as usize).div_ceil(2)];
+    read_exact(reader, &mut codes)?;
+    let mut header = [0u8; 5];
+    read_exact(reader, &mut header)?;
+    let [flags, variant, halfmove, fullmove_low, fullmove_high] = header;
+
+    let mut gamestate = GameState::new();
+    gamestate.board = [None; 64];
+    let mut i = 0;
+    for coord in 0..64 {
+        if occupied & (1 << coord) == 0 {
+            continue;
+        }
+        let code = codes[i / 2] >> (4 * (i % 2)) & 15;
+        let (piecetype, color) = PIECE_CODES[code as usize];
+        // Pawns on or behind their start row have not moved, as in `GameState::from_fen`
+        let piecetype = match piecetype {
+            PieceType::PAWN(_) => PieceType::PAWN(if color == WHITE { coord >= 16 } else { coord < 48 }),
+            piecetype => piecetype
+        };
+        gamestate.board[coord] = Some(ChessPiece::new(piecetype, color));
+        i += 1;
+    }
+    gamestate.prev_board = gamestate.board;
+    gamestate.current_player = if flags & FLAG_BLACK != 0 { BLACK } else { WHITE };
+    gamestate.chess960 = flags & FLAG_CHESS960 != 0;
+    if flags & FLAG_EN_PASSANT != 0 {
+        let row = if gamestate.current_player == WHITE { 5 } else { 2 };
+        gamestate.en_passant = Some(row * 8 + (flags >> 4 & 7) as usize);
+    } else {
+        gamestate.en_passant = None;
+    }
+    gamestate.variant = *VariantKind::ALL.get(variant as usize).ok_or(format!("Unknown variant {}", variant))?;
+    // Crazyhouse was only a flag on standard positions before it became a variant of its own
+    if flags & FLAG_CRAZYHOUSE != 0 && gamestate.variant == VariantKind::STANDARD {
+        gamestate.variant = VariantKind::CRAZYHOUSE;
+    }
+    gamestate.halfmove_clock = halfmove as u32;
+    gamestate.fullmove_number = u16::from_le_bytes([fullmove_low, fullmove_high]) as u32;
+
+    if gamestate.variant == VariantKind::THREECHECK {
+        let mut checks = [0u8; 1];
+        read_exact(reader, &mut checks)?;
+        gamestate.checks_given = [checks[0] & 15, checks[0] >> 4];
+    }
+    if gamestate.variant.rules().has_pockets() {
+        let mut pockets = [0u8; 18];
+        read_exact(reader, &mut pockets)?;
+        gamestate.pockets[0].copy_from_slice(&pockets[0..5]);
+        gamestate.pockets[1].copy_from_slice(&pockets[5..10]);
+        gamestate.promoted = u64::from_le_bytes(pockets[10..18].try_into().unwrap_or_default());
+    }
+
+    gamestate.wkc = gamestate.find_white_king();
+    gamestate.bkc = gamestate.find_black_king();
+    gamestate.old_wkc = gamestate.wkc;
+    gamestate.old_bkc = gamestate.bkc;
+    let player = gamestate.current_player;
+    if gamestate.is_checked(player) {
+        gamestate.checked_flag = true;
+        gamestate.checked_player = player;
+    }
+    Ok(Some(gamestate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(gamestate: &GameState) -> GameState {
+        let bytes = encode_position(gamestate);
+        let (decoded, used) = decode_position(&bytes).unwrap();
+        assert_eq!(used, bytes.len());
+        assert_eq!(decoded.to_fen(), gamestate.to_fen());
+        assert_eq!(decoded.variant, gamestate.variant);
+        assert_eq!(decoded.chess960, gamestate.chess960);
+        assert_eq!((decoded.wkc, decoded.bkc), (gamestate.wkc, gamestate.bkc));
+        decoded
+    }
+
+    #[test]
+    fn start_and_chess960_positions_survive_a_round_trip() {
+        assert_eq!(encode_position(&GameState::new()).len(), 29);
+        round_trip(&GameState::new());
+        for index in [0, 518, 959] {
+            let decoded = round_trip(&GameState::new_chess960(index));
+            assert!(decoded.chess960);
+            assert_eq!(decoded.castling_rights(), [true; 4]);
+        }
+    }
+
+    #[test]
+    fn crazyhouse_pockets_and_promoted_pieces_survive_a_round_trip() {
+        let gamestate = GameState::from_fen("r3k2r/8/8/8/8/8/8/R3K1Q~R[QNPnpp] w KQkq - 0 1").unwrap();
+        assert_eq!(gamestate.variant, VariantKind::CRAZYHOUSE);
+        assert_ne!(gamestate.promoted, 0);
+        let decoded = round_trip(&gamestate);
+        assert_eq!(decoded.pockets, gamestate.pockets);
+        assert_eq!(decoded.promoted, gamestate.promoted);
+    }
+
+    #[test]
+    fn three_check_counters_and_horde_survive_a_round_trip() {
+        let mut gamestate = VariantKind::THREECHECK.start_position();
+        gamestate.checks_given = [2, 1];
+        assert_eq!(round_trip(&gamestate).checks_given, [2, 1]);
+
+        let horde = VariantKind::HORDE.start_position();
+        let decoded = round_trip(&horde);
+        assert_eq!(decoded.wkc, 64);
+        assert_eq!(decoded.board.iter().flatten().filter(|piece| piece.color == WHITE).count(), 36);
+    }
+
+    #[test]
+    fn en_passant_and_odd_piece_counts_survive_a_round_trip() {
+        let gamestate = GameState::from_fen("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3").unwrap();
+        let decoded = round_trip(&gamestate);
+        assert_eq!(decoded.en_passant, GameState::parse_square("d6"));
+        assert_eq!((decoded.halfmove_clock, decoded.fullmove_number), (0, 3));
+
+        // Three pieces take two nibble bytes, the unused high nibble stays zero
+        let gamestate = GameState::from_fen("4k3/8/8/8/8/8/8/4K2R b K - 7 40").unwrap();
+        let bytes = encode_position(&gamestate);
+        assert_eq!(bytes.len(), 8 + 2 + 5);
+        assert_eq!(bytes[9] >> 4, 0);
+        let decoded = round_trip(&gamestate);
+        assert_eq!(decoded.current_player, BLACK);
+        assert_eq!(decoded.castling_rights(), [true, false, false, false]);
+    }
+
+    #[test]
+    fn moves_survive_a_round_trip() {
+        let moves = [
+            MoveKind::BOARD(12, 28),
+            MoveKind::PROMOTION(52, 60, PieceType::KNIGHT),
+            MoveKind::PROMOTION(52, 61, PieceType::BISHOP),
+            MoveKind::PROMOTION(11, 3, PieceType::ROOK(true)),
+            MoveKind::DROP(PieceType::PAWN(true), 35),
+            MoveKind::DROP(PieceType::QUEEN, 0),
+        ];
+        for mv in moves {
+            assert_eq!(decode_move(encode_move(mv)), Some(mv));
+        }
+        assert_eq!(decode_move(4 << 12 | 52 << 6 | 60), Some(MoveKind::BOARD(52, 60)));
+        assert_eq!(decode_move(12 << 6 | 12), None);
+        assert_eq!(decode_move(MOVE_DROP | 5 << 12 | 35), None);
+    }
+
+    #[test]
+    fn streams_are_read_up_to_the_end_of_the_input() {
+        let positions = [GameState::new(), GameState::new_chess960(100), VariantKind::HORDE.start_position()];
+        let mut writer = PositionWriter::new(Vec::new());
+        for gamestate in &positions {
+            writer.write(gamestate).unwrap();
+        }
+        writer.write_with_move(&GameState::new(), MoveKind::BOARD(6, 21)).unwrap();
+        assert_eq!(writer.count, 4);
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = PositionReader::new(&bytes[..]);
+        for gamestate in &positions {
+            assert_eq!(reader.read().unwrap().unwrap().to_fen(), gamestate.to_fen());
+        }
+        let (gamestate, mv) = reader.read_with_move().unwrap().unwrap();
+        assert_eq!(gamestate.to_fen(), GameState::new().to_fen());
+        assert_eq!(mv, MoveKind::BOARD(6, 21));
+        assert!(reader.read().unwrap().is_none());
+        assert!(reader.read_with_move().unwrap().is_none());
+
+        // Cut off inside the second position, the reader yields the first and then one error
+        let cut = encode_position(&positions[0]).len() + 10;
+        let results: Vec<Result<GameState, String>> = PositionReader::new(&bytes[..cut]).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].as_ref().is_err_and(|e| e.contains("Position 2") && e.contains("ends inside a position")));
+        assert!(decode_position(&bytes[..5]).is_err());
+        assert!(decode_position(&[]).is_err());
+    }
+}
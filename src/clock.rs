@@ -0,0 +1,421 @@
+use crate::piece::Color;
+use serde::{Serialize, Deserialize};
+use std::time::{Duration, Instant};
+
+/// The time a player gets back for a move.
+///
+/// * `NONE`: No time is given back.
+/// * `FISCHER`: The time is added after every move.
+/// * `BRONSTEIN`: The time used for the move is given back, up to the given amount.
+/// * `DELAY`: The clock waits the given amount before it starts counting down (simple delay).
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum Increment {
+    NONE,
+    FISCHER(Duration),
+    BRONSTEIN(Duration),
+    DELAY(Duration)
+}
+
+/// A session of a time control, for example the first 40 moves in 90 minutes.
+///
+/// Properties:
+///
+/// * `moves`: The number of moves to make in this session, None if the session lasts for the rest of the game.
+/// * `time`: The time added to the clock when the session starts.
+/// * `increment`: The time given back for every move of the session.
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub struct Session {
+    pub moves: Option<u32>,
+    pub time: Duration,
+    pub increment: Increment,
+}
+
+/// A time control made of one or more sessions. When the last session has a move count it is
+/// repeated for as long as the game goes on.
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct TimeControl {
+    pub sessions: Vec<Session>,
+}
+
+impl TimeControl {
+/// It parses a time control such as `5+3`, `90+30`, `40/90+30:30+30`, `5d3` or `5b3`
+///
+/// Sessions are separated by ':' and written as `[moves/]minutes[+seconds]`. The increment after '+'
+/// is a Fischer increment, 'd' is used instead for a simple delay and 'b' for a Bronstein delay.
+///
+/// Arguments:
+///
+/// * `arg`: The time control to parse.
+///
+/// Returns:
+///
+/// The time control, or a message describing what is wrong with the argument.
+    pub fn parse(arg: &str) -> Result<TimeControl, String> {
+        let mut sessions: Vec<Session> = Vec::new();
+        for session in arg.trim().split(':') {
+            let (moves, rest) = match session.split_once('/') {
+                Some((moves, rest)) => {
+                    let moves: u32 = moves.parse().map_err(|_| format!("Invalid move count: '{}'", moves))?;
+                    if moves == 0 {
+                        return Err("A session needs at least one move".to_string());
+                    }
+                    (Some(moves), rest)
+                },
+                None => (None, session)
+            };
+            let (minutes, increment) = match rest.find(['+', 'd', 'b']) {
+                Some(i) => {
+                    let seconds: f64 = rest[i + 1..].parse().map_err(|_| format!("Invalid increment: '{}'", &rest[i + 1..]))?;
+                    let seconds = Duration::from_secs_f64(seconds);
+                    let increment = match &rest[i..i + 1] {
+                        "+" => Increment::FISCHER(seconds),
+                        "d" => Increment::DELAY(seconds),
+                        _ => Increment::BRONSTEIN(seconds)
+                    };
+                    (&rest[..i], increment)
+                },
+                None => (rest, Increment::NONE)
+            };
+            let minutes: f64 = minutes.parse().map_err(|_| format!("Invalid number of minutes: '{}'", minutes))?;
+            if minutes < 0.0 {
+                return Err(format!("Invalid number of minutes: '{}'", minutes));
+            }
+            sessions.push(Session { moves, time: Duration::from_secs_f64(minutes * 60.0), increment });
+        }
+        if sessions.iter().all(|s| s.time.is_zero()) {
+            return Err("The time control gives no time at all".to_string());
+        }
+        Ok(TimeControl { sessions })
+    }
+
+/// It writes the time control in the notation read by `parse`
+    pub fn format(&self) -> String {
+        let sessions: Vec<String> = self.sessions.iter().map(|session| {
+            let mut text = match session.moves {
+                Some(moves) => format!("{}/", moves),
+                None => String::new()
+            };
+            text.push_str(&(session.time.as_secs_f64() / 60.0).to_string());
+            match session.increment {
+                Increment::FISCHER(seconds) => text.push_str(&format!("+{}", seconds.as_secs_f64())),
+                Increment::DELAY(seconds) => text.push_str(&format!("d{}", seconds.as_secs_f64())),
+                Increment::BRONSTEIN(seconds) => text.push_str(&format!("b{}", seconds.as_secs_f64())),
+                Increment::NONE => {}
+            }
+            text
+        }).collect();
+        sessions.join(":")
+    }
+
+    fn session(&self, index: usize) -> Session {
+        self.sessions[index.min(self.sessions.len() - 1)]
+    }
+}
+
+/// A chess clock for both players.
+///
+/// Properties:
+///
+/// * `time_control`: The time control the clock was started with.
+/// * `remaining`: The time left for White and Black, not counting the running turn.
+/// * `moves`: The number of moves made by White and Black.
+/// * `sessions`: The session White and Black are playing in.
+/// * `running`: The player whose clock is running, UNCOLORED when the clock is stopped.
+/// * `flagged`: The player whose time ran out, UNCOLORED as long as both have time left.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Clock {
+    pub time_control: TimeControl,
+    pub remaining: [Duration; 2],
+    pub moves: [u32; 2],
+    pub sessions: [usize; 2],
+    pub running: Color,
+    pub flagged: Color,
+    #[serde(skip)]
+    turn_started: Option<Instant>,
+}
+
+impl Clock {
+/// It creates a stopped clock with the time of the first session on both sides
+///
+/// Arguments:
+///
+/// * `time_control`: The time control to play with.
+    pub fn new(time_control: TimeControl) -> Clock {
+        let time = time_control.session(0).time;
+        Clock {
+            time_control,
+            remaining: [time, time],
+            moves: [0, 0],
+            sessions: [0, 0],
+            running: Color::UNCOLORED,
+            flagged: Color::UNCOLORED,
+            turn_started: None,
+        }
+    }
+
+    fn index(color: Color) -> usize {
+        match color {
+            Color::WHITE => 0,
+            Color::BLACK => 1,
+            Color::UNCOLORED => panic!("Color is not colored!")
+        }
+    }
+
+/// It starts the clock of the given player
+    pub fn start(&mut self, color: Color) {
+        self.running = color;
+        self.turn_started = Some(Instant::now());
+    }
+
+/// It stops the clock, charging the running player for the time spent so far
+    pub fn stop(&mut self) {
+        if self.running != Color::UNCOLORED {
+            let elapsed = self.elapsed();
+            let i = Clock::index(self.running);
+            self.remaining[i] = self.remaining[i].saturating_sub(self.charged(elapsed));
+            if self.remaining[i].is_zero() {
+                self.flagged = self.running;
+            }
+        }
+        self.running = Color::UNCOLORED;
+        self.turn_started = None;
+    }
+
+/// It ends the turn of the running player, as when pressing the button of a chess clock, and starts
+/// the clock of the other player
+///
+/// Returns:
+///
+/// The player who ran out of time, UNCOLORED if the move was made in time.
+    pub fn press(&mut self) -> Color {
+        let elapsed = self.elapsed();
+        self.press_after(elapsed)
+    }
+
+/// It ends the turn of the running player as if `elapsed` time was spent on the move
+///
+/// Arguments:
+///
+/// * `elapsed`: The time spent on the move.
+///
+/// Returns:
+///
+/// The player who ran out of time, UNCOLORED if the move was made in time.
+    pub fn press_after(&mut self, elapsed: Duration) -> Color {
+        let color = self.running;
+        if color == Color::UNCOLORED || self.flagged != Color::UNCOLORED {
+            return self.flagged;
+        }
+        let i = Clock::index(color);
+        let session = self.time_control.session(self.sessions[i]);
+
+        let charged = self.charged(elapsed);
+        if charged >= self.remaining[i] {
+            self.remaining[i] = Duration::ZERO;
+            self.flagged = color;
+            self.running = Color::UNCOLORED;
+            self.turn_started = None;
+            return color;
+        }
+        self.remaining[i] -= charged;
+        match session.increment {
+            Increment::FISCHER(increment) => self.remaining[i] += increment,
+            Increment::BRONSTEIN(delay) => self.remaining[i] += elapsed.min(delay),
+            Increment::DELAY(_) | Increment::NONE => {}
+        }
+
+        self.moves[i] += 1;
+        let moves_before: u32 = (0..self.sessions[i] + 1)
+            .map(|s| self.time_control.session(s).moves.unwrap_or(u32::MAX))
+            .fold(0u32, |a, b| a.saturating_add(b));
+        if self.moves[i] == moves_before {
+            self.sessions[i] += 1;
+            self.remaining[i] += self.time_control.session(self.sessions[i]).time;
+        }
+
+        self.start(color.opposite());
+        Color::UNCOLORED
+    }
+
+/// It returns the time left for the given player, counting the running turn
+    pub fn remaining(&self, color: Color) -> Duration {
+        let remaining = self.remaining[Clock::index(color)];
+        if color == self.running {
+            remaining.saturating_sub(self.charged(self.elapsed()))
+        } else {
+            remaining
+        }
+    }
+
+/// It checks whether the running player has run out of time, flagging them if they have
+///
+/// Returns:
+///
+/// The player who ran out of time, UNCOLORED if both players have time left.
+    pub fn check_flag(&mut self) -> Color {
+        if self.flagged == Color::UNCOLORED && self.running != Color::UNCOLORED && self.remaining(self.running).is_zero() {
+            self.stop();
+        }
+        self.flagged
+    }
+
+/// It decides how long the engine may think about its next move. The remaining time is spread over
+/// the moves left in the session, or over 30 moves in sudden death, and most of the increment is
+/// spent on top of that.
+///
+/// Arguments:
+///
+/// * `color`: The player the engine is moving for.
+///
+/// Returns:
+///
+/// The time to spend on the move.
+    pub fn think_time(&self, color: Color) -> Duration {
+        let i = Clock::index(color);
+        let remaining = self.remaining(color);
+        let session = self.time_control.session(self.sessions[i]);
+        let moves_to_go: u32 = match session.moves {
+            Some(moves) => {
+                let played: u32 = (0..self.sessions[i])
+                    .map(|s| self.time_control.session(s).moves.unwrap_or(0))
+                    .sum();
+                (moves + played).saturating_sub(self.moves[i]).max(1)
+            },
+            None => 30
+        };
+        let bonus = match session.increment {
+            Increment::FISCHER(d) | Increment::BRONSTEIN(d) | Increment::DELAY(d) => d * 3 / 4,
+            Increment::NONE => Duration::ZERO
+        };
+        let think_time = remaining / (moves_to_go + 1) + bonus;
+        // Never plan to use more than half of what is left
+        think_time.min(remaining / 2)
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self.turn_started {
+            Some(started) => started.elapsed(),
+            None => Duration::ZERO
+        }
+    }
+
+    fn charged(&self, elapsed: Duration) -> Duration {
+        if self.running == Color::UNCOLORED {
+            return elapsed;
+        }
+        let session = self.time_control.session(self.sessions[Clock::index(self.running)]);
+        match session.increment {
+            Increment::DELAY(delay) => elapsed.saturating_sub(delay),
+            _ => elapsed
+        }
+    }
+
+/// It formats the time left for the given player as `h:mm:ss`, or `m:ss.t` below one hour
+    pub fn format_remaining(&self, color: Color) -> String {
+        let remaining = self.remaining(color);
+        let secs = remaining.as_secs();
+        if secs >= 3600 {
+            format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+        } else {
+            format!("{}:{:02}.{}", secs / 60, secs % 60, remaining.subsec_millis() / 100)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(arg: &str) -> Clock {
+        let mut clock = Clock::new(TimeControl::parse(arg).unwrap());
+        clock.start(Color::WHITE);
+        clock
+    }
+
+    #[test]
+    fn parse_and_format_round_trip() {
+        for arg in ["5", "5+3", "90+30", "40/90+30:30+30", "5d3", "5b3", "0.5+0.5", "40/120:20/60:15"] {
+            assert_eq!(TimeControl::parse(arg).unwrap().format(), arg);
+        }
+        let time_control = TimeControl::parse("40/90+30:30+30").unwrap();
+        assert_eq!(time_control.sessions[0], Session { moves: Some(40), time: Duration::from_secs(5400), increment: Increment::FISCHER(Duration::from_secs(30)) });
+        assert_eq!(time_control.sessions[1], Session { moves: None, time: Duration::from_secs(1800), increment: Increment::FISCHER(Duration::from_secs(30)) });
+        for arg in ["", "0", "x+3", "5+x", "0/5", "-5"] {
+            assert!(TimeControl::parse(arg).is_err(), "{} should not parse", arg);
+        }
+    }
+
+    #[test]
+    fn fischer_increment_is_added_after_the_move() {
+        let mut clock = started("5+3");
+        assert_eq!(clock.press_after(Duration::from_secs(10)), Color::UNCOLORED);
+        assert_eq!(clock.remaining[0], Duration::from_secs(293));
+        assert_eq!(clock.running, Color::BLACK);
+        assert_eq!(clock.press_after(Duration::from_secs(1)), Color::UNCOLORED);
+        assert_eq!(clock.remaining[1], Duration::from_secs(302));
+        assert_eq!(clock.moves, [1, 1]);
+    }
+
+    #[test]
+    fn bronstein_and_simple_delay_never_add_time() {
+        for arg in ["5b3", "5d3"] {
+            let mut clock = started(arg);
+            clock.press_after(Duration::from_secs(2));
+            assert_eq!(clock.remaining[0], Duration::from_secs(300), "{}", arg);
+            clock.press_after(Duration::from_secs(10));
+            assert_eq!(clock.remaining[1], Duration::from_secs(293), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn next_session_starts_after_its_move_count() {
+        let mut clock = started("40/90+30:30+30");
+        for _ in 0..39 {
+            clock.press_after(Duration::from_secs(1));
+            clock.press_after(Duration::from_secs(1));
+        }
+        assert_eq!(clock.sessions, [0, 0]);
+        assert_eq!(clock.remaining[0], Duration::from_secs(5400 + 39 * 29));
+        clock.press_after(Duration::from_secs(1));
+        assert_eq!(clock.sessions, [1, 0]);
+        assert_eq!(clock.remaining[0], Duration::from_secs(5400 + 40 * 29 + 1800));
+        clock.press_after(Duration::from_secs(1));
+        assert_eq!(clock.sessions, [1, 1]);
+
+        // The last session lasts for the rest of the game
+        for _ in 0..100 {
+            clock.press_after(Duration::from_secs(1));
+        }
+        assert_eq!(clock.sessions, [1, 1]);
+    }
+
+    #[test]
+    fn flag_falls_when_the_time_runs_out() {
+        let mut clock = started("1+5");
+        assert_eq!(clock.press_after(Duration::from_secs(61)), Color::WHITE);
+        assert_eq!(clock.flagged, Color::WHITE);
+        assert_eq!(clock.remaining[0], Duration::ZERO);
+        assert_eq!(clock.running, Color::UNCOLORED);
+        // The increment does not save a flagged player and the clock stays stopped
+        assert_eq!(clock.press_after(Duration::from_secs(1)), Color::WHITE);
+        assert_eq!(clock.moves, [0, 0]);
+
+        // A simple delay is not charged
+        let mut clock = started("1d5");
+        assert_eq!(clock.press_after(Duration::from_secs(64)), Color::UNCOLORED);
+        assert_eq!(clock.remaining[0], Duration::from_secs(1));
+    }
+
+    #[test]
+    fn think_time_spreads_the_remaining_time() {
+        let clock = Clock::new(TimeControl::parse("5").unwrap());
+        assert_eq!(clock.think_time(Color::WHITE), Duration::from_secs(300) / 31);
+
+        let clock = Clock::new(TimeControl::parse("40/90+30:30+30").unwrap());
+        assert_eq!(clock.think_time(Color::BLACK), Duration::from_secs(5400) / 41 + Duration::from_millis(22500));
+
+        // Never more than half of the remaining time
+        let clock = Clock::new(TimeControl::parse("1/1+60").unwrap());
+        assert_eq!(clock.think_time(Color::WHITE), Duration::from_secs(30));
+    }
+}
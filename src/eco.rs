@@ -0,0 +1,263 @@
+use crate::game::Game;
+use crate::gamestate::{GameState, MoveKind};
+use crate::pgn::{parse_pgn, parse_san};
+use crate::transposition::position_hash;
+use std::collections::HashMap;
+
+/// The files looked for in the working directory when the program starts.
+pub const DEFAULT_ECO_FILES: [&str; 2] = ["eco.tsv", "eco.pgn"];
+
+/// A named opening line.
+///
+/// Properties:
+///
+/// * `eco`: The Encyclopaedia of Chess Openings code, such as "C50".
+/// * `name`: The name of the opening, such as "Italian Game: Giuoco Piano".
+/// * `moves`: The number of plies of the line.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Opening {
+    pub eco: String,
+    pub name: String,
+    pub moves: usize,
+}
+
+/// The openings of an ECO file, found by the position at the end of their line so that a game
+/// reaching the position by another move order gets the same name.
+///
+/// Properties:
+///
+/// * `positions`: The openings by the hash of their final position, see position_key.
+#[derive(Clone, Debug, Default)]
+pub struct EcoDatabase {
+    positions: HashMap<u64, Opening>,
+}
+
+/// It hashes a position for the database. The en passant tile is left out: it depends on the move
+/// order and would keep transpositions apart.
+pub fn position_key(gamestate: &GameState) -> u64 {
+    let mut position: GameState = *gamestate;
+    position.en_passant = None;
+    position_hash(&position)
+}
+
+impl EcoDatabase {
+/// It reads an ECO file: PGN games with `ECO`, `Opening` and `Variation` tags, or, for files not
+/// ending in ".pgn", tab separated lines of code, name and moves as in the Lichess chess-openings files
+///
+/// Arguments:
+///
+/// * `path`: The file to read.
+///
+/// Returns:
+///
+/// The database, or a message if the file cannot be read or one of its lines is not playable.
+    pub fn load(path: &str) -> Result<EcoDatabase, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        if path.to_lowercase().ends_with(".pgn") {
+            EcoDatabase::from_pgn(&text)
+        } else {
+            EcoDatabase::from_tsv(&text)
+        }
+    }
+
+/// It loads the first of the default files that exists, None if there is none or it cannot be read
+    pub fn find_default() -> Option<EcoDatabase> {
+        let path = DEFAULT_ECO_FILES.iter().find(|path| std::path::Path::new(path).exists())?;
+        match EcoDatabase::load(path) {
+            Ok(database) => Some(database),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        }
+    }
+
+/// It reads openings from PGN games, named by their `Opening` and `Variation` tags
+    pub fn from_pgn(text: &str) -> Result<EcoDatabase, String> {
+        let mut database = EcoDatabase::default();
+        for game in parse_pgn(text) {
+            let eco = game.tag("ECO").unwrap_or("").to_string();
+            let name = match (game.tag("Opening"), game.tag("Variation")) {
+                (Some(opening), Some(variation)) => format!("{}: {}", opening, variation),
+                (Some(opening), None) => opening.to_string(),
+                (None, _) => continue
+            };
+            database.add(eco, name, &game.moves)?;
+        }
+        Ok(database)
+    }
+
+/// It reads openings from tab separated lines of ECO code, name and moves such as "1. e4 e5 2. Nf3",
+/// skipping a header line
+    pub fn from_tsv(text: &str) -> Result<EcoDatabase, String> {
+        let mut database = EcoDatabase::default();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if line.trim().is_empty() || fields[0] == "eco" {
+                continue;
+            }
+            if fields.len() < 3 {
+                return Err(format!("Line {}: expected the ECO code, the name and the moves", i + 1));
+            }
+            let moves = parse_pgn(fields[2]).into_iter().next().map(|game| game.moves).unwrap_or_default();
+            database.add(fields[0].to_string(), fields[1].to_string(), &moves).map_err(|e| format!("Line {}: {}", i + 1, e))?;
+        }
+        Ok(database)
+    }
+
+/// It plays a line from the standard start position and stores the opening under its final position.
+/// A position already named keeps its first name.
+    fn add(&mut self, eco: String, name: String, moves: &[String]) -> Result<(), String> {
+        let mut gamestate = GameState::new();
+        gamestate.debug_flag = false;
+        for san in moves {
+            let mv = parse_san(&mut gamestate, san).ok_or(format!("The move '{}' of {} is not legal", san, name))?;
+            gamestate.make_move_kind(mv);
+        }
+        if !moves.is_empty() {
+            self.positions.entry(position_key(&gamestate)).or_insert(Opening { eco, name, moves: moves.len() });
+        }
+        Ok(())
+    }
+
+/// The number of named positions
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+/// It tells whether the database names no position
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+/// It returns the opening of a position, if the database names it
+    pub fn lookup(&self, gamestate: &GameState) -> Option<&Opening> {
+        self.positions.get(&position_key(gamestate))
+    }
+
+/// It names the opening of a game by the last of its positions found in the database
+///
+/// Arguments:
+///
+/// * `start`: The position the game started from.
+/// * `moves`: The moves of the game.
+    pub fn classify(&self, start: &GameState, moves: &[MoveKind]) -> Option<&Opening> {
+        let mut gamestate: GameState = *start;
+        gamestate.debug_flag = false;
+        let mut opening = None;
+        for &mv in moves {
+            gamestate.make_move_kind(mv);
+            opening = self.lookup(&gamestate).or(opening);
+        }
+        opening
+    }
+
+/// It stores the opening of the game in its `ECO` and `Opening` metadata, which are written as PGN tags
+///
+/// Returns:
+///
+/// The opening, None if no position of the game is named and the metadata is left as it was.
+    pub fn tag(&self, game: &mut Game) -> Option<Opening> {
+        let opening = self.classify(&game.start, &game.moves)?.clone();
+        game.metadata.insert("ECO".to_string(), opening.eco.clone());
+        game.metadata.insert("Opening".to_string(), opening.name.clone());
+        Some(opening)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TSV: &str = "eco\tname\tpgn\n\
+        C20\tKing's Pawn Game\t1. e4 e5\n\
+        C50\tItalian Game\t1. e4 e5 2. Nf3 Nc6 3. Bc4\n\
+        C50\tItalian Game: Copy\t1. e4 e5 2. Nf3 Nc6 3. Bc4\n\
+        \n\
+        C60\tRuy Lopez\t1. e4 e5 2. Nf3 Nc6 3. Bb5\n";
+
+    const PGN: &str = "[ECO \"B20\"]\n[Opening \"Sicilian Defense\"]\n\n1. e4 c5 *\n\n\
+        [ECO \"B27\"]\n[Opening \"Sicilian Defense\"]\n[Variation \"Hyperaccelerated Dragon\"]\n\n1. e4 c5 2. Nf3 g6 *\n\n\
+        [ECO \"A00\"]\n\n1. a3 *\n";
+
+    fn play(moves: &[&str]) -> Game {
+        let mut gamestate = GameState::new();
+        let mut game = Game::new(gamestate);
+        for san in moves {
+            let mv = parse_san(&mut gamestate, san).unwrap();
+            gamestate.make_move_kind(mv);
+            game.record(mv);
+        }
+        game
+    }
+
+    #[test]
+    fn tsv_lines_are_read_and_the_first_name_of_a_position_is_kept() {
+        let database = EcoDatabase::from_tsv(TSV).unwrap();
+        assert_eq!(database.len(), 3);
+        let game = play(&["e4", "e5", "Nf3", "Nc6", "Bc4"]);
+        let opening = database.classify(&game.start, &game.moves).unwrap();
+        assert_eq!(opening, &Opening { eco: "C50".to_string(), name: "Italian Game".to_string(), moves: 5 });
+
+        assert!(EcoDatabase::from_tsv("C20\tKing's Pawn Game").unwrap_err().starts_with("Line 1:"));
+        assert!(EcoDatabase::from_tsv("eco\tname\tpgn\nC20\tBroken\t1. e4 e4").unwrap_err().starts_with("Line 2: The move 'e4' of Broken"));
+    }
+
+    #[test]
+    fn pgn_games_are_named_by_their_opening_and_variation_tags() {
+        let database = EcoDatabase::from_pgn(PGN).unwrap();
+        assert_eq!(database.len(), 2);
+        let game = play(&["e4", "c5", "Nf3", "g6", "d4"]);
+        let opening = database.classify(&game.start, &game.moves).unwrap();
+        assert_eq!(opening.eco, "B27");
+        assert_eq!(opening.name, "Sicilian Defense: Hyperaccelerated Dragon");
+        assert!(database.classify(&game.start, &play(&["a3"]).moves).is_none());
+    }
+
+    #[test]
+    fn files_are_read_by_their_extension() {
+        for (extension, text, eco) in [("pgn", PGN, "B20"), ("tsv", TSV, "C20")] {
+            let path = std::env::temp_dir().join(format!("chess-eco-{}.{}", std::process::id(), extension));
+            let path = path.to_str().unwrap();
+            std::fs::write(path, text).unwrap();
+            let database = EcoDatabase::load(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            let game = play(&["e4", if eco == "B20" { "c5" } else { "e5" }]);
+            assert_eq!(database.classify(&game.start, &game.moves).unwrap().eco, eco);
+        }
+        assert!(EcoDatabase::load("no-such-eco-file.tsv").unwrap_err().starts_with("Could not read no-such-eco-file.tsv"));
+    }
+
+    #[test]
+    fn transpositions_reach_the_named_position() {
+        let database = EcoDatabase::from_tsv(TSV).unwrap();
+        let game = play(&["Nf3", "Nc6", "e4", "e5", "Bb5", "a6"]);
+        let opening = database.classify(&game.start, &game.moves).unwrap();
+        assert_eq!(opening.name, "Ruy Lopez");
+
+        // The en passant tile left by the last double push does not keep the positions apart
+        let mut gamestate = GameState::new();
+        for san in ["e4", "e5"] {
+            let mv = parse_san(&mut gamestate, san).unwrap();
+            gamestate.make_move_kind(mv);
+        }
+        assert!(gamestate.en_passant.is_some());
+        assert_eq!(database.lookup(&gamestate).unwrap().eco, "C20");
+    }
+
+    #[test]
+    fn tagging_writes_the_eco_and_opening_tags() {
+        let database = EcoDatabase::from_tsv(TSV).unwrap();
+        let mut game = play(&["e4", "e5", "Nf3", "Nc6", "Bc4", "Bc5"]);
+        assert_eq!(database.tag(&mut game).unwrap().eco, "C50");
+        let pgn = game.to_pgn("*").to_pgn();
+        assert!(pgn.contains("[ECO \"C50\"]"));
+        assert!(pgn.contains("[Opening \"Italian Game\"]"));
+
+        let mut game = play(&["d4"]);
+        game.metadata.insert("ECO".to_string(), "A40".to_string());
+        assert!(database.tag(&mut game).is_none());
+        assert_eq!(game.metadata.get("ECO").map(String::as_str), Some("A40"));
+        assert!(!game.metadata.contains_key("Opening"));
+    }
+}
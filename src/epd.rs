@@ -0,0 +1,318 @@
+use crate::game::format_move;
+use crate::gamestate::{GameState, MoveKind};
+use crate::pgn::parse_san;
+use crate::search::{Engine, SearchResult};
+use std::time::{Duration, Instant};
+
+/// A position of a test suite in Extended Position Description.
+///
+/// Properties:
+///
+/// * `gamestate`: The position, from the four FEN fields of the line.
+/// * `operations`: Every operation of the line as (opcode, operands), quotes removed from the operands.
+/// * `best_moves`: The moves of the `bm` operation, one of them has to be found.
+/// * `avoid_moves`: The moves of the `am` operation, none of them may be played.
+/// * `id`: The name of the position from the `id` operation.
+/// * `comment`: The `c0` comment, in STS suites the points per move such as "Nf3=10, e4=3".
+#[derive(Clone, Debug)]
+pub struct EpdRecord {
+    pub gamestate: GameState,
+    pub operations: Vec<(String, Vec<String>)>,
+    pub best_moves: Vec<MoveKind>,
+    pub avoid_moves: Vec<MoveKind>,
+    pub id: Option<String>,
+    pub comment: Option<String>,
+}
+
+/// How long the engine looks at each position of a suite.
+///
+/// * `DEPTH`: Search every position to the given depth.
+/// * `TIME`: Search every position for the given time.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum EpdLimit {
+    DEPTH(u32),
+    TIME(Duration)
+}
+
+/// The result of running a test suite.
+///
+/// Properties:
+///
+/// * `positions`: The number of positions searched.
+/// * `solved`: The number of positions where the engine found a `bm` move and avoided the `am` moves.
+/// * `points`: The STS points earned, from the "move=points" lists in `c0`.
+/// * `max_points`: The STS points that could have been earned.
+/// * `nodes`: The nodes searched over all positions.
+/// * `time`: The time spent searching.
+#[derive(Clone, Debug, Default)]
+pub struct SuiteSummary {
+    pub positions: usize,
+    pub solved: usize,
+    pub points: u32,
+    pub max_points: u32,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl EpdRecord {
+/// It returns the operands of the operation with the given opcode, if the line has it
+    pub fn operation(&self, opcode: &str) -> Option<&[String]> {
+        self.operations.iter().find(|(o, _)| o == opcode).map(|(_, operands)| operands.as_slice())
+    }
+
+/// It reads the STS points of the position from the `c0` comment, written as "Nf3=10, e4=3"
+///
+/// Returns:
+///
+/// The moves with their points, empty if the comment does not hold points.
+    pub fn move_points(&self) -> Vec<(MoveKind, u32)> {
+        let mut gamestate = self.gamestate;
+        let comment = match &self.comment {
+            Some(comment) => comment,
+            None => return Vec::new()
+        };
+        comment.split(',').filter_map(|entry| {
+            let (san, points) = entry.trim().split_once('=')?;
+            Some((parse_san(&mut gamestate, san)?, points.trim().parse().ok()?))
+        }).collect()
+    }
+
+/// It checks a move against the `bm` and `am` operations of the position
+///
+/// Returns:
+///
+/// True if the move is one of the best moves, or if there are no best moves and it avoids every move
+/// to avoid.
+    pub fn is_solved_by(&self, mv: MoveKind) -> bool {
+        if self.avoid_moves.contains(&mv) {
+            return false;
+        }
+        self.best_moves.is_empty() || self.best_moves.contains(&mv)
+    }
+}
+
+/// It reads one line of an EPD file: the board, player in turn, castling and en passant fields of a FEN
+/// followed by operations such as `bm Nf3 e4; id "WAC.001";`
+///
+/// Arguments:
+///
+/// * `line`: The line to read.
+///
+/// Returns:
+///
+/// The position with its operations, or a message describing what is wrong with the line.
+pub fn parse_epd_line(line: &str) -> Result<EpdRecord, String> {
+    let line = line.trim();
+    let mut fields = line.splitn(5, char::is_whitespace);
+    let fen_fields: Vec<&str> = (&mut fields).take(4).collect();
+    if fen_fields.len() < 4 {
+        return Err("An EPD line needs the four position fields of a FEN".to_string());
+    }
+    let mut gamestate = GameState::from_fen(&fen_fields.join(" "))?;
+    let operations = parse_operations(fields.next().unwrap_or(""))?;
+
+    for (opcode, operands) in &operations {
+        match (opcode.as_str(), operands.first()) {
+            ("hmvc", Some(n)) => gamestate.halfmove_clock = n.parse().map_err(|_| format!("Invalid hmvc '{}'", n))?,
+            ("fmvn", Some(n)) => gamestate.fullmove_number = n.parse().map_err(|_| format!("Invalid fmvn '{}'", n))?,
+            _ => {}
+        }
+    }
+    let mut moves = |opcode: &str| -> Result<Vec<MoveKind>, String> {
+        let operands = operations.iter().find(|(o, _)| o == opcode).map(|(_, operands)| operands.clone()).unwrap_or_default();
+        operands.iter().map(|san| parse_san(&mut gamestate, san).ok_or(format!("The {} move '{}' is not legal", opcode, san))).collect()
+    };
+    let best_moves = moves("bm")?;
+    let avoid_moves = moves("am")?;
+    let operand = |opcode: &str| operations.iter().find(|(o, _)| o == opcode).map(|(_, operands)| operands.join(" "));
+    let id = operand("id");
+    let comment = operand("c0");
+
+    Ok(EpdRecord { gamestate, operations, best_moves, avoid_moves, id, comment })
+}
+
+/// It reads every position of an EPD file, skipping empty lines and lines starting with '#'. A line
+/// that cannot be read is left out so that one broken record does not end the whole suite.
+///
+/// Returns:
+///
+/// The positions, and a message for every line that was left out.
+pub fn parse_epd(text: &str) -> (Vec<EpdRecord>, Vec<String>) {
+    let mut records: Vec<EpdRecord> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match parse_epd_line(line) {
+            Ok(record) => records.push(record),
+            Err(e) => errors.push(format!("Line {}: {}", i + 1, e))
+        }
+    }
+    (records, errors)
+}
+
+/// It splits the operations of an EPD line, each an opcode followed by operands and ended by ';'.
+/// Quoted operands may contain spaces and semicolons.
+fn parse_operations(text: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut operations: Vec<(String, Vec<String>)> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                words.push(std::mem::take(&mut word));
+                quoted = false;
+            },
+            '"' => quoted = true,
+            '\\' if quoted => word.extend(chars.next()),
+            c if quoted => word.push(c),
+            ';' | ' ' | '\t' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                if c == ';' && !words.is_empty() {
+                    let opcode = words.remove(0);
+                    operations.push((opcode, std::mem::take(&mut words)));
+                }
+            },
+            c => word.push(c)
+        }
+    }
+    if quoted {
+        return Err("A quoted operand is not closed".to_string());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    if !words.is_empty() {
+        let opcode = words.remove(0);
+        operations.push((opcode, words));
+    }
+    Ok(operations)
+}
+
+/// It searches every position of a suite and prints a line per position followed by a summary
+///
+/// Arguments:
+///
+/// * `engine`: The engine to search with, its transposition table is cleared before each position.
+/// * `records`: The positions of the suite.
+/// * `limit`: How long to search each position.
+///
+/// Returns:
+///
+/// The totals of the run.
+pub fn run_suite(engine: &mut Engine, records: &[EpdRecord], limit: EpdLimit) -> SuiteSummary {
+    let mut summary = SuiteSummary::default();
+    for (i, record) in records.iter().enumerate() {
+        engine.clear();
+        let started = Instant::now();
+        let result: SearchResult = match limit {
+            EpdLimit::DEPTH(depth) => engine.search(&record.gamestate, depth),
+            EpdLimit::TIME(time) => engine.search_timed(&record.gamestate, time, 64)
+        };
+        summary.time += started.elapsed();
+        summary.positions += 1;
+        summary.nodes += result.nodes;
+
+        let name = record.id.clone().unwrap_or(format!("#{}", i + 1));
+        let best_move = result.best_move_kind();
+        let found = match best_move {
+            Some(mv) => format_move(&record.gamestate, mv),
+            None => "none".to_string()
+        };
+        let solved = best_move.is_some_and(|mv| record.is_solved_by(mv));
+        if solved {
+            summary.solved += 1;
+        }
+        let points = record.move_points();
+        let earned = points.iter().find(|(mv, _)| Some(*mv) == best_move).map_or(0, |(_, p)| *p);
+        summary.points += earned;
+        summary.max_points += points.iter().map(|(_, p)| *p).max().unwrap_or(0);
+
+        let expected: Vec<String> = record.best_moves.iter().map(|&mv| format_move(&record.gamestate, mv)).collect();
+        let avoided: Vec<String> = record.avoid_moves.iter().map(|&mv| format_move(&record.gamestate, mv)).collect();
+        println!("{:<12} {:<4} found {:<6} bm [{}] am [{}] score {} nodes {}{}",
+            name, if solved { "OK" } else { "FAIL" }, found, expected.join(" "), avoided.join(" "), result.score, result.nodes,
+            if points.is_empty() { String::new() } else { format!(" points {}", earned) });
+    }
+
+    println!("Solved {}/{} ({:.1}%)", summary.solved, summary.positions, 100.0 * summary.solved as f64 / summary.positions.max(1) as f64);
+    if summary.max_points > 0 {
+        println!("Points {}/{} ({:.1}%)", summary.points, summary.max_points, 100.0 * summary.points as f64 / summary.max_points as f64);
+    }
+    let seconds = summary.time.as_secs_f64();
+    println!("Nodes {} in {:.2}s ({:.0} nodes/s)", summary.nodes, seconds, summary.nodes as f64 / seconds.max(0.001));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceType;
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn quoted_operands_keep_spaces_and_semicolons() {
+        let operations = parse_operations(r#"id "WAC; \"one\""; c0 "Nf3=10, e4=3"; bm Nf3 e4;"#).unwrap();
+        assert_eq!(operations, vec![
+            ("id".to_string(), vec!["WAC; \"one\"".to_string()]),
+            ("c0".to_string(), vec!["Nf3=10, e4=3".to_string()]),
+            ("bm".to_string(), vec!["Nf3".to_string(), "e4".to_string()])
+        ]);
+        assert!(parse_operations(r#"id "open"#).is_err());
+    }
+
+    #[test]
+    fn best_and_avoid_moves_id_and_comment_are_read() {
+        let record = parse_epd_line(r#"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm Nf3 e4; am f3; id "start.001"; c0 "Nf3=10, e4=8, d4=5"; hmvc 3; fmvn 12;"#).unwrap();
+        assert_eq!(record.best_moves, vec![MoveKind::BOARD(square("g1"), square("f3")), MoveKind::BOARD(square("e2"), square("e4"))]);
+        assert_eq!(record.avoid_moves, vec![MoveKind::BOARD(square("f2"), square("f3"))]);
+        assert_eq!(record.id.as_deref(), Some("start.001"));
+        assert_eq!(record.comment.as_deref(), Some("Nf3=10, e4=8, d4=5"));
+        assert_eq!(record.operation("hmvc"), Some(&["3".to_string()][..]));
+        assert_eq!((record.gamestate.halfmove_clock, record.gamestate.fullmove_number), (3, 12));
+        assert_eq!(record.move_points(), vec![
+            (MoveKind::BOARD(square("g1"), square("f3")), 10),
+            (MoveKind::BOARD(square("e2"), square("e4")), 8),
+            (MoveKind::BOARD(square("d2"), square("d4")), 5)
+        ]);
+        assert!(record.is_solved_by(MoveKind::BOARD(square("e2"), square("e4"))));
+        assert!(!record.is_solved_by(MoveKind::BOARD(square("d2"), square("d4"))));
+
+        let record = parse_epd_line("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - am f3;").unwrap();
+        assert!(record.is_solved_by(MoveKind::BOARD(square("e2"), square("e4"))));
+        assert!(!record.is_solved_by(MoveKind::BOARD(square("f2"), square("f3"))));
+    }
+
+    #[test]
+    fn bad_lines_are_reported_and_skipped() {
+        let text = "# a comment\n\
+            rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; id \"good.1\";\n\
+            \n\
+            rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e5; id \"illegal\";\n\
+            not an epd line\n\
+            rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm d4; id \"good.2\";\n";
+        let (records, errors) = parse_epd(text);
+        let ids: Vec<Option<String>> = records.into_iter().map(|record| record.id).collect();
+        assert_eq!(ids, vec![Some("good.1".to_string()), Some("good.2".to_string())]);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("Line 4: "), "{}", errors[0]);
+        assert!(errors[1].starts_with("Line 5: "), "{}", errors[1]);
+    }
+
+    #[test]
+    fn underpromotion_best_move_can_be_solved() {
+        let record = parse_epd_line(r#"8/4P3/3q1k2/8/8/8/6PP/7K w - - bm e8=N+; id "fork";"#).unwrap();
+        assert_eq!(record.best_moves, vec![MoveKind::PROMOTION(square("e7"), square("e8"), PieceType::KNIGHT)]);
+        let mut engine = Engine::new();
+        let summary = run_suite(&mut engine, &[record], EpdLimit::DEPTH(3));
+        assert_eq!((summary.positions, summary.solved), (1, 1));
+    }
+}
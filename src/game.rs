@@ -0,0 +1,571 @@
+use crate::clock::{Clock, TimeControl};
+use crate::gamestate::{GameState, MoveKind};
+use crate::pgn::{format_san, PgnGame};
+use crate::piece::{Color, PieceType};
+use crate::uci::{format_uci_move, parse_uci_move};
+use crate::variant::VariantKind;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The version of the save file format written by `Game::save`. Older versions are migrated when they
+/// are loaded.
+///
+/// * 0: no version, a serde dump of a GameState written by the old `serialize` command. It is loaded
+///   as a game starting from that position.
+/// * 1: serde dumps of the start and current GameState next to the moves.
+/// * 2: a start FEN, moves as text and metadata, see `GameRecord`.
+pub const SAVE_VERSION: u32 = 2;
+
+/// The course of a game kept next to the GameState by the client: where the game started, the moves
+/// played since and who is playing.
+///
+/// Properties:
+///
+/// * `start`: The position the game started from.
+/// * `moves`: The moves played since the start position, in order.
+/// * `white`: The name of the white player.
+/// * `black`: The name of the black player.
+/// * `metadata`: Further information about the game, such as ("Event", "Club championship").
+#[derive(Clone, Debug)]
+pub struct Game {
+    pub start: GameState,
+    pub moves: Vec<MoveKind>,
+    pub white: String,
+    pub black: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// A game as it is written to disk. Only this model is stored, never the GameState itself, so the
+/// internals of GameState can change without breaking saved games.
+///
+/// ```json
+/// {
+///   "version": 2,
+///   "variant": "standard",
+///   "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+///   "moves": ["e2e4", "e7e5", "g1f3"],
+///   "metadata": { "Black": "Black", "White": "White" }
+/// }
+/// ```
+///
+/// Properties:
+///
+/// * `version`: The version of the format, `SAVE_VERSION` when written by this program.
+/// * `variant`: The name of the rules the game is played with, such as "standard" or "atomic".
+/// * `chess960`: True if castling is played the Chess960 way, left out when false.
+/// * `fen`: The start position in FEN, with Crazyhouse pockets and Chess960 castling rights (X-FEN).
+/// * `moves`: The moves from the start position in UCI notation, such as "e2e4" or "e7e8q", and drops
+///   such as "P@e4". Castling is written as the king move of the game, "e1g1" in standard chess and the
+///   king taking its rook in Chess960.
+/// * `metadata`: Names and values such as "White", "Black", "Event" or "Date", as in PGN tags.
+/// * `clock`: The clock of the game, left out when it is played without one.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct GameRecord {
+    pub version: u32,
+    pub variant: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub chess960: bool,
+    pub fen: String,
+    pub moves: Vec<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<ClockRecord>,
+}
+
+/// A stopped clock as it is written to disk.
+///
+/// Properties:
+///
+/// * `time_control`: The time control in the notation of `TimeControl::parse`, such as "40/90+30:30+30".
+/// * `remaining_ms`: The milliseconds left for White and Black.
+/// * `moves`: The number of moves made by White and Black.
+/// * `sessions`: The session White and Black are playing in.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ClockRecord {
+    pub time_control: String,
+    pub remaining_ms: [u64; 2],
+    pub moves: [u32; 2],
+    pub sessions: [usize; 2],
+}
+
+/// Version 1 of the save file, only read to migrate it.
+#[derive(Deserialize)]
+struct SavedGameV1 {
+    white: String,
+    black: String,
+    start: GameState,
+    moves: Vec<MoveKind>,
+    clock: Option<Clock>,
+}
+
+impl Game {
+/// It creates a game without moves starting from the given position
+    pub fn new(start: GameState) -> Game {
+        Game { start, moves: Vec::new(), white: "White".to_string(), black: "Black".to_string(), metadata: BTreeMap::new() }
+    }
+
+/// It starts the game over from a new position, keeping the player names. The opening found for the
+/// old moves is forgotten.
+    pub fn restart(&mut self, start: GameState) {
+        self.start = start;
+        self.moves.clear();
+        self.metadata.remove("ECO");
+        self.metadata.remove("Opening");
+    }
+
+/// It adds a move that has been played to the game
+    pub fn record(&mut self, mv: MoveKind) {
+        self.moves.push(mv);
+    }
+
+/// It takes back the last move of the game
+///
+/// Returns:
+///
+/// The position before that move, or a message if no move has been played.
+    pub fn undo(&mut self) -> Result<GameState, String> {
+        self.moves.pop().ok_or("There is no move to take back")?;
+        self.replay()
+    }
+
+/// It plays the moves of the game from the start position, checking that every move is legal
+///
+/// Returns:
+///
+/// The position after the last move, or a message naming the first illegal move.
+    pub fn replay(&self) -> Result<GameState, String> {
+        let mut gamestate: GameState = self.start;
+        gamestate.debug_flag = false;
+        for (i, &mv) in self.moves.iter().enumerate() {
+            if !gamestate.legal_move_kinds().contains(&mv) {
+                return Err(format!("Move {} ({:?}) is not legal", i + 1, mv));
+            }
+            gamestate.make_move_kind(mv);
+        }
+        Ok(gamestate)
+    }
+
+/// It converts the game to PGN with the moves in Standard Algebraic Notation. The seven standard tags
+/// come first, followed by the metadata, the variant and the start position when they are not the
+/// standard ones.
+///
+/// Arguments:
+///
+/// * `result`: The result of the game, "1-0", "0-1", "1/2-1/2" or "*".
+    pub fn to_pgn(&self, result: &str) -> PgnGame {
+        let mut gamestate: GameState = self.start;
+        gamestate.debug_flag = false;
+        let mut moves: Vec<String> = Vec::new();
+        for &mv in &self.moves {
+            moves.push(format_san(&mut gamestate, mv));
+            gamestate.make_move_kind(mv);
+        }
+
+        let mut tags: Vec<(String, String)> = Vec::new();
+        for (name, default) in [("Event", "?"), ("Site", "?"), ("Date", "????.??.??"), ("Round", "?")] {
+            tags.push((name.to_string(), self.metadata.get(name).map_or(default, |value| value.as_str()).to_string()));
+        }
+        tags.push(("White".to_string(), self.white.clone()));
+        tags.push(("Black".to_string(), self.black.clone()));
+        tags.push(("Result".to_string(), result.to_string()));
+        for (name, value) in &self.metadata {
+            if !["Event", "Site", "Date", "Round", "White", "Black", "Result", "Variant", "SetUp", "FEN"].contains(&name.as_str()) {
+                tags.push((name.clone(), value.clone()));
+            }
+        }
+        let variant = self.start.variant.rules().name();
+        if variant != "standard" || self.start.chess960 {
+            let name = if self.start.chess960 { "Chess960".to_string() } else { variant[..1].to_uppercase() + &variant[1..] };
+            tags.push(("Variant".to_string(), name));
+        }
+        let fen = self.start.to_fen();
+        if fen != self.start.variant.start_position().to_fen() || self.start.chess960 {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), fen));
+        }
+        PgnGame { tags, moves, result: result.to_string(), annotations: Vec::new() }
+    }
+
+/// It converts the game to the model written to disk
+///
+/// Arguments:
+///
+/// * `clock`: The clock of the game, stored stopped with the time used so far charged.
+///
+/// Returns:
+///
+/// The record of the game, or a message naming the first illegal move.
+    pub fn to_record(&self, clock: &Option<Clock>) -> Result<GameRecord, String> {
+        let mut gamestate: GameState = self.start;
+        gamestate.debug_flag = false;
+        let mut moves: Vec<String> = Vec::new();
+        for (i, &mv) in self.moves.iter().enumerate() {
+            if !gamestate.legal_move_kinds().contains(&mv) {
+                return Err(format!("Move {} ({:?}) is not legal", i + 1, mv));
+            }
+            moves.push(format_move(&gamestate, mv));
+            gamestate.make_move_kind(mv);
+        }
+
+        let mut metadata = self.metadata.clone();
+        metadata.insert("White".to_string(), self.white.clone());
+        metadata.insert("Black".to_string(), self.black.clone());
+        let clock = clock.clone().map(|mut clock| {
+            clock.stop();
+            ClockRecord {
+                time_control: clock.time_control.format(),
+                remaining_ms: clock.remaining.map(|time| time.as_millis() as u64),
+                moves: clock.moves,
+                sessions: clock.sessions,
+            }
+        });
+        Ok(GameRecord {
+            version: SAVE_VERSION,
+            variant: self.start.variant.rules().name().to_string(),
+            chess960: self.start.chess960,
+            fen: self.start.to_fen(),
+            moves,
+            metadata,
+            clock,
+        })
+    }
+
+/// It rebuilds a game from the model written to disk, replaying its moves to make sure it is
+/// consistent
+///
+/// Arguments:
+///
+/// * `record`: The record of the game, of the current version.
+///
+/// Returns:
+///
+/// The game, the current position and the stopped clock, or a message describing what is wrong.
+    pub fn from_record(record: &GameRecord) -> Result<(Game, GameState, Option<Clock>), String> {
+        if record.version != SAVE_VERSION {
+            return Err(format!("Unsupported save version {}, expected {}", record.version, SAVE_VERSION));
+        }
+        let variant = VariantKind::from_name(&record.variant).ok_or(format!("Unknown variant '{}'", record.variant))?;
+        let mut start = GameState::from_fen_variant(&record.fen, variant)?;
+        start.chess960 |= record.chess960;
+
+        let mut gamestate: GameState = start;
+        let mut moves: Vec<MoveKind> = Vec::new();
+        for (i, text) in record.moves.iter().enumerate() {
+            let mv = parse_move(&gamestate, text).ok_or(format!("Move {} ('{}') can not be read", i + 1, text))?;
+            if !gamestate.legal_move_kinds().contains(&mv) {
+                return Err(format!("Move {} ('{}') is not legal", i + 1, text));
+            }
+            gamestate.make_move_kind(mv);
+            moves.push(mv);
+        }
+
+        let mut metadata = record.metadata.clone();
+        let white = metadata.remove("White").unwrap_or("White".to_string());
+        let black = metadata.remove("Black").unwrap_or("Black".to_string());
+        let clock = match &record.clock {
+            Some(saved) => {
+                let mut clock = Clock::new(TimeControl::parse(&saved.time_control)?);
+                clock.remaining = saved.remaining_ms.map(Duration::from_millis);
+                clock.moves = saved.moves;
+                clock.sessions = saved.sessions;
+                for color in [Color::WHITE, Color::BLACK] {
+                    if clock.remaining(color).is_zero() {
+                        clock.flagged = color;
+                    }
+                }
+                Some(clock)
+            },
+            None => None
+        };
+        Ok((Game { start, moves, white, black, metadata }, gamestate, clock))
+    }
+
+/// It writes the game as JSON in the current version of the format
+///
+/// Arguments:
+///
+/// * `clock`: The clock of the game, stored stopped with the time used so far charged.
+    pub fn to_json(&self, clock: &Option<Clock>) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.to_record(clock)?).map_err(|e| format!("Could not write the game: {}", e))
+    }
+
+/// It reads a game from JSON, migrating files written by older versions
+///
+/// Arguments:
+///
+/// * `text`: The JSON document.
+///
+/// Returns:
+///
+/// The game, the current position and the stopped clock, or a message describing what is wrong.
+    pub fn from_json(text: &str) -> Result<(Game, GameState, Option<Clock>), String> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(|e| format!("Not a saved game: {}", e))?;
+        Game::from_record(&migrate(value)?)
+    }
+
+/// It writes the game to a file as JSON
+///
+/// Arguments:
+///
+/// * `path`: The file to write.
+/// * `clock`: The clock of the game, saved stopped with the time used so far charged.
+///
+/// Returns:
+///
+/// An error message if the file could not be written.
+    pub fn save(&self, path: &str, clock: &Option<Clock>) -> Result<(), String> {
+        let json = self.to_json(clock)?;
+        std::fs::write(path, json).map_err(|e| format!("Could not write '{}': {}", path, e))
+    }
+
+/// It reads a game written by `save`, of this or an older version
+///
+/// Arguments:
+///
+/// * `path`: The file to read.
+///
+/// Returns:
+///
+/// The game, the current position and the stopped clock, or a message describing why the file could
+/// not be loaded.
+    pub fn load(path: &str) -> Result<(Game, GameState, Option<Clock>), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read '{}': {}", path, e))?;
+        Game::from_json(&text)
+    }
+}
+
+/// It brings a saved game of any known version to the current version
+///
+/// Arguments:
+///
+/// * `value`: The parsed JSON document.
+///
+/// Returns:
+///
+/// The record in the current version, or a message if the version is unknown or the document is
+/// broken.
+pub fn migrate(value: serde_json::Value) -> Result<GameRecord, String> {
+    let version = match value.get("version") {
+        Some(version) => version.as_u64().ok_or("The save file version is not a number")?,
+        // The old `serialize` command dumped the GameState alone, without a version
+        None if value.get("board").is_some() => 0,
+        None => return Err("The save file has no version".to_string())
+    };
+    match version {
+        0 => {
+            let crazyhouse = legacy_crazyhouse(&value);
+            let mut start: GameState = serde_json::from_value(value).map_err(|e| format!("Not a saved game or position: {}", e))?;
+            if crazyhouse {
+                start.variant = VariantKind::CRAZYHOUSE;
+            }
+            check_position(&start)?;
+            start.debug_flag = false;
+            start.fullmove_number = start.fullmove_number.max(1);
+            Game::new(start).to_record(&None)
+        },
+        1 => {
+            let crazyhouse = value.get("start").is_some_and(legacy_crazyhouse);
+            let mut saved: SavedGameV1 = serde_json::from_value(value).map_err(|e| format!("Not a version 1 save file: {}", e))?;
+            if crazyhouse {
+                saved.start.variant = VariantKind::CRAZYHOUSE;
+            }
+            check_position(&saved.start)?;
+            if saved.clock.as_ref().is_some_and(|clock| clock.time_control.sessions.is_empty()) {
+                return Err("The clock has no time control".to_string());
+            }
+            let game = Game { start: saved.start, moves: saved.moves, white: saved.white, black: saved.black, metadata: BTreeMap::new() };
+            game.to_record(&saved.clock)
+        },
+        2 => serde_json::from_value(value).map_err(|e| format!("Not a version 2 save file: {}", e)),
+        _ => Err(format!("Unsupported save version {}, this program reads up to version {}", version, SAVE_VERSION))
+    }
+}
+
+/// Before Crazyhouse became a variant, GameState dumps marked a Crazyhouse game with a flag of its own
+fn legacy_crazyhouse(dump: &serde_json::Value) -> bool {
+    dump.get("crazyhouse").and_then(serde_json::Value::as_bool) == Some(true)
+}
+
+/// It writes a move of the position as text, in UCI notation for moves on the board and as `P@e4`
+/// for drops
+pub fn format_move(gamestate: &GameState, mv: MoveKind) -> String {
+    let letter = |piecetype: PieceType| match piecetype {
+        PieceType::KNIGHT => 'N',
+        PieceType::BISHOP => 'B',
+        PieceType::ROOK(_) => 'R',
+        PieceType::QUEEN => 'Q',
+        _ => 'P'
+    };
+    let square = |coord: usize| GameState::coordinate_translator_usize(coord).to_lowercase();
+    match mv {
+        MoveKind::BOARD(pc, tc) => format_uci_move(gamestate, pc, tc),
+        MoveKind::PROMOTION(pc, tc, piecetype) => format!("{}{}{}", square(pc), square(tc), letter(piecetype).to_ascii_lowercase()),
+        MoveKind::DROP(piecetype, tc) => format!("{}@{}", letter(piecetype), square(tc))
+    }
+}
+
+/// It reads a move written by `format_move`. Castling may also be written as the king taking its rook.
+pub fn parse_move(gamestate: &GameState, text: &str) -> Option<MoveKind> {
+    if text.contains('@') {
+        let (piecetype, tc) = GameState::parse_drop(text)?;
+        return Some(MoveKind::DROP(piecetype, tc));
+    }
+    match parse_uci_move(text)? {
+        MoveKind::BOARD(pc, tc) => Some(MoveKind::BOARD(pc, gamestate.castling_input(pc, tc))),
+        mv => Some(mv)
+    }
+}
+
+/// It checks the parts of a position read from a file that could make the program index outside the
+/// board
+fn check_position(gamestate: &GameState) -> Result<(), String> {
+    if gamestate.wkc != gamestate.find_white_king() || gamestate.bkc != gamestate.find_black_king() {
+        return Err("The king coordinates do not match the board".to_string());
+    }
+    if gamestate.en_passant.is_some_and(|coord| coord > 63) {
+        return Err("The en passant tile is outside the board".to_string());
+    }
+    if gamestate.current_player == Color::UNCOLORED {
+        return Err("Nobody is in turn".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(fen: &str, moves: &[&str]) -> (Game, GameState) {
+        let start = GameState::from_fen(fen).unwrap();
+        let mut game = Game::new(start);
+        let mut gamestate = start;
+        for text in moves {
+            let mv = parse_move(&gamestate, text).unwrap();
+            assert!(gamestate.legal_move_kinds().contains(&mv), "{} is not legal", text);
+            gamestate.make_move_kind(mv);
+            game.record(mv);
+        }
+        (game, gamestate)
+    }
+
+    #[test]
+    fn saved_games_load_with_the_same_position_and_moves() {
+        let games = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", vec!["e2e4", "c7c5", "g1f3", "d7d6"]),
+            ("4k3/1P6/8/8/8/8/8/R3K2R w KQ - 0 1", vec!["e1g1", "e8d8", "b7b8n"]),
+            ("r3k3/8/8/8/8/8/8/4K2R[Nn] w - - 0 1", vec!["N@f3", "n@d4", "f3d4"]),
+        ];
+        let path = std::env::temp_dir().join(format!("chess-save-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        for (fen, moves) in games {
+            let (mut game, gamestate) = play(fen, &moves);
+            game.white = "Ann".to_string();
+            game.metadata.insert("Event".to_string(), "Club championship".to_string());
+            game.save(path, &None).unwrap();
+
+            let (loaded, position, clock) = Game::load(path).unwrap();
+            assert_eq!(loaded.start.to_fen(), game.start.to_fen());
+            assert_eq!(loaded.moves, game.moves);
+            assert_eq!(position.to_fen(), gamestate.to_fen());
+            assert_eq!(loaded.white, "Ann");
+            assert_eq!(loaded.metadata.get("Event").map(String::as_str), Some("Club championship"));
+            assert!(clock.is_none());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn chess960_games_and_clocks_survive_a_round_trip() {
+        let start = GameState::new_chess960(0);
+        let mut game = Game::new(start);
+        let mut gamestate = start;
+        let mv = parse_move(&gamestate, "e2e4").unwrap();
+        gamestate.make_move_kind(mv);
+        game.record(mv);
+        let mut clock = Clock::new(TimeControl::parse("40/90+30").unwrap());
+        clock.remaining = [Duration::from_millis(5_399_000), Duration::from_millis(5_400_000)];
+        clock.moves = [1, 0];
+        let remaining = clock.remaining;
+        let time_control = clock.time_control.clone();
+        let clock = Some(clock);
+
+        let record = game.to_record(&clock).unwrap();
+        assert!(record.chess960);
+        let (loaded, position, loaded_clock) = Game::from_json(&game.to_json(&clock).unwrap()).unwrap();
+        assert!(loaded.start.chess960);
+        assert_eq!(loaded.start.to_fen(), start.to_fen());
+        assert_eq!(loaded.moves, game.moves);
+        assert_eq!(position.to_fen(), gamestate.to_fen());
+        let loaded_clock = loaded_clock.unwrap();
+        assert_eq!(loaded_clock.remaining, remaining);
+        assert_eq!(loaded_clock.moves, [1, 0]);
+        assert_eq!(loaded_clock.time_control, time_control);
+    }
+
+    #[test]
+    fn version_1_files_are_migrated_to_version_2() {
+        let (game, gamestate) = play("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &["e2e4", "e7e5", "g1f3"]);
+        let v1 = serde_json::json!({
+            "version": 1,
+            "white": "Ann",
+            "black": "Bob",
+            "start": game.start,
+            "current": gamestate,
+            "moves": game.moves,
+            "clock": null
+        });
+
+        let record = migrate(v1.clone()).unwrap();
+        assert_eq!(record.version, SAVE_VERSION);
+        assert_eq!(record.variant, "standard");
+        assert_eq!(record.fen, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(record.moves, vec!["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(record.metadata.get("White").map(String::as_str), Some("Ann"));
+        assert_eq!(record.metadata.get("Black").map(String::as_str), Some("Bob"));
+
+        let (loaded, position, _) = Game::from_json(&v1.to_string()).unwrap();
+        assert_eq!(loaded.moves, game.moves);
+        assert_eq!(position.to_fen(), gamestate.to_fen());
+    }
+
+    #[test]
+    fn serialize_dumps_without_a_version_are_loaded_as_positions() {
+        let (_, gamestate) = play("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &["e2e4", "e7e5"]);
+        let mut dump = serde_json::to_value(gamestate).unwrap();
+        // The GameState had only these fields when the old `serialize` command was written
+        for field in ["en_passant", "chess960", "halfmove_clock", "fullmove_number", "crazyhouse", "pockets", "promoted", "variant", "checks_given"] {
+            dump.as_object_mut().unwrap().remove(field);
+        }
+
+        let record = migrate(dump.clone()).unwrap();
+        assert_eq!(record.version, SAVE_VERSION);
+        assert!(record.moves.is_empty());
+        assert_eq!(record.fen, "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 1");
+        let (_, position, _) = Game::from_json(&dump.to_string()).unwrap();
+        assert_eq!(position.board.map(|tile| tile.map(|piece| (piece.piecetype, piece.color))), gamestate.board.map(|tile| tile.map(|piece| (piece.piecetype, piece.color))));
+
+        assert!(migrate(serde_json::json!({ "moves": [] })).is_err());
+        assert!(migrate(serde_json::json!({ "version": 3 })).is_err());
+    }
+
+    #[test]
+    fn old_dumps_with_the_crazyhouse_flag_load_as_crazyhouse() {
+        let (game, gamestate) = play("r3k3/8/8/8/8/8/8/4K2R[Nn] w - - 0 1", &["N@f3", "n@d4"]);
+        let mut dump = serde_json::to_value(gamestate).unwrap();
+        dump["variant"] = serde_json::json!("STANDARD");
+        dump["crazyhouse"] = serde_json::json!(true);
+        let (_, position, _) = Game::from_json(&dump.to_string()).unwrap();
+        assert_eq!(position.variant, VariantKind::CRAZYHOUSE);
+        assert_eq!(position.to_fen(), gamestate.to_fen());
+
+        let mut start = serde_json::to_value(game.start).unwrap();
+        start["variant"] = serde_json::json!("STANDARD");
+        start["crazyhouse"] = serde_json::json!(true);
+        let v1 = serde_json::json!({ "version": 1, "white": "Ann", "black": "Bob", "start": start, "current": dump, "moves": game.moves, "clock": null });
+        let (loaded, position, _) = Game::from_json(&v1.to_string()).unwrap();
+        assert_eq!(loaded.start.variant, VariantKind::CRAZYHOUSE);
+        assert_eq!(position.to_fen(), gamestate.to_fen());
+    }
+}
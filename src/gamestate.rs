@@ -1,4 +1,5 @@
 use crate::piece::{ChessPiece, Color, PieceType};
+use crate::variant::{Outcome, VariantKind};
 use serde::{Serialize, Deserialize};
 
 use serde_big_array::{BigArray};
@@ -23,6 +24,68 @@ pub struct GameState {
     pub bkc: usize,
     pub old_wkc: usize,
     pub old_bkc: usize,
+    pub en_passant: Option<usize>,
+    #[serde(default)]
+    pub chess960: bool,
+    #[serde(default)]
+    pub halfmove_clock: u32,
+    #[serde(default)]
+    pub fullmove_number: u32,
+    #[serde(default)]
+    pub pockets: [[u8; 5]; 2],
+    #[serde(default)]
+    pub promoted: u64,
+    #[serde(default)]
+    pub variant: VariantKind,
+    #[serde(default)]
+    pub checks_given: [u8; 2],
+}
+
+/// The pieces that can be held in a Crazyhouse pocket, in the order of `GameState::pockets`
+pub const POCKET_PIECES: [PieceType; 5] = [PieceType::PAWN(true), PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK(true), PieceType::QUEEN];
+
+/// The steps of a rook along files and rows, as (file, row) differences
+pub const ROOK_DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+/// The steps of a bishop along diagonals, as (file, row) differences
+pub const BISHOP_DIRECTIONS: [(isize, isize); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+/// The jumps of a knight, as (file, row) differences
+pub const KNIGHT_JUMPS: [(isize, isize); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+
+/// The pieces a pawn may become besides a queen, in the order of their Polyglot codes
+pub const UNDERPROMOTION_PIECES: [PieceType; 3] = [PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK(true)];
+
+/// A move of the current player, either a piece moving on the board or, in Crazyhouse, a piece dropped
+/// from the pocket onto an empty tile. A pawn moving to the last row with `BOARD` becomes a queen,
+/// `PROMOTION` names the knight, bishop or rook it becomes instead.
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum MoveKind {
+    BOARD(usize, usize),
+    DROP(PieceType, usize),
+    PROMOTION(usize, usize, PieceType),
+}
+
+impl MoveKind {
+/// It returns the move of a pawn from `pc` to the last row at `tc` becoming the given piece, a plain
+/// `BOARD` move for a queen so that every move has a single form
+    pub fn promotion(pc: usize, tc: usize, piecetype: PieceType) -> MoveKind {
+        match piecetype {
+            PieceType::QUEEN => MoveKind::BOARD(pc, tc),
+            piecetype => MoveKind::PROMOTION(pc, tc, piecetype)
+        }
+    }
+
+/// It reads the letter of a promotion piece in either case, as written in SAN (`N`) or UCI (`n`)
+    pub fn promotion_piece(letter: char) -> Option<PieceType> {
+        match letter.to_ascii_lowercase() {
+            'q' => Some(PieceType::QUEEN),
+            'r' => Some(PieceType::ROOK(true)),
+            'b' => Some(PieceType::BISHOP),
+            'n' => Some(PieceType::KNIGHT),
+            _ => None
+        }
+    }
 }
 
 impl GameState {
@@ -55,11 +118,19 @@ impl GameState {
             wkc: 4,
             bkc: 60,
             old_wkc: 4,
-            old_bkc: 60
+            old_bkc: 60,
+            en_passant: None,
+            chess960: false,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            pockets: [[0; 5]; 2],
+            promoted: 0,
+            variant: VariantKind::STANDARD,
+            checks_given: [0; 2]
         }
     }
 
-pub fn new_custom(&mut self, arg: &str) {
+pub fn new_custom(&mut self, arg: &str) -> Result<(), String> {
     let chars = arg.chars();
     let mut board: [Option<ChessPiece>; 64] = [None; 64];
     let mut i = 0;
@@ -109,6 +180,12 @@ pub fn new_custom(&mut self, arg: &str) {
         }
         i += 1;
     }
+    for (color, letter) in [(WHITE, 'k'), (BLACK, 'K')] {
+        let kings = arg.chars().take(64).filter(|&c| c == letter).count();
+        if kings != 1 {
+            return Err(format!("{:?} needs exactly one king, found {}", color, kings));
+        }
+    }
     self.board = board;
     self.prev_board = board;
     self.current_player = current_player;
@@ -120,15 +197,334 @@ pub fn new_custom(&mut self, arg: &str) {
     self.bkc = bkc;
     self.old_wkc = wkc;
     self.old_bkc = bkc;
+    self.en_passant = None;
+    self.chess960 = false;
+    self.halfmove_clock = 0;
+    self.fullmove_number = 1;
+    self.pockets = [[0; 5]; 2];
+    self.promoted = 0;
+    self.variant = VariantKind::STANDARD;
+    self.checks_given = [0; 2];
+    Ok(())
 }
 
-/// `serialize_me` takes a mutable reference to `self` and returns nothing
-    pub fn serialize_me(&mut self) ->  String {
-        let serialized: String = serde_json::to_string(&self).unwrap();
-        println!("Serialized: '{:?}'", serialized);
-        serialized
+/// It creates the Chess960 (Fischer Random) start position with the given number, using the standard
+/// numbering where 518 is the normal start position
+///
+/// Arguments:
+///
+/// * `index`: The number of the start position, 0 to 959.
+///
+/// Returns:
+///
+/// A GameState with the pieces placed, White to move and castling played the Chess960 way.
+    pub fn new_chess960(index: usize) -> GameState {
+        let mut back_rank: [Option<PieceType>; 8] = [None; 8];
+        let mut n = index % 960;
+        back_rank[(n % 4) * 2 + 1] = Some(PieceType::BISHOP);
+        n /= 4;
+        back_rank[(n % 4) * 2] = Some(PieceType::BISHOP);
+        n /= 4;
+        let queen = n % 6;
+        n /= 6;
+        let knights: [(usize, usize); 10] = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+        let free = |back_rank: &[Option<PieceType>; 8]| -> Vec<usize> { (0..8).filter(|&f| back_rank[f].is_none()).collect() };
+        back_rank[free(&back_rank)[queen]] = Some(PieceType::QUEEN);
+        let empty = free(&back_rank);
+        back_rank[empty[knights[n].0]] = Some(PieceType::KNIGHT);
+        back_rank[empty[knights[n].1]] = Some(PieceType::KNIGHT);
+        let empty = free(&back_rank);
+        back_rank[empty[0]] = Some(PieceType::ROOK(false));
+        back_rank[empty[1]] = Some(PieceType::KING(false));
+        back_rank[empty[2]] = Some(PieceType::ROOK(false));
+
+        let mut gamestate = GameState::new();
+        for (file, piecetype) in back_rank.iter().enumerate() {
+            let piecetype = piecetype.unwrap_or(PieceType::NONE);
+            gamestate.board[file] = Some(ChessPiece::new(piecetype, WHITE));
+            gamestate.board[56 + file] = Some(ChessPiece::new(piecetype, BLACK));
+        }
+        gamestate.prev_board = gamestate.board;
+        gamestate.wkc = empty[1];
+        gamestate.bkc = 56 + empty[1];
+        gamestate.old_wkc = gamestate.wkc;
+        gamestate.old_bkc = gamestate.bkc;
+        gamestate.chess960 = true;
+        gamestate
+    }
+
+/// It reads a position written in Forsyth-Edwards Notation. The castling field may be written as
+/// `KQkq`, with the files of the rooks (Shredder-FEN, `HAha`) or mixed (X-FEN). Castling with a king
+/// that is not on the e-file or a rook that is not in the corner makes the game a Chess960 game.
+/// Crazyhouse pockets (`[QNp]` after the board, or a ninth row) make the game a Crazyhouse game.
+///
+/// Arguments:
+///
+/// * `fen`: The position, the move counters at the end may be left out.
+///
+/// Returns:
+///
+/// The position, or a message describing what is wrong with the FEN.
+    pub fn from_fen(fen: &str) -> Result<GameState, String> {
+        GameState::from_fen_variant(fen, VariantKind::STANDARD)
+    }
+
+/// It reads a position written in Forsyth-Edwards Notation for a game of the given variant, which
+/// decides how many kings each player needs and when a king is checked
+///
+/// Arguments:
+///
+/// * `fen`: The position, the move counters at the end may be left out.
+/// * `variant`: The rules of the game.
+///
+/// Returns:
+///
+/// The position with the variant stored in it, or a message describing what is wrong with the FEN.
+    pub fn from_fen_variant(fen: &str, variant: VariantKind) -> Result<GameState, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(format!("A FEN needs at least 4 fields, found {}", fields.len()));
+        }
+
+        let mut board: [Option<ChessPiece>; 64] = [None; 64];
+        let mut promoted: u64 = 0;
+        // Crazyhouse pockets follow the board either in brackets or as a ninth row
+        let (placement, pocket) = match fields[0].split_once('[') {
+            Some((placement, pocket)) => (placement, Some(pocket.strip_suffix(']').ok_or("The pocket is missing its closing ']'")?)),
+            None => match fields[0].splitn(9, '/').nth(8) {
+                Some(pocket) => (&fields[0][..fields[0].len() - pocket.len() - 1], Some(pocket)),
+                None => (fields[0], None)
+            }
+        };
+        let rows: Vec<&str> = placement.split('/').collect();
+        if rows.len() != 8 {
+            return Err(format!("The board needs 8 rows, found {}", rows.len()));
+        }
+        for (i, row) in rows.iter().enumerate() {
+            let y = 7 - i;
+            let mut x = 0;
+            for c in row.chars() {
+                if c == '~' && x > 0 && board[y * 8 + x - 1].is_some() {
+                    promoted |= 1 << (y * 8 + x - 1);
+                    continue;
+                }
+                if let Some(empty) = c.to_digit(10) {
+                    x += empty as usize;
+                    continue;
+                }
+                if x > 7 {
+                    return Err(format!("Row {} is too long", y + 1));
+                }
+                let color = if c.is_ascii_uppercase() { WHITE } else { BLACK };
+                // Pawns behind their usual start row, as in Horde, may still move two tiles
+                let unmoved = if color == WHITE { y <= 1 } else { y >= 6 };
+                let piecetype = match c.to_ascii_lowercase() {
+                    'p' => PieceType::PAWN(!unmoved),
+                    'n' => PieceType::KNIGHT,
+                    'b' => PieceType::BISHOP,
+                    'r' => PieceType::ROOK(true),
+                    'q' => PieceType::QUEEN,
+                    'k' => PieceType::KING(true),
+                    _ => return Err(format!("Invalid piece '{}'", c))
+                };
+                let first_row = if color == WHITE { 0 } else { 7 };
+                if matches!(piecetype, PieceType::PAWN(_)) && (y == 7 - first_row || (y == first_row && !variant.rules().allows_first_row_pawns())) {
+                    return Err("Pawns can not stand on the first or last row".to_string());
+                }
+                board[y * 8 + x] = Some(ChessPiece::new(piecetype, color));
+                x += 1;
+            }
+            if x != 8 {
+                return Err(format!("Row {} does not have 8 tiles", y + 1));
+            }
+        }
+
+        let mut gamestate = GameState::new();
+        gamestate.variant = variant;
+        gamestate.board = board;
+        gamestate.prev_board = board;
+        gamestate.promoted = promoted;
+        if let Some(pocket) = pocket {
+            // Only Crazyhouse has pockets, a standard FEN with pockets is a Crazyhouse position
+            if variant == VariantKind::STANDARD {
+                gamestate.variant = VariantKind::CRAZYHOUSE;
+            } else if !variant.rules().has_pockets() {
+                return Err(format!("The {} rules have no pockets", variant.rules().name()));
+            }
+            for c in pocket.chars() {
+                let color = if c.is_ascii_uppercase() { WHITE } else { BLACK };
+                let index = "pnbrq".find(c.to_ascii_lowercase()).ok_or(format!("Invalid pocket piece '{}'", c))?;
+                gamestate.pockets[GameState::pocket_owner(color)][index] += 1;
+            }
+        }
+        gamestate.wkc = gamestate.find_white_king();
+        gamestate.bkc = gamestate.find_black_king();
+        gamestate.old_wkc = gamestate.wkc;
+        gamestate.old_bkc = gamestate.bkc;
+        let king_count = |color: Color| board.iter().flatten().filter(|p| p.color == color && matches!(p.piecetype, PieceType::KING(_))).count();
+        for color in [WHITE, BLACK] {
+            if !variant.rules().allows_king_count(color, king_count(color)) {
+                return Err(format!("{:?} can not have {} kings in {}", color, king_count(color), variant.rules().name()));
+            }
+        }
+
+        gamestate.current_player = match fields[1] {
+            "w" => WHITE,
+            "b" => BLACK,
+            other => return Err(format!("Invalid player to move '{}'", other))
+        };
+
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                let color = if c.is_ascii_uppercase() { WHITE } else { BLACK };
+                let row = if color == WHITE { 0 } else { 7 };
+                let king = if color == WHITE { gamestate.wkc } else { gamestate.bkc };
+                if GameState::coord_y(king) != row {
+                    return Err(format!("Castling '{}' needs the king on its first row", c));
+                }
+                let is_rook = |coord: usize| matches!(board[coord], Some(p) if p.color == color && matches!(p.piecetype, PieceType::ROOK(_)));
+                let rook = match c.to_ascii_lowercase() {
+                    'k' => (GameState::coord_x(king) + 1..8).rev().map(|x| row * 8 + x).find(|&coord| is_rook(coord)),
+                    'q' => (0..GameState::coord_x(king)).map(|x| row * 8 + x).find(|&coord| is_rook(coord)),
+                    f @ 'a'..='h' => Some(row * 8 + (f as usize - 'a' as usize)).filter(|&coord| is_rook(coord)),
+                    _ => return Err(format!("Invalid castling right '{}'", c))
+                };
+                let rook = rook.ok_or(format!("There is no rook for castling right '{}'", c))?;
+                gamestate.board[king] = Some(ChessPiece::new(PieceType::KING(false), color));
+                gamestate.board[rook] = Some(ChessPiece::new(PieceType::ROOK(false), color));
+                if GameState::coord_x(king) != 4 || (GameState::coord_x(rook) != 0 && GameState::coord_x(rook) != 7) {
+                    gamestate.chess960 = true;
+                }
+            }
+            gamestate.prev_board = gamestate.board;
+        }
+
+        gamestate.en_passant = match fields[3] {
+            "-" => None,
+            square => {
+                let coord = GameState::parse_square(square).ok_or(format!("Invalid en passant square '{}'", square))?;
+                let row = if gamestate.current_player == WHITE { 5 } else { 2 };
+                if GameState::coord_y(coord) != row {
+                    return Err(format!("Invalid en passant square '{}'", square));
+                }
+                // The pawn that just moved two tiles stands in front of the square, which it passed over
+                let (pawn, origin) = if gamestate.current_player == WHITE { (coord - 8, coord + 8) } else { (coord + 8, coord - 8) };
+                let moved_pawn = matches!(gamestate.board[pawn], Some(p) if p.color == gamestate.current_player.opposite() && matches!(p.piecetype, PieceType::PAWN(_)));
+                if !moved_pawn || gamestate.board[coord].is_some() || gamestate.board[origin].is_some() {
+                    return Err(format!("No pawn can have just passed over the en passant square '{}'", square));
+                }
+                Some(coord)
+            }
+        };
+        gamestate.halfmove_clock = match fields.get(4) {
+            Some(n) => n.parse().map_err(|_| format!("Invalid halfmove clock '{}'", n))?,
+            None => 0
+        };
+        gamestate.fullmove_number = match fields.get(5) {
+            Some(n) => n.parse().map_err(|_| format!("Invalid move number '{}'", n))?,
+            None => 1
+        };
+
+        if gamestate.is_checked(gamestate.current_player.opposite()) {
+            return Err("The player who is not to move is in check".to_string());
+        }
+        if gamestate.is_checked(gamestate.current_player) {
+            gamestate.checked_flag = true;
+            gamestate.checked_player = gamestate.current_player;
+        }
+        Ok(gamestate)
+    }
+
+/// It writes the position in Forsyth-Edwards Notation. Castling rights are written as `KQkq` when the
+/// rook is the outermost one on its side, and with the file of the rook otherwise (X-FEN). Crazyhouse
+/// pockets are written in brackets after the board, with promoted pieces marked by a `~`.
+///
+/// Returns:
+///
+/// The FEN of the position.
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for y in (0..8).rev() {
+            let mut empty = 0;
+            for x in 0..8 {
+                match self.board[y * 8 + x] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let letter = match piece.piecetype {
+                            PieceType::PAWN(_) => 'p',
+                            PieceType::KNIGHT => 'n',
+                            PieceType::BISHOP => 'b',
+                            PieceType::ROOK(_) => 'r',
+                            PieceType::QUEEN => 'q',
+                            PieceType::KING(_) => 'k',
+                            PieceType::NONE => '?'
+                        };
+                        fen.push(if piece.color == WHITE { letter.to_ascii_uppercase() } else { letter });
+                        if self.promoted & (1 << (y * 8 + x)) != 0 {
+                            fen.push('~');
+                        }
+                    },
+                    None => empty += 1
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y > 0 {
+                fen.push('/');
+            }
+        }
+        if self.variant.rules().has_pockets() {
+            fen.push('[');
+            for (color, letters) in [(WHITE, "PNBRQ"), (BLACK, "pnbrq")] {
+                for (count, letter) in self.pockets[GameState::pocket_owner(color)].iter().zip(letters.chars()) {
+                    fen.extend(std::iter::repeat_n(letter, *count as usize));
+                }
+            }
+            fen.push(']');
+        }
+
+        fen.push_str(if self.current_player == BLACK { " b " } else { " w " });
+        let mut castling = String::new();
+        for (color, king) in [(WHITE, self.wkc), (BLACK, self.bkc)] {
+            for short in [true, false] {
+                if let Some(rook) = self.castling_rook_on_side(king, short) {
+                    let is_rook = |x: usize| matches!(self.board[GameState::coord_y(rook) * 8 + x], Some(p) if p.color == color && matches!(p.piecetype, PieceType::ROOK(_)));
+                    let outer_rooks = if short { (GameState::coord_x(rook) + 1..8).any(is_rook) } else { (0..GameState::coord_x(rook)).any(is_rook) };
+                    let letter = if !outer_rooks {
+                        if short { 'k' } else { 'q' }
+                    } else {
+                        (b'a' + GameState::coord_x(rook) as u8) as char
+                    };
+                    castling.push(if color == WHITE { letter.to_ascii_uppercase() } else { letter });
+                }
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+        match self.en_passant {
+            Some(coord) => fen.push_str(&format!(" {} ", GameState::coordinate_translator_usize(coord).to_lowercase())),
+            None => fen.push_str(" - ")
+        }
+        fen.push_str(&format!("{} {}", self.halfmove_clock, self.fullmove_number));
+        fen
     }
 
+/// It reads a square such as `e4`, in either case
+    pub fn parse_square(square: &str) -> Option<usize> {
+        let chars: Vec<char> = square.chars().collect();
+        if chars.len() != 2 {
+            return None;
+        }
+        let x = "abcdefgh".find(chars[0].to_ascii_lowercase())?;
+        let y = "12345678".find(chars[1])?;
+        Some(y * 8 + x)
+    }
 
     pub fn get_player_color_as_bool(&self) -> bool {
         return match self.current_player {
@@ -179,94 +575,6 @@ pub fn new_custom(&mut self, arg: &str) {
         }
     }
 
-/// > For each row, print the piece at each column, or a blank space if there is no piece
-    pub fn print_me(&self){
-        for i in (0..8).rev() {
-            for j in 0..8 {
-                let x = i * 8;
-                match self.board[x+j] {
-                    Some(tile) => {
-                        match (tile.piecetype, tile.color) {
-                            (PieceType::PAWN(_), WHITE) => { print!(" PW ");}
-                            (PieceType::ROOK(_), WHITE) => { print!(" RW ");}
-                            (PieceType::KING(_), WHITE) => { print!(" KW ");}
-                            (PieceType::KNIGHT, WHITE) => { print!(" KnW ");}
-                            (PieceType::QUEEN, WHITE) => { print!(" QW ");}
-                            (PieceType::BISHOP, WHITE) => { print!(" BW ");}
-                            (PieceType::PAWN(_), BLACK) => { print!(" PB ");}
-                            (PieceType::ROOK(_), BLACK) => { print!(" RB ");}
-                            (PieceType::KING(_), BLACK) => { print!(" KB ");}
-                            (PieceType::KNIGHT, BLACK) => { print!(" KnB ");}
-                            (PieceType::QUEEN, BLACK) => { print!(" QB ");}
-                            (PieceType::BISHOP, BLACK) => { print!(" BB ");}
-                            (_, _) => {eprint!(" ? ");}
-                        }                   
-                    }
-                    None => {
-                        print!("  _  ");
-                    }
-                }
-            }
-            println!();
-        }
-        println!();
-    }
-
-/// For each tile in the board, print the unicode character for the piece on that tile, or a space if
-/// there is no piece
-    pub fn print_unicode(&self){
-        for i in (0..8).rev(){
-            for j in 0..8{
-                let x = i*8;
-                match self.board[x+j]{
-                    Some(tile) => {
-                        match (tile.piecetype, tile.color){
-                            (PieceType::PAWN(_), BLACK) => { print!(" ♙ ");}
-                            (PieceType::ROOK(_), BLACK) => { print!(" ♖ ");}
-                            (PieceType::KING(_), BLACK) => { print!(" ♔ ");}
-                            (PieceType::KNIGHT, BLACK) => { print!(" ♘ ");}
-                            (PieceType::QUEEN, BLACK) => { print!(" ♕ ");}
-                            (PieceType::BISHOP, BLACK) => { print!(" ♗ ");}
-                            (PieceType::PAWN(_), WHITE) => { print!(" ♟ ");}
-                            (PieceType::ROOK(_), WHITE) => { print!(" ♜ ");}
-                            (PieceType::KING(_), WHITE) => { print!(" ♚ ");}
-                            (PieceType::KNIGHT, WHITE) => { print!(" ♞ ");}
-                            (PieceType::QUEEN, WHITE) => { print!(" ♛ ");}
-                            (PieceType::BISHOP, WHITE) => { print!(" ♝ ");}
-                            (_, _) => {eprint!(" ? ");}
-                        }
-                    }
-                    None => {
-                        print!(" _ ");
-                    }
-                }
-            }
-            println!();
-        }
-    }
-
-/// It prints the board
-    pub fn print_board(&self){
-        for i in (1..9).rev(){
-            for j in 1..9 {
-                let c = match j {
-                    1 => 'A',
-                    2 => 'B',
-                    3 => 'C',
-                    4 => 'D',
-                    5 => 'E',
-                    6 => 'F',
-                    7 => 'G',
-                    8 => 'H',
-                    _ => 'X'
-                };
-                print!(" {}{} ", c, i);
-            }
-            println!();
-        }
-        println!();
-    }
-
 /// `coord_x` returns the x coordinate of a given coordinate
 /// 
 /// Arguments:
@@ -349,6 +657,27 @@ pub fn new_custom(&mut self, arg: &str) {
         return (coord2 as isize/8) - (coord1 as isize/8);
     }
 
+/// It moves from a coordinate by the given number of files and rows
+/// 
+/// Arguments:
+/// 
+/// * `coord`: The coordinate to start from.
+/// * `dx`: The number of files to the right, negative to the left.
+/// * `dy`: The number of rows up, negative down.
+/// 
+/// Returns:
+/// 
+/// The coordinate reached, or None if it is off the board.
+    pub fn step(coord: usize, dx: isize, dy: isize) -> Option<usize> {
+        let x = (coord % 8) as isize + dx;
+        let y = (coord / 8) as isize + dy;
+        if (0..8).contains(&x) && (0..8).contains(&y) {
+            Some((y * 8 + x) as usize)
+        } else {
+            None
+        }
+    }
+
     fn next_player(&mut self){
         self.current_player = match self.current_player {
             WHITE => BLACK,
@@ -480,7 +809,6 @@ pub fn new_custom(&mut self, arg: &str) {
     pub fn move_validity_checker(&mut self, piece_coord: &str, target_coord: &str) -> bool {
         self.debug_print("Move checker entered");
 
-        let valid_move: bool;
         let pc = GameState::coordinate_translator_str(piece_coord);
         let tc = GameState::coordinate_translator_str(target_coord);
         self.debug_print("Piece: ");
@@ -490,7 +818,23 @@ pub fn new_custom(&mut self, arg: &str) {
         self.debug_print(tc);
         self.debug_print(GameState::coordinate_translator_usize(tc));
 
-        if let Some(piece) = self.get_piece_at(piece_coord) {
+        self.piece_move_checker(pc, tc)
+    }
+
+/// It dispatches the move from `pc` to `tc` to the move checker of the piece standing on `pc`
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The index of the piece you want to move.
+/// * `tc`: The index of the target square.
+/// 
+/// Returns:
+/// 
+/// A boolean value, false if there is no piece at `pc`.
+    pub fn piece_move_checker(&mut self, pc: usize, tc: usize) -> bool {
+        let valid_move: bool;
+
+        if let Some(piece) = self.board[pc] {
             //piece.print_piece();
             match piece.piecetype {
                 PieceType::PAWN(_) => {
@@ -520,8 +864,8 @@ pub fn new_custom(&mut self, arg: &str) {
         return valid_move;
     }
 
-/// If the current coordinate is on the left side of the board and the target coordinate is on the right
-/// side of the board, or vice versa, then the move is invalid
+/// If the current coordinate and the target coordinate are more than one file apart, the step wrapped
+/// around the edge of the board and the move is invalid
 /// 
 /// Arguments:
 /// 
@@ -536,18 +880,9 @@ pub fn new_custom(&mut self, arg: &str) {
             self.debug_print("Out of bounds Y val");
             return false;
         }
-        if (current_coord_numeric % 8 == 0) && (target_coord_numeric % 7 == 0){
-            if target_coord_numeric == 0 {
-                return true; //Valid
-            }
-            self.debug_print("out of bounds 1");
-            return false; //Invalid crossing A -> H
-        } else if (current_coord_numeric % 7 == 0) && (target_coord_numeric % 8 == 0) {
-            if current_coord_numeric == 0 {
-                return true;
-            }
-            self.debug_print("out of bounds 2");
-            return false;
+        if GameState::abs_diff_x(current_coord_numeric, target_coord_numeric) > 1 {
+            self.debug_print("out of bounds, crossed A <-> H");
+            return false; //Invalid crossing A <-> H
         }
         true
     }
@@ -634,7 +969,15 @@ pub fn new_custom(&mut self, arg: &str) {
                             self.debug_print("Pawn move checker: Invalid move, did not cross A <-> H, target piece is same color");
                         }
                     },
-                    None => return false
+                    None => {
+                        //En passant, the square skipped by a pawn that just moved two steps
+                        let en_passant_row = if bool_tuple.0 { 5 } else { 2 };
+                        if self.en_passant != Some(tc) || GameState::coord_y(tc) != en_passant_row {
+                            return false;
+                        }
+                        self.debug_print("Pawn move checker: Valid en passant capture");
+                        returner = true;
+                    }
                 }
             } else {
                 //Invalid Move, crossed A <-> H
@@ -677,6 +1020,11 @@ pub fn new_custom(&mut self, arg: &str) {
         let color_bool: bool = piece.get_color_as_bool();
         let mut returner: bool = false;
 
+        if self.chess960 && matches!(self.board[tc], Some(p) if p.color == piece.color && matches!(p.piecetype, PieceType::ROOK(_))) {
+            //Chess960 castling, the king moves onto its own rook
+            self.debug_print("Castling check!");
+            return self.castling_check(pc, tc);
+        }
 
         if pc + 8 == tc || pc + 1 == tc || pc as isize -1 == tc as isize || pc as isize - 8 == tc as isize {
             self.debug_print("king_check first if");
@@ -717,7 +1065,7 @@ pub fn new_custom(&mut self, arg: &str) {
                     }
                 }
             }
-        } else if pc + 2 == tc || pc as isize - 2 == tc as isize {
+        } else if (pc + 2 == tc || pc as isize - 2 == tc as isize) && !self.chess960 {
             //Castling check
             self.debug_print("Castling check!");
             return self.castling_check(pc, tc);
@@ -949,10 +1297,19 @@ pub fn new_custom(&mut self, arg: &str) {
 
         if abs_diff_x > 0 {
             self.debug_print("Rook moved horizontally");
-            for i in 1..diff_x {
-                if self.board[(pc as isize + i) as usize].is_some() {
-                    self.debug_print("Rook move found piece");
-                    return false;
+            if diff_x < 0 {
+                for i in diff_x+1..0 {
+                    if self.board[(pc as isize + i) as usize].is_some() {
+                        self.debug_print("Rook move found piece");
+                        return false;
+                    }
+                }
+            } else {
+                for i in 1..diff_x {
+                    if self.board[(pc as isize + i) as usize].is_some() {
+                        self.debug_print("Rook move found piece");
+                        return false;
+                    }
                 }
             }
         } else if abs_diff_y > 0 {
@@ -1209,25 +1566,24 @@ pub fn new_custom(&mut self, arg: &str) {
                 }
             }
         }
-        let mut returner = String::new();
-        for elem in v {
-            returner.push_str(&elem);
-            returner.push_str(", ");
-        }
-        return returner;        
+        v.join(", ")
     }
 
 
 /// If the move is valid, make the move.
 /// Check if the player is checked, 
-///     if they are, revert the move,
+///     if they are, the move is not made,
 ///     if they aren't, change the player
 /// 
 /// Arguments:
 /// 
 /// * `pc`: The piece's current coordinate
 /// * `tc`: Target Coordinate
-    pub fn do_valid_move(&mut self, pc: &str, tc: &str) {
+/// 
+/// Returns:
+/// 
+/// Nothing if the move was made, a message if there is no piece to move or the rules do not allow it.
+    pub fn do_valid_move(&mut self, pc: &str, tc: &str) -> Result<(), String> {
         self.debug_print("Do Valid Move Entered");
         self.debug_print("pc: ");
         self.debug_print(pc);
@@ -1235,268 +1591,898 @@ pub fn new_custom(&mut self, arg: &str) {
         self.debug_print(tc);
         let pc_usize = GameState::coordinate_translator_str(pc);
         let tc_usize = GameState::coordinate_translator_str(tc);
-        if let Some(piece) = self.board[pc_usize] {
-            let mut was_checked = false;
-            if self.current_player == self.checked_player && self.checked_flag == true {
-                was_checked = true;
-            }
-            if pc_usize == self.wkc {
-                self.debug_print("Piece Coord is White King Coord");
-                self.debug_print(pc_usize);
-                self.debug_print(self.wkc);
-                self.old_wkc = self.wkc;
-                self.wkc = tc_usize;
-            } else if pc_usize == self.bkc {
-                self.debug_print("Piece Coord is Black King Coord");
-                self.debug_print(pc_usize);
-                self.debug_print(self.bkc);
-                self.old_bkc = self.bkc;
-                self.bkc = tc_usize;
-            }
-            self.prev_board = self.board;
-            self.board[tc_usize] = Some(piece);
-            self.board[pc_usize] = None;
-
-            //TODO: En Passant function
-            //TODO: Checkmate function
-            //TODO: Stalemate function
-            self.debug_print("before checked checker: ");
-            self.checked_checker();
-            self.debug_print("after checked checker: ");
-
-            if was_checked && self.checked_player == self.current_player {
-                self.board = self.prev_board;
-                self.wkc = self.old_wkc;
-                self.bkc = self.old_bkc;
-                self.debug_print("Reverting!!! Was checked, is still checked");
-                if self.castling_flag {
-                    self.debug_print("Invalid castling move????");
-                    self.castling_flag = false;
-                }
-            } else {
-                self.debug_print("Next player!");
-                self.next_player();
-            }
-            match piece.piecetype {
-                PieceType::KING(false) => {
-                    self.debug_print("Setting King to moved = true");
-                    self.board[tc_usize] = Some(ChessPiece::new(PieceType::KING(true), piece.color));
-                    self.debug_print(self.board[tc_usize]);
-                },
-                PieceType::PAWN(false) => {
-                    self.debug_print("Setting Pawn to moved = true");
-                    self.board[tc_usize] = Some(ChessPiece::new(PieceType::PAWN(true), piece.color));
-                    self.debug_print(self.board[tc_usize]);
-                },
-                PieceType::ROOK(false) => {
-                    self.debug_print("Setting Rook to moved = true");
-                    self.board[tc_usize] = Some(ChessPiece::new(PieceType::ROOK(true), piece.color));
-                    self.debug_print(self.board[tc_usize]);
-                },
-                _ => {
-                    //Do nothing
-                }
-            }
-            if self.castling_flag {
-                if pc_usize + 2 == tc_usize {
-                    if let Some( mut rook) = self.board[pc_usize + 3] {
-                        rook.piecetype = PieceType::ROOK(true);
-                        self.board[pc_usize + 3] = None;
-                        self.board[pc_usize + 1] = Some(rook);
-                    } else {
-                        panic!("This move should have been valid!");
-                    }
-                } else if pc_usize - 2 == tc_usize {
-                    if let Some(mut rook) = self.board[pc_usize - 4] {
+        if self.board[pc_usize].is_none() {
+            return Err(format!("There is no piece on {}", pc));
+        }
+        // The variant decides about the king's safety, in Atomic blowing up the other king wins
+        // even when the own king is left attacked. Only rules such as compulsory captures need the
+        // other moves as well.
+        let rules = self.variant.rules();
+        let allowed = self.is_legal_move(pc_usize, tc_usize)
+            && (!rules.filters_moves() || self.legal_moves().contains(&(pc_usize, tc_usize)));
+        if !allowed {
+            self.debug_print("Move would leave the king checked or the variant does not allow it");
+            return Err(format!("The {} rules do not allow this move", rules.name()));
+        }
+        self.apply_move(pc_usize, tc_usize, PieceType::QUEEN);
+        self.debug_print("before checked checker: ");
+        self.checked_checker();
+        self.debug_print("after checked checker: ");
+        self.debug_print("Next player!");
+        self.next_player();
+        self.castling_flag = false;
+        Ok(())
+    }
+
+/// It moves the piece at `pc` to `tc` without checking the move, updating the king coordinates, the
+/// move counters, the moved flags of kings, pawns and rooks and the en passant square, moving the rook along when the
+/// king castles, removing a pawn captured en passant and promoting a pawn that reaches the last row.
+/// Afterwards the variant gets to change the position, such as the explosion in Atomic.
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+/// * `promotion`: The piece a pawn reaching the last row becomes.
+    fn apply_move(&mut self, pc: usize, tc: usize, promotion: PieceType) {
+        let mut piece = match self.board[pc] {
+            Some(piece) => piece,
+            None => return
+        };
+        self.prev_board = self.board;
+        self.old_wkc = self.wkc;
+        self.old_bkc = self.bkc;
+        let capture = self.is_capture(pc, tc);
+        if capture || matches!(piece.piecetype, PieceType::PAWN(_)) {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if piece.color == BLACK {
+            self.fullmove_number += 1;
+        }
+
+        if self.variant.rules().has_pockets() {
+            self.pocket_capture(pc, tc, piece.color);
+        }
+
+        let mut target = tc;
+        let mut castled = false;
+        match piece.piecetype {
+            PieceType::KING(_) => {
+                if let Some(rook_from) = self.castling_rook(pc, tc) {
+                    self.debug_print("Moving the rook along with the castling king");
+                    let (king_to, rook_to) = GameState::castling_squares(pc, rook_from);
+                    if let Some(mut rook) = self.board[rook_from] {
                         rook.piecetype = PieceType::ROOK(true);
-                        self.board[pc_usize - 4] = None;
-                        self.board[pc_usize - 1] = Some(rook);
-                    } else {
-                        panic!("This move should have been valid!");
+                        self.board[rook_from] = None;
+                        self.board[pc] = None;
+                        self.board[rook_to] = Some(rook);
                     }
+                    target = king_to;
+                    castled = true;
                 }
-                self.castling_flag = false;
+                piece.piecetype = PieceType::KING(true);
+                match piece.color {
+                    WHITE => self.wkc = target,
+                    BLACK => self.bkc = target,
+                    UNCOLORED => {}
+                }
+            },
+            PieceType::PAWN(_) => {
+                // A Horde pawn moving up from the first row may still move two tiles from the second,
+                // just like the pawns a FEN places there
+                let start_row = if piece.color == WHITE { 1 } else { 6 };
+                piece.piecetype = PieceType::PAWN(GameState::coord_y(tc) != start_row);
+                if Some(tc) == self.en_passant && GameState::coord_x(pc) != GameState::coord_x(tc) {
+                    self.debug_print("Capturing en passant");
+                    let captured = if tc > pc { tc - 8 } else { tc + 8 };
+                    self.board[captured] = None;
+                }
+                if GameState::coord_y(tc) == 0 || GameState::coord_y(tc) == 7 {
+                    self.debug_print("Promoting the pawn");
+                    piece.piecetype = promotion;
+                }
+            },
+            PieceType::ROOK(_) => piece.piecetype = PieceType::ROOK(true),
+            _ => {}
+        }
+        self.en_passant = match piece.piecetype {
+            PieceType::PAWN(_) if GameState::abs_diff_y(pc, tc) == 2 => Some((pc + tc) / 2),
+            _ => None
+        };
+
+        if self.variant.rules().has_pockets() {
+            let was_promoted = self.promoted & (1 << pc) != 0;
+            self.promoted &= !(1 << pc) & !(1 << target);
+            if was_promoted || (!matches!(piece.piecetype, PieceType::PAWN(_)) && matches!(self.board[pc], Some(p) if matches!(p.piecetype, PieceType::PAWN(_)))) {
+                self.promoted |= 1 << target;
             }
-        } else {
-            panic!("This should have been a valid move!");
         }
+        // A castling king has already left its tile, where the rook may stand now in Chess960
+        if !castled {
+            self.board[pc] = None;
+        }
+        self.board[target] = Some(piece);
+        self.variant.rules().after_move(self, piece.color, target, capture);
     }
 
-/// For each piece on the board, check if the piece's moveset contains the king's position. If it does,
-/// set the checked flag to true and break
-pub fn checked_checker(&mut self) {
-    self.debug_print("Checked checker entered...");
-
-    for i in 0..64 {
-        let piece = match self.board[i] {
-            None => continue,
-            Some(piece) => piece
+/// It puts the piece captured by the move from `pc` to `tc` into the pocket of the capturing player,
+/// a promoted piece goes back into the pocket as a pawn
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+/// * `color`: The color of the capturing player.
+    fn pocket_capture(&mut self, pc: usize, tc: usize, color: Color) {
+        if !self.is_capture(pc, tc) || self.is_castling(pc, tc) {
+            return;
+        }
+        let captured = match self.board[tc] {
+            Some(_) => tc,
+            // En passant
+            None => if tc > pc { tc - 8 } else { tc + 8 }
         };
-        let piece_color = piece.get_color_as_bool();
-        let moveset = self.get_moves_from_tile(i);
-
-        if piece_color {
-            if moveset.contains(&self.bkc) {
-                self.debug_print("Piece that is checking: ");
-                self.debug_print(piece);
-                self.debug_print("Checking BLACK");
-                self.debug_print("bkc: ");
-                self.debug_print(self.bkc);
-                self.debug_print(GameState::coordinate_translator_usize(self.bkc));
-                self.debug_print("moveset: ");
-                self.debug_print(moveset);
-
+        let piecetype = match self.board[captured] {
+            Some(_) if self.promoted & (1 << captured) != 0 => PieceType::PAWN(true),
+            Some(piece) => piece.piecetype,
+            None => return
+        };
+        self.promoted &= !(1 << captured);
+        if let Some(index) = GameState::pocket_index(piecetype) {
+            self.pockets[GameState::pocket_owner(color)][index] += 1;
+        }
+    }
 
-                self.checked_flag = true;
-                self.checked_player = BLACK;
-                println!("Player {:?} has been checked", BLACK);
-                self.debug_print("Black has been checked");
-                break;
-            }   
-        } else {
-            if moveset.contains(&self.wkc) {
-                self.debug_print("Piece that is checking: ");
-                self.debug_print(piece);
-                self.debug_print("Checking WHITE");
-                self.debug_print("wkc: ");
-                self.debug_print(self.wkc);
-                self.debug_print(GameState::coordinate_translator_usize(self.wkc));
-                self.debug_print("moveset: ");
-                self.debug_print(moveset);
+/// It returns the index of the pocket of the given color in `pockets`
+    pub fn pocket_owner(color: Color) -> usize {
+        if color == BLACK { 1 } else { 0 }
+    }
 
-                self.checked_flag = true;
-                self.checked_player = WHITE;
-                println!("Player {:?} has been checked", WHITE);
-                self.debug_print("WHITE has been checked");
-                break;
-            }      
+/// It returns the index of the piece type in a pocket, None for kings
+    pub fn pocket_index(piecetype: PieceType) -> Option<usize> {
+        match piecetype {
+            PieceType::PAWN(_) => Some(0),
+            PieceType::KNIGHT => Some(1),
+            PieceType::BISHOP => Some(2),
+            PieceType::ROOK(_) => Some(3),
+            PieceType::QUEEN => Some(4),
+            _ => None
         }
-        self.checked_flag = false;
-        self.checked_player = UNCOLORED;
     }
-    self.debug_print("Checked checker left...");
-}
 
-/// If the king is not checked, and the king has not moved, and the king is not moving to a tile that is
-/// checked, and the king is not moving to a tile that is checked along the way, and the king is moving
-/// to the correct tile, and the rook is of the correct color and has not moved, then the move is valid
+/// It checks that the current player holds the piece type in their pocket, that `tc` is empty, that a
+/// pawn is not dropped on the first or last row and that the drop does not leave their own king checked
 /// 
 /// Arguments:
 /// 
-/// * `pc`: The position of the piece
-/// * `tc`: Target coordinate
+/// * `piecetype`: The type of the piece to drop.
+/// * `tc`: Target Coordinate
 /// 
 /// Returns:
 /// 
-/// A bool that is true if the move is valid and false if it is not.
-pub fn castling_check(&mut self, pc: usize, tc: usize) -> bool {
-    self.debug_print("Castling check entered...");
-    if let Some(piece) = self.board[pc] {
-        let piece_color = piece.get_color_as_bool();
-        if self.checked_flag {
-            if self.checked_player.match_color_as_bool() == piece_color {
-                self.debug_print("Cannot castle when checked");
-                return false;            
+/// True if the drop is legal.
+    pub fn is_legal_drop(&mut self, piecetype: PieceType, tc: usize) -> bool {
+        let index = match GameState::pocket_index(piecetype) {
+            Some(index) if self.variant.rules().has_pockets() => index,
+            _ => return false
+        };
+        if tc > 63 || self.board[tc].is_some() || self.pockets[GameState::pocket_owner(self.current_player)][index] == 0 {
+            return false;
+        }
+        if matches!(piecetype, PieceType::PAWN(_)) && (GameState::coord_y(tc) == 0 || GameState::coord_y(tc) == 7) {
+            return false;
+        }
+        let mut next_state: GameState = *self;
+        next_state.debug_flag = false;
+        next_state.apply_drop(piecetype, tc);
+        !next_state.is_checked(self.current_player)
+    }
+
+/// It returns every legal drop of the current player, empty outside Crazyhouse
+    pub fn legal_drops(&mut self) -> Vec<(PieceType, usize)> {
+        let mut v: Vec<(PieceType, usize)> = Vec::new();
+        if !self.variant.rules().has_pockets() {
+            return v;
+        }
+        for piecetype in POCKET_PIECES {
+            for tc in 0..64 {
+                if self.is_legal_drop(piecetype, tc) {
+                    v.push((piecetype, tc));
+                }
             }
         }
-        match (piece.piecetype, piece_color) {
-            (PieceType::KING(true), _) => {
-                self.debug_print("King has already castled");
-                return false;
-            },
-            (PieceType::KING(false), _) => {
-                //Do nothing
-            },
-            _ => {
-                panic!("This aint no king!");
+        v
+    }
+
+/// It returns every legal move of the current player, drops and promotions to every piece included
+    pub fn legal_move_kinds(&mut self) -> Vec<MoveKind> {
+        let mut v: Vec<MoveKind> = Vec::new();
+        for (pc, tc) in self.legal_moves() {
+            v.push(MoveKind::BOARD(pc, tc));
+            if self.is_promotion(pc, tc) {
+                v.extend(UNDERPROMOTION_PIECES.iter().map(|&piecetype| MoveKind::PROMOTION(pc, tc, piecetype)));
             }
         }
-        if tc == pc + 2 {
-            self.debug_print("Castling to the right");
-            if let Some(rook) = self.board[tc +1] {
-                match (rook.piecetype, rook.color) {
-                    (PieceType::ROOK(false), piece_color) => {
-                        //Seems correct!
-                        self.debug_print("Piece is a rook of correct color and hasnt moved");
-                    },
-                    _ => {
-                        self.debug_print("Piece is not a rook of correct color that hasnt mvoed");
-                        return false;
-                    }
+        v.extend(self.legal_drops().into_iter().map(|(piecetype, tc)| MoveKind::DROP(piecetype, tc)));
+        v
+    }
+
+/// It takes a piece of the current player out of the pocket and places it on `tc` without checking
+/// the drop. A pawn dropped on its second row may still move two tiles, a dropped rook can not castle.
+    fn apply_drop(&mut self, piecetype: PieceType, tc: usize) {
+        let index = match GameState::pocket_index(piecetype) {
+            Some(index) => index,
+            None => return
+        };
+        let color = self.current_player;
+        self.prev_board = self.board;
+        self.old_wkc = self.wkc;
+        self.old_bkc = self.bkc;
+        self.pockets[GameState::pocket_owner(color)][index] -= 1;
+        let start_row = if color == WHITE { 1 } else { 6 };
+        let piecetype = match piecetype {
+            PieceType::PAWN(_) => PieceType::PAWN(GameState::coord_y(tc) != start_row),
+            PieceType::ROOK(_) => PieceType::ROOK(true),
+            piecetype => piecetype
+        };
+        self.board[tc] = Some(ChessPiece::new(piecetype, color));
+        self.en_passant = None;
+        self.halfmove_clock += 1;
+        if color == BLACK {
+            self.fullmove_number += 1;
+        }
+    }
+
+/// If the drop is legal, drop the piece, look for checks and hand the turn to the other player
+/// 
+/// Arguments:
+/// 
+/// * `piecetype`: The type of the piece to drop.
+/// * `tc`: Target Coordinate
+/// 
+/// Returns:
+/// 
+/// True if the piece was dropped.
+    pub fn do_valid_drop(&mut self, piecetype: PieceType, tc: usize) -> bool {
+        if !self.is_legal_drop(piecetype, tc) {
+            return false;
+        }
+        self.apply_drop(piecetype, tc);
+        self.checked_checker();
+        self.next_player();
+        true
+    }
+
+/// It makes a legal move or drop without printing anything, like `make_move`
+    pub fn make_move_kind(&mut self, mv: MoveKind) {
+        match mv {
+            MoveKind::BOARD(pc, tc) => self.make_move(pc, tc),
+            MoveKind::PROMOTION(pc, tc, piecetype) => self.make_move_promoting(pc, tc, piecetype),
+            MoveKind::DROP(piecetype, tc) => {
+                self.apply_drop(piecetype, tc);
+                self.next_player();
+                self.checked_flag = self.is_checked(self.current_player);
+                self.checked_player = if self.checked_flag { self.current_player } else { UNCOLORED };
+            }
+        }
+    }
+
+/// It reads a drop written as `P@e4`, the piece letter followed by `@` and the target tile
+/// 
+/// Returns:
+/// 
+/// The piece type and target coordinate, None if the text is not a drop.
+    pub fn parse_drop(text: &str) -> Option<(PieceType, usize)> {
+        let (letter, square) = text.trim().split_once('@')?;
+        let piecetype = match letter {
+            "P" | "p" | "" => PieceType::PAWN(true),
+            "N" | "n" => PieceType::KNIGHT,
+            "B" | "b" => PieceType::BISHOP,
+            "R" | "r" => PieceType::ROOK(true),
+            "Q" | "q" => PieceType::QUEEN,
+            _ => return None
+        };
+        Some((piecetype, GameState::parse_square(square)?))
+    }
+
+/// It makes a legal move for the current player without printing anything, updates the checked flags
+/// and hands the turn to the other player. Used by the engine to walk the game tree.
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+    pub fn make_move(&mut self, pc: usize, tc: usize) {
+        self.make_move_promoting(pc, tc, PieceType::QUEEN);
+    }
+
+/// It makes a legal move like `make_move`, a pawn reaching the last row becomes the given piece
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+/// * `promotion`: The queen, rook, bishop or knight the pawn becomes.
+    pub fn make_move_promoting(&mut self, pc: usize, tc: usize, promotion: PieceType) {
+        let castling_flag = self.castling_flag;
+        self.apply_move(pc, tc, promotion);
+        self.castling_flag = castling_flag;
+        self.next_player();
+        if self.is_checked(self.current_player) {
+            self.checked_flag = true;
+            self.checked_player = self.current_player;
+        } else {
+            self.checked_flag = false;
+            self.checked_player = UNCOLORED;
+        }
+    }
+
+/// It checks whether any piece of the other color could capture the king of the given color
+/// 
+/// Arguments:
+/// 
+/// * `color`: The color of the king to look at.
+/// 
+/// Returns:
+/// 
+/// True if the king is attacked, false if it is not or if there is no such king on the board.
+    pub fn is_checked(&mut self, color: Color) -> bool {
+        let king_coord = match color {
+            WHITE => self.wkc,
+            BLACK => self.bkc,
+            UNCOLORED => return false
+        };
+        if king_coord > 63 || !self.variant.rules().can_be_checked(self, color) {
+            return false;
+        }
+        self.is_square_attacked(king_coord, color.opposite())
+    }
+
+/// It returns every piece of the given color that attacks `tile`, whatever stands on it. Only the
+/// geometry of the pieces is looked at: pawns attack diagonally forwards, sliding pieces stop at the
+/// first piece in their way and castling never attacks.
+/// 
+/// Arguments:
+/// 
+/// * `tile`: The tile that is attacked.
+/// * `color`: The color of the attacking pieces.
+/// 
+/// Returns:
+/// 
+/// A vector with the coordinates of the attacking pieces, in ascending order.
+    pub fn attackers_of(&self, tile: usize, color: Color) -> Vec<usize> {
+        let mut v: Vec<usize> = Vec::new();
+        let is = |coord: usize, matches: &dyn Fn(PieceType) -> bool| {
+            matches!(self.board[coord], Some(piece) if piece.color == color && matches(piece.piecetype))
+        };
+        let forward: isize = if color == WHITE { 1 } else { -1 };
+        for dx in [-1, 1] {
+            if let Some(coord) = GameState::step(tile, dx, -forward) {
+                if is(coord, &|p| matches!(p, PieceType::PAWN(_))) {
+                    v.push(coord);
                 }
-                for i in 1..3 {
-                    match self.board[pc + i] {
-                        Some(piece) => {
-                            self.debug_print("There seems to be a piece in the way at: ");
-                            self.debug_print(pc + i);
-                            self.debug_print(piece);
-                            return false;
-                        }
-                        None => {
-                            //Do nothing
-                        }
+            }
+        }
+        for (dx, dy) in KNIGHT_JUMPS {
+            if let Some(coord) = GameState::step(tile, dx, dy) {
+                if is(coord, &|p| p == PieceType::KNIGHT) {
+                    v.push(coord);
+                }
+            }
+        }
+        for (directions, diagonal) in [(ROOK_DIRECTIONS, false), (BISHOP_DIRECTIONS, true)] {
+            for (dx, dy) in directions {
+                let mut coord = tile;
+                let mut distance = 0;
+                while let Some(next) = GameState::step(coord, dx, dy) {
+                    coord = next;
+                    distance += 1;
+                    if self.board[coord].is_none() {
+                        continue;
                     }
-                    if !self.get_color_moves_to_tile(pc + i, Color::match_bool_as_color(!piece_color)).is_empty() {
-                        self.debug_print("Move would result in being checked along the way!");
-                        return false;
+                    let slides = |p: PieceType| p == PieceType::QUEEN || if diagonal { p == PieceType::BISHOP } else { matches!(p, PieceType::ROOK(_)) };
+                    if is(coord, &slides) || (distance == 1 && is(coord, &|p| matches!(p, PieceType::KING(_)))) {
+                        v.push(coord);
                     }
+                    break;
                 }
-            } else {
-                self.debug_print("There was no rook to the right!");
-                return false;
             }
-        } else if tc == pc - 2 {
-            self.debug_print("Castling to the left");
-            self.debug_print("Tc: ");
-            self.debug_print(tc);
-            self.debug_print(tc as isize - 2);
-            if tc as isize - 2 < 0 {
-                self.debug_print("Tc is less than 0");
-                return false;
+        }
+        v.sort_unstable();
+        v
+    }
+
+/// It returns every tile the piece on `tile` attacks, see attackers_of
+/// 
+/// Arguments:
+/// 
+/// * `tile`: The coordinate of the piece.
+/// 
+/// Returns:
+/// 
+/// A vector with the attacked coordinates in ascending order, empty if the tile is empty.
+    pub fn attacks_from(&self, tile: usize) -> Vec<usize> {
+        let piece = match self.board[tile] {
+            Some(piece) => piece,
+            None => return Vec::new()
+        };
+        let mut v: Vec<usize> = Vec::new();
+        let forward: isize = if piece.color == WHITE { 1 } else { -1 };
+        let (steps, slides): (Vec<(isize, isize)>, bool) = match piece.piecetype {
+            PieceType::PAWN(_) => (vec![(-1, forward), (1, forward)], false),
+            PieceType::KNIGHT => (KNIGHT_JUMPS.to_vec(), false),
+            PieceType::BISHOP => (BISHOP_DIRECTIONS.to_vec(), true),
+            PieceType::ROOK(_) => (ROOK_DIRECTIONS.to_vec(), true),
+            PieceType::QUEEN => ([ROOK_DIRECTIONS, BISHOP_DIRECTIONS].concat(), true),
+            PieceType::KING(_) => ([ROOK_DIRECTIONS, BISHOP_DIRECTIONS].concat(), false),
+            PieceType::NONE => (Vec::new(), false)
+        };
+        for (dx, dy) in steps {
+            let mut coord = tile;
+            while let Some(next) = GameState::step(coord, dx, dy) {
+                coord = next;
+                v.push(coord);
+                if !slides || self.board[coord].is_some() {
+                    break;
+                }
             }
-            if let Some(rook) = self.board[(tc as isize - 2) as usize] {
-                match (rook.piecetype, rook.get_color_as_bool()) {
-                    (PieceType::ROOK(false), piece_color) => {
-                        //Seems correct!
-                        self.debug_print("Piece is a rook of correct color and hasnt moved");
-                    },
-                    _ => {
-                        self.debug_print("Piece is not a rook of correct color that hasnt mvoed");
-                        return false;
-                    }
+        }
+        v.sort_unstable();
+        v
+    }
+
+/// It checks that the piece at `pc` belongs to the current player, that it can move to `tc`, and that
+/// the variant allows the move, in standard chess when it does not leave its own king checked
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+/// 
+/// Returns:
+/// 
+/// True if the move is legal.
+    pub fn is_legal_move(&mut self, pc: usize, tc: usize) -> bool {
+        let piece = match self.board[pc] {
+            Some(piece) => piece,
+            None => return false
+        };
+        if piece.color != self.current_player || pc == tc {
+            return false;
+        }
+        let castling_flag = self.castling_flag;
+        let valid_move = self.piece_move_checker(pc, tc);
+        self.castling_flag = castling_flag;
+        if !valid_move {
+            return false;
+        }
+        self.variant.rules().allows_move(self, pc, tc)
+    }
+
+/// It tells whether the move is legal for the current player, like `is_legal_move` for moves on the
+/// board. A promotion to a knight, bishop or rook is legal when the queen promotion is.
+    pub fn is_legal_move_kind(&mut self, mv: MoveKind) -> bool {
+        match mv {
+            MoveKind::BOARD(pc, tc) => self.is_legal_move(pc, tc),
+            MoveKind::PROMOTION(pc, tc, piecetype) => {
+                UNDERPROMOTION_PIECES.contains(&piecetype) && self.is_promotion(pc, tc) && self.is_legal_move(pc, tc)
+            },
+            MoveKind::DROP(piecetype, tc) => self.is_legal_drop(piecetype, tc)
+        }
+    }
+
+/// It tells whether the piece at `pc` is a pawn moving to the last row at `tc`
+    pub fn is_promotion(&self, pc: usize, tc: usize) -> bool {
+        matches!(self.board[pc], Some(piece) if matches!(piece.piecetype, PieceType::PAWN(_))) && (GameState::coord_y(tc) == 0 || GameState::coord_y(tc) == 7)
+    }
+
+/// It tells whether moving the piece at `pc` to `tc` would leave the king of the moving player checked
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+    pub fn leaves_king_checked(&self, pc: usize, tc: usize) -> bool {
+        let color = match self.board[pc] {
+            Some(piece) => piece.color,
+            None => return false
+        };
+        self.preview_move(pc, tc).is_checked(color)
+    }
+
+/// It returns the position after moving the piece at `pc` to `tc`, with the moving player still in
+/// turn and without checking the move
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+    pub fn preview_move(&self, pc: usize, tc: usize) -> GameState {
+        let mut next_state: GameState = *self;
+        next_state.debug_flag = false;
+        next_state.apply_move(pc, tc, PieceType::QUEEN);
+        next_state
+    }
+
+/// It returns every legal move of the current player as (piece coordinate, target coordinate) pairs
+/// 
+/// Returns:
+/// 
+/// A vector of moves, empty if the current player is checkmated or stalemated.
+    pub fn legal_moves(&mut self) -> Vec<(usize, usize)> {
+        let mut v: Vec<(usize, usize)> = Vec::new();
+        for pc in 0..64 {
+            match self.board[pc] {
+                Some(piece) if piece.color == self.current_player => {},
+                _ => continue
+            }
+            for tc in 0..64 {
+                if self.is_legal_move(pc, tc) {
+                    v.push((pc, tc));
                 }
-                for i in 1..3 {
-                    match self.board[pc - i] {
-                        Some(_) => {
-                            self.debug_print("There seems to be a piece in the way at: ");
-                            self.debug_print(pc - i);
-                            return false;
-                        }
-                        None => {
-                            //Do nothing
-                        }
+            }
+        }
+        self.variant.rules().filter_moves(self, v)
+    }
+
+/// It decides whether the game is over, by the rules of the variant
+/// 
+/// Returns:
+/// 
+/// The outcome of the game, None while it goes on.
+    pub fn outcome(&mut self) -> Option<Outcome> {
+        let rules = self.variant.rules();
+        if let Some(winner) = rules.winner(self) {
+            return Some(Outcome::WIN(winner));
+        }
+        if self.legal_move_kinds().is_empty() {
+            return Some(rules.no_moves_outcome(self));
+        }
+        if rules.is_draw(self) {
+            return Some(Outcome::DRAW);
+        }
+        None
+    }
+
+/// It removes the piece on `tc` and every piece other than a pawn on the eight tiles around it, as a
+/// capture does in Atomic chess
+    pub fn explode(&mut self, tc: usize) {
+        for coord in 0..64 {
+            if GameState::abs_diff_x(coord, tc) > 1 || GameState::abs_diff_y(coord, tc) > 1 {
+                continue;
+            }
+            match self.board[coord] {
+                Some(piece) if coord == tc || !matches!(piece.piecetype, PieceType::PAWN(_)) => {
+                    self.debug_print("Exploding: ");
+                    self.debug_print(piece);
+                    self.board[coord] = None;
+                    if coord == self.wkc {
+                        self.wkc = 64;
                     }
-                    if !self.get_color_moves_to_tile(pc - i, Color::match_bool_as_color(!piece_color)).is_empty() {
-                        self.debug_print("Move would result in being checked along the way!");
-                        return false;
+                    if coord == self.bkc {
+                        self.bkc = 64;
                     }
-                }
-            } else {
-                self.debug_print("There was no rook to the left!");
-                return false;
+                },
+                _ => {}
             }
+        }
+    }
+
+/// It returns the file of the en passant square when a pawn of the player to move stands next to the
+/// pawn that just made a double step. This is when Polyglot hashes the en passant file: without such a
+/// pawn the capture cannot be made and the position is the same as one without the square.
+    pub fn en_passant_file(&self) -> Option<usize> {
+        let en_passant = self.en_passant?;
+        let pawn = if self.current_player == WHITE { en_passant.checked_sub(8)? } else { en_passant + 8 };
+        let can_capture = [-1, 1].iter().filter_map(|&dx| GameState::step(pawn, dx, 0)).any(|n| {
+            matches!(self.board[n], Some(p) if p.color == self.current_player && matches!(p.piecetype, PieceType::PAWN(_)))
+        });
+        if can_capture { Some(GameState::coord_x(en_passant)) } else { None }
+    }
+
+/// It tells which castling moves are still possible later in the game, because neither the king nor
+/// the rook has moved
+///
+/// Returns:
+///
+/// The rights in the order White short, White long, Black short, Black long.
+    pub fn castling_rights(&self) -> [bool; 4] {
+        let king = |color: Color| -> Option<usize> {
+            let row = if color == WHITE { 0 } else { 56 };
+            (row..row + 8).find(|&coord| matches!(self.board[coord], Some(p) if p.color == color && p.piecetype == PieceType::KING(false)))
+        };
+        let castling: [(Color, bool); 4] = [(WHITE, true), (WHITE, false), (BLACK, true), (BLACK, false)];
+        castling.map(|(color, short)| king(color).and_then(|king| self.castling_rook_on_side(king, short)).is_some())
+    }
+
+/// It counts the pieces on the board, kings included
+    pub fn piece_count(&self) -> usize {
+        self.board.iter().filter(|tile| tile.is_some()).count()
+    }
+
+/// Static exchange evaluation of moving the piece at `pc` to `tc`. Both sides keep recapturing on
+/// `tc` with their least valuable attacker for as long as it pays off, pieces moving away uncover
+/// x-ray attackers behind them.
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The coordinate of the first capturing piece.
+/// * `tc`: The coordinate of the square the exchange takes place on.
+/// 
+/// Returns:
+/// 
+/// The material balance of the exchange in centipawns for the side making the first capture, a
+/// negative value means the capture loses material.
+    pub fn see(&self, pc: usize, tc: usize) -> i32 {
+        let piece = match self.board[pc] {
+            Some(piece) => piece,
+            None => return 0
+        };
+        let mut state: GameState = *self;
+        state.debug_flag = false;
+
+        let mut gain: [i32; 33] = [0; 33];
+        let mut depth: usize = 0;
+        gain[0] = match state.board[tc] {
+            Some(target) => target.piecetype.value(),
+            None => 0
+        };
+        let mut attacker_value = piece.piecetype.value();
+        state.board[tc] = Some(piece);
+        state.board[pc] = None;
+        let mut side = piece.color.opposite();
+
+        while depth < 32 {
+            depth += 1;
+            gain[depth] = attacker_value - gain[depth - 1];
+            if std::cmp::max(-gain[depth - 1], gain[depth]) < 0 {
+                break;
+            }
+            let attacker = state.attackers_of(tc, side).into_iter()
+                .filter_map(|coord| state.board[coord].map(|p| (coord, p)))
+                .min_by_key(|(_, p)| p.piecetype.value());
+            match attacker {
+                Some((coord, p)) => {
+                    attacker_value = p.piecetype.value();
+                    state.board[tc] = Some(p);
+                    state.board[coord] = None;
+                    side = side.opposite();
+                },
+                None => break
+            }
+        }
+        while depth > 1 {
+            depth -= 1;
+            gain[depth - 1] = -std::cmp::max(-gain[depth - 1], gain[depth]);
+        }
+        gain[0]
+    }
+
+/// For each piece on the board, check if the piece's moveset contains the king's position. If it does,
+/// set the checked flag to true and break. Kings the variant does not allow to be checked are skipped,
+/// such as any king in Antichess or a king standing next to the other king in Atomic.
+pub fn checked_checker(&mut self) {
+    self.debug_print("Checked checker entered...");
+    let white_checkable = self.variant.rules().can_be_checked(self, WHITE);
+    let black_checkable = self.variant.rules().can_be_checked(self, BLACK);
+    self.checked_flag = false;
+    self.checked_player = UNCOLORED;
+
+    for i in 0..64 {
+        let piece = match self.board[i] {
+            None => continue,
+            Some(piece) => piece
+        };
+        let piece_color = piece.get_color_as_bool();
+        let moveset = self.get_moves_from_tile(i);
+
+        if piece_color {
+            if black_checkable && moveset.contains(&self.bkc) {
+                self.debug_print("Piece that is checking: ");
+                self.debug_print(piece);
+                self.debug_print("Checking BLACK");
+                self.debug_print("bkc: ");
+                self.debug_print(self.bkc);
+                self.debug_print(GameState::coordinate_translator_usize(self.bkc));
+                self.debug_print("moveset: ");
+                self.debug_print(moveset);
+
+
+                self.checked_flag = true;
+                self.checked_player = BLACK;
+                println!("Player {:?} has been checked", BLACK);
+                self.debug_print("Black has been checked");
+                break;
+            }   
         } else {
-            self.debug_print("Castling to the wrong side!");
+            if white_checkable && moveset.contains(&self.wkc) {
+                self.debug_print("Piece that is checking: ");
+                self.debug_print(piece);
+                self.debug_print("Checking WHITE");
+                self.debug_print("wkc: ");
+                self.debug_print(self.wkc);
+                self.debug_print(GameState::coordinate_translator_usize(self.wkc));
+                self.debug_print("moveset: ");
+                self.debug_print(moveset);
+
+                self.checked_flag = true;
+                self.checked_player = WHITE;
+                println!("Player {:?} has been checked", WHITE);
+                self.debug_print("WHITE has been checked");
+                break;
+            }      
+        }
+        self.checked_flag = false;
+        self.checked_player = UNCOLORED;
+    }
+    self.debug_print("Checked checker left...");
+}
+
+/// If the king is not checked, has not moved and does not pass a checked tile on its way, and the
+/// rook it castles with has not moved, and all tiles the king and rook pass are empty, then the move
+/// is valid. In standard chess the king moves two tiles towards the rook, in Chess960 it moves onto
+/// the rook; either way it ends on the g- or c-file with the rook next to it on the inside.
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The position of the piece
+/// * `tc`: Target coordinate
+/// 
+/// Returns:
+/// 
+/// A bool that is true if the move is valid and false if it is not.
+pub fn castling_check(&mut self, pc: usize, tc: usize) -> bool {
+    self.debug_print("Castling check entered...");
+    let king = match self.board[pc] {
+        Some(piece) if piece.piecetype == PieceType::KING(false) => piece,
+        Some(piece) if matches!(piece.piecetype, PieceType::KING(true)) => {
+            self.debug_print("King has already moved");
+            return false;
+        },
+        _ => panic!("This aint no king!")
+    };
+    let rook = match self.castling_rook(pc, tc) {
+        Some(rook) => rook,
+        None => {
+            self.debug_print("There is no rook to castle with on that side");
+            return false;
+        }
+    };
+    let (king_to, rook_to) = GameState::castling_squares(pc, rook);
+
+    let first = pc.min(rook).min(king_to).min(rook_to);
+    let last = pc.max(rook).max(king_to).max(rook_to);
+    for coord in first..last + 1 {
+        if coord != pc && coord != rook && self.board[coord].is_some() {
+            self.debug_print("There seems to be a piece in the way at: ");
+            self.debug_print(coord);
+            return false;
+        }
+    }
+    for coord in pc.min(king_to)..pc.max(king_to) + 1 {
+        if self.is_square_attacked(coord, king.color.opposite()) {
+            self.debug_print("Cannot castle out of, through or into check");
             return false;
         }
-        self.castling_flag = true;
-        self.debug_print("Castling check left...");
-        return true;
     }
+    self.castling_flag = true;
     self.debug_print("Castling check left...");
-    return false;
+    true
 }
 
+/// It finds the rook the king on `pc` castles with when moving to `tc`: in standard chess when the
+/// king moves two tiles along its first row, in Chess960 when it moves onto one of its own rooks
+/// 
+/// Returns:
+/// 
+/// The coordinate of the rook, None if the move is not castling or the king or rook has moved.
+    fn castling_rook(&self, pc: usize, tc: usize) -> Option<usize> {
+        if pc == tc || GameState::coord_y(pc) != GameState::coord_y(tc) {
+            return None;
+        }
+        let king = self.board[pc]?;
+        if self.chess960 {
+            match self.board[tc] {
+                Some(rook) if rook.color == king.color && rook.piecetype == PieceType::ROOK(false) && self.castling_rook_on_side(pc, tc > pc).is_some() => Some(tc),
+                _ => None
+            }
+        } else if GameState::abs_diff_x(pc, tc) == 2 {
+            self.castling_rook_on_side(pc, tc > pc)
+        } else {
+            None
+        }
+    }
+
+/// It finds the outermost unmoved rook on one side of an unmoved king standing on its first row
+    fn castling_rook_on_side(&self, king: usize, short: bool) -> Option<usize> {
+        let piece = self.board[king.min(63)]?;
+        let home_row = if piece.color == WHITE { 0 } else { 7 };
+        if piece.piecetype != PieceType::KING(false) || GameState::coord_y(king) != home_row {
+            return None;
+        }
+        let is_rook = |x: &usize| matches!(self.board[home_row * 8 + x], Some(p) if p.color == piece.color && p.piecetype == PieceType::ROOK(false));
+        let x = if short {
+            (GameState::coord_x(king) + 1..8).rev().find(is_rook)
+        } else {
+            (0..GameState::coord_x(king)).find(is_rook)
+        };
+        x.map(|x| home_row * 8 + x)
+    }
+
+/// It returns where the king and the rook end up when the king on `king` castles with the rook on `rook`
+    fn castling_squares(king: usize, rook: usize) -> (usize, usize) {
+        let row = GameState::coord_y(king) * 8;
+        if rook > king {
+            (row + 6, row + 5)
+        } else {
+            (row + 2, row + 3)
+        }
+    }
+
+/// It tells whether moving the piece at `pc` to `tc` castles, written the way of the current game:
+/// two tiles with the king in standard chess, king onto rook in Chess960
+    pub fn is_castling(&self, pc: usize, tc: usize) -> bool {
+        self.castling_rook(pc, tc).is_some()
+    }
+
+/// It rewrites a castling move given the other way than the current game writes it. In standard
+/// chess the king taking its own rook becomes the two tile king move, in Chess960 a king move to the
+/// g- or c-file that is not a plain king move becomes the king taking the rook.
+/// 
+/// Arguments:
+/// 
+/// * `pc`: The coordinate of the moving piece.
+/// * `tc`: The target coordinate as given.
+/// 
+/// Returns:
+/// 
+/// The target coordinate to use, `tc` itself when nothing needs rewriting.
+    pub fn castling_input(&self, pc: usize, tc: usize) -> usize {
+        let king = match self.board[pc] {
+            Some(piece) if piece.piecetype == PieceType::KING(false) => piece,
+            _ => return tc
+        };
+        if GameState::coord_y(pc) != GameState::coord_y(tc) {
+            return tc;
+        }
+        let takes_rook = matches!(self.board[tc], Some(p) if p.color == king.color && p.piecetype == PieceType::ROOK(false));
+        if !self.chess960 && takes_rook && GameState::abs_diff_x(pc, tc) > 1 {
+            return if tc > pc { pc + 2 } else { pc - 2 };
+        }
+        let file = GameState::coord_x(tc);
+        if self.chess960 && !takes_rook && GameState::abs_diff_x(pc, tc) > 1 && (file == 6 || file == 2) {
+            if let Some(rook) = self.castling_rook_on_side(pc, file == 6) {
+                return rook;
+            }
+        }
+        tc
+    }
+
+/// It tells whether the move captures a piece of the other player, en passant included
+    pub fn is_capture(&self, pc: usize, tc: usize) -> bool {
+        match (self.board[pc], self.board[tc]) {
+            (Some(piece), Some(target)) => piece.color != target.color,
+            (Some(piece), None) => matches!(piece.piecetype, PieceType::PAWN(_)) && GameState::coord_x(pc) != GameState::coord_x(tc),
+            _ => false
+        }
+    }
+
+/// It checks whether a piece of the given color attacks `tile`, see attackers_of
+/// 
+/// Arguments:
+/// 
+/// * `tile`: The tile to look at.
+/// * `color`: The color of the attacking pieces.
+/// 
+/// Returns:
+/// 
+/// True if the tile is attacked.
+    pub fn is_square_attacked(&self, tile: usize, color: Color) -> bool {
+        !self.attackers_of(tile, color).is_empty()
+    }
+
 /// "Return the index of the white king, or 64 if there is no white king."
 /// 
 /// The function starts by looping over all 64 squares on the board. For each square, it checks if there
@@ -1552,31 +2538,35 @@ pub fn find_black_king(&self) -> usize {
 /// Arguments:
 /// 
 /// * `coord_numeric`: The coordinate of the piece you want to place.
-/// * `color_string`: "Black" or "White"
-/// * `piece_string`: The type of piece you want to place.
-    pub fn place_piece(&mut self, coord_numeric: usize, color_string: &str, piece_string: &str){
-        let color: Color = match color_string {
-            "Black" =>  BLACK,
+/// * `color_string`: "Black" or "White", in any case
+/// * `piece_string`: The type of piece you want to place, such as "Knight", in any case.
+/// 
+/// Returns:
+/// 
+/// An error naming the argument that could not be read, the board is then left as it was.
+    pub fn place_piece(&mut self, coord_numeric: usize, color_string: &str, piece_string: &str) -> Result<(), String> {
+        let color: Color = match color_string.trim().to_lowercase().as_str() {
             "black" => BLACK,
-            "BLACK" => BLACK,
             "white" => WHITE,
-            "White" => WHITE,
-            "WHITE" => WHITE,
-            _ => panic!("color should be written as 'Black' or 'White'")
+            _ => return Err(format!("The color should be written as 'Black' or 'White', not '{}'", color_string.trim()))
         };
 
-        let piecetype: PieceType = match piece_string {
-            "Pawn" => PieceType::PAWN(false),
-            "Rook" => PieceType::ROOK(false),
-            "King" =>  PieceType::KING(false),
-            "Bishop" =>  PieceType::BISHOP,
-            "Knight" => PieceType::KNIGHT,
-            "Queen" => PieceType::QUEEN,
-            _ => panic!("Invalid Piece: '{}'", piece_string)
+        let piecetype: PieceType = match piece_string.trim().to_lowercase().as_str() {
+            "pawn" => PieceType::PAWN(false),
+            "rook" => PieceType::ROOK(false),
+            "king" =>  PieceType::KING(false),
+            "bishop" =>  PieceType::BISHOP,
+            "knight" => PieceType::KNIGHT,
+            "queen" => PieceType::QUEEN,
+            _ => return Err(format!("Invalid piece: '{}'", piece_string.trim()))
         };
+        if coord_numeric > 63 {
+            return Err("The coordinate is outside the board".to_string());
+        }
 
         let piece = ChessPiece::new(piecetype, color);
         self.board[coord_numeric] = Some(piece);
+        Ok(())
     }
 
 /// `debug_print` takes a string and prints it to the console
@@ -1591,3 +2581,161 @@ pub fn find_black_king(&self) -> usize {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn rook_cannot_jump_over_pieces_towards_the_a_file() {
+        let mut gamestate = GameState::from_fen("4k3/8/8/8/8/8/4K3/3N3R w - - 0 1").unwrap();
+        assert!(!gamestate.piece_move_checker(square("h1"), square("a1")));
+        assert!(!gamestate.piece_move_checker(square("h1"), square("c1")));
+        assert!(gamestate.piece_move_checker(square("h1"), square("e1")));
+        assert!(!gamestate.legal_moves().contains(&(square("h1"), square("b1"))));
+    }
+
+    #[test]
+    fn rook_path_is_checked_in_every_direction() {
+        let mut gamestate = GameState::from_fen("3r4/8/8/1p1R1p2/8/8/3p4/4K2k w - - 0 1").unwrap();
+        let d5 = square("d5");
+        for (target, allowed) in [("a5", false), ("b5", true), ("h5", false), ("f5", true), ("d8", true), ("d1", false), ("d2", true)] {
+            assert_eq!(gamestate.piece_move_checker(d5, square(target)), allowed, "d5 to {}", target);
+        }
+    }
+
+    #[test]
+    fn pieces_on_the_a_and_h_files_step_without_wrapping_around() {
+        let mut gamestate = GameState::from_fen("k7/8/8/8/5K2/8/4R3/8 b - - 0 1").unwrap();
+        let mut moves = gamestate.legal_moves();
+        moves.sort();
+        assert_eq!(moves, vec![(square("a8"), square("a7")), (square("a8"), square("b7")), (square("a8"), square("b8"))]);
+
+        let mut gamestate = GameState::from_fen("4k3/8/8/8/7N/8/8/K6R w - - 0 1").unwrap();
+        for (from, target, allowed) in [("a1", "a2", true), ("a1", "b2", true), ("a1", "h2", false), ("h1", "a2", false),
+            ("h4", "g6", true), ("h4", "f5", true), ("h4", "a6", false), ("h4", "b5", false), ("h4", "b3", false)] {
+            assert_eq!(gamestate.piece_move_checker(square(from), square(target)), allowed, "{} to {}", from, target);
+        }
+    }
+
+    #[test]
+    fn chess960_rook_may_land_on_the_tile_the_king_left() {
+        let mut gamestate = GameState::from_fen("4k3/8/8/8/8/8/8/5KR1 w G - 0 1").unwrap();
+        assert!(gamestate.is_legal_move(square("f1"), square("g1")));
+        gamestate.make_move(square("f1"), square("g1"));
+        assert!(gamestate.to_fen().starts_with("4k3/8/8/8/8/8/8/5RK1 b"));
+
+        let mut gamestate = GameState::from_fen("4k3/8/8/8/8/8/8/R2K4 w A - 0 1").unwrap();
+        assert!(gamestate.is_legal_move(square("d1"), square("a1")));
+        gamestate.make_move(square("d1"), square("a1"));
+        assert!(gamestate.to_fen().starts_with("4k3/8/8/8/8/8/8/2KR4 b"));
+        assert_eq!(gamestate.wkc, square("c1"));
+    }
+
+    #[test]
+    fn pawn_captures_en_passant_only_right_after_the_double_step() {
+        let mut gamestate = GameState::from_fen("4k3/3p4/8/4P3/8/8/8/4K3 b - - 0 1").unwrap();
+        gamestate.make_move(square("d7"), square("d5"));
+        assert_eq!(gamestate.en_passant, Some(square("d6")));
+        assert!(gamestate.legal_moves().contains(&(square("e5"), square("d6"))));
+        let mut captured = gamestate;
+        captured.make_move(square("e5"), square("d6"));
+        assert!(captured.board[square("d5")].is_none());
+        assert!(matches!(captured.board[square("d6")], Some(p) if p.color == WHITE));
+
+        // The right is lost when it is not used at once
+        gamestate.make_move(square("e1"), square("e2"));
+        gamestate.make_move(square("e8"), square("e7"));
+        assert_eq!(gamestate.en_passant, None);
+        assert!(!gamestate.legal_moves().contains(&(square("e5"), square("d6"))));
+    }
+
+    #[test]
+    fn queen_cannot_slide_through_pieces_along_the_rank() {
+        let mut gamestate = GameState::from_fen("4k3/8/8/8/8/8/4K3/Q1B4q b - - 0 1").unwrap();
+        assert!(!gamestate.piece_move_checker(square("h1"), square("a1")));
+        assert!(gamestate.piece_move_checker(square("h1"), square("f1")));
+    }
+
+    #[test]
+    fn pawn_promotes_to_any_of_the_four_pieces() {
+        let mut gamestate = GameState::from_fen("7k/4P3/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let (e7, e8) = (square("e7"), square("e8"));
+        let promotions = gamestate.legal_move_kinds().into_iter()
+            .filter(|&mv| mv == MoveKind::BOARD(e7, e8) || matches!(mv, MoveKind::PROMOTION(pc, tc, _) if pc == e7 && tc == e8))
+            .count();
+        assert_eq!(promotions, 4);
+        for piecetype in [PieceType::QUEEN, PieceType::ROOK(true), PieceType::BISHOP, PieceType::KNIGHT] {
+            let mut promoted = gamestate;
+            let mv = MoveKind::promotion(e7, e8, piecetype);
+            assert!(promoted.is_legal_move_kind(mv));
+            promoted.make_move_kind(mv);
+            assert!(matches!(promoted.board[e8], Some(p) if p.piecetype == piecetype && p.color == WHITE), "{:?}", piecetype);
+        }
+        assert!(!gamestate.is_legal_move_kind(MoveKind::PROMOTION(square("a1"), square("a2"), PieceType::KNIGHT)));
+    }
+
+    #[test]
+    fn underpromotions_are_read_and_written_in_san_and_uci() {
+        let mut gamestate = GameState::from_fen("3r3k/4P3/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let (e7, d8, e8) = (square("e7"), square("d8"), square("e8"));
+        let knight = MoveKind::PROMOTION(e7, d8, PieceType::KNIGHT);
+        assert_eq!(crate::pgn::parse_san(&mut gamestate, "exd8=N"), Some(knight));
+        assert_eq!(crate::pgn::format_san(&mut gamestate, knight), "exd8=N");
+        assert_eq!(crate::pgn::parse_san(&mut gamestate, "e8=R"), Some(MoveKind::PROMOTION(e7, e8, PieceType::ROOK(true))));
+        assert_eq!(crate::pgn::parse_san(&mut gamestate, "e8=Q"), Some(MoveKind::BOARD(e7, e8)));
+        assert_eq!(crate::pgn::parse_san(&mut gamestate, "e8"), Some(MoveKind::BOARD(e7, e8)));
+        assert_eq!(crate::pgn::parse_san(&mut gamestate, "Kb2=N"), None);
+        assert_eq!(crate::game::format_move(&gamestate, knight), "e7d8n");
+        assert_eq!(crate::game::parse_move(&gamestate, "e7d8n"), Some(knight));
+        assert_eq!(crate::game::parse_move(&gamestate, "e7e8b"), Some(MoveKind::PROMOTION(e7, e8, PieceType::BISHOP)));
+        assert_eq!(crate::game::parse_move(&gamestate, "e7e8q"), Some(MoveKind::BOARD(e7, e8)));
+    }
+
+    #[test]
+    fn promoted_knight_goes_back_to_the_pocket_as_a_pawn() {
+        let mut gamestate = GameState::from_fen("3r3k/4P3/8/8/8/8/3r4/K7[] w - - 0 1").unwrap();
+        gamestate.make_move_kind(MoveKind::PROMOTION(square("e7"), square("d8"), PieceType::KNIGHT));
+        assert_eq!(gamestate.pockets[0], [0, 0, 0, 1, 0]);
+        gamestate.make_move(square("d2"), square("d8"));
+        assert_eq!(gamestate.pockets[1], [1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn promoted_queen_and_rook_go_back_to_the_pocket_as_pawns() {
+        let mut gamestate = GameState::from_fen("3r3k/4P3/8/8/8/8/3r4/K7[] w - - 0 1").unwrap();
+        gamestate.make_move(square("e7"), square("d8"));
+        assert!(matches!(gamestate.board[square("d8")], Some(p) if p.piecetype == PieceType::QUEEN));
+        assert!(gamestate.to_fen().starts_with("3Q~3k/"));
+        gamestate.make_move(square("d2"), square("d8"));
+        assert_eq!(gamestate.pockets[1], [1, 0, 0, 0, 0]);
+
+        let mut gamestate = GameState::from_fen("7k/4P3/8/8/8/8/8/K3r3[] w - - 0 1").unwrap();
+        gamestate.make_move_kind(MoveKind::PROMOTION(square("e7"), square("e8"), PieceType::ROOK(true)));
+        assert!(gamestate.checked_flag);
+        gamestate.make_move(square("e1"), square("e8"));
+        assert_eq!(gamestate.pockets[1], [1, 0, 0, 0, 0]);
+        assert_eq!(gamestate.promoted, 0);
+    }
+
+    #[test]
+    fn do_valid_move_reports_moves_it_does_not_make() {
+        let mut gamestate = GameState::from_fen("4k3/8/8/8/8/8/4r3/4K3 w - - 0 1").unwrap();
+        let before = gamestate.to_fen();
+        assert_eq!(gamestate.do_valid_move("a1", "a2"), Err("There is no piece on a1".to_string()));
+        assert_eq!(gamestate.do_valid_move("e1", "f2"), Err("The standard rules do not allow this move".to_string()));
+        assert_eq!(gamestate.to_fen(), before);
+        assert_eq!(gamestate.do_valid_move("e1", "e2"), Ok(()));
+        assert_eq!(gamestate.current_player, BLACK);
+
+        // Antichess forces the capture, the quiet pawn move is not allowed
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/3p4/4P3/8/P7/4K3 w - - 0 1", VariantKind::ANTICHESS).unwrap();
+        assert!(gamestate.do_valid_move("a2", "a3").is_err());
+        assert_eq!(gamestate.current_player, WHITE);
+        assert_eq!(gamestate.do_valid_move("e4", "d5"), Ok(()));
+    }
+}
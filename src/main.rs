@@ -1,38 +1,278 @@
+pub mod analysis;
+pub mod attacks;
+pub mod binary;
+pub mod clock;
+pub mod eco;
+pub mod epd;
+pub mod game;
 pub mod gamestate;
+pub mod net;
+pub mod pgn;
 pub mod piece;
+pub mod polyglot;
+pub mod puzzle;
+pub mod render;
+pub mod script;
+pub mod search;
+pub mod server;
+pub mod setup;
+pub mod syzygy;
+pub mod transposition;
+pub mod tui;
+pub mod uci;
+pub mod variant;
 
+use clock::*;
+use eco::*;
+use epd::*;
+use game::*;
 use gamestate::*;
+use net::*;
 use piece::*;
+use polyglot::*;
+use render::*;
+use script::*;
+use search::*;
+use syzygy::*;
+use variant::*;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 
 const WHITE: Color = Color::WHITE;
 const BLACK: Color = Color::BLACK;
 
 fn main() {
-    play();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|arg| arg.as_str()) {
+        Some("epd") => run_epd(&args[1..]),
+        Some("serve") => run_server(&args[1..]),
+        Some("puzzles") => run_puzzles(&args[1..], &mut CommandInput::stdin(), &BoardRenderer::new()),
+        Some(mode @ ("host" | "join")) => {
+            let mut gamestate = GameState::new();
+            let mut game = Game::new(gamestate);
+            network_game(mode, args.get(1).map_or("", |arg| arg.as_str()), &mut gamestate, &mut game, &mut CommandInput::stdin(), &BoardRenderer::new())
+        },
+        _ => run_game(&args)
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// It plays a game with commands typed in the terminal, or read line by line from a script given as
+/// `--script <file>` or piped to the standard input. The final position is printed as FEN with `--fen`.
+///
+/// Returns:
+///
+/// A message if the script cannot be read or some of its commands failed.
+fn run_game(args: &[String]) -> Result<(), String> {
+    let mut script: Option<&String> = None;
+    let mut print_fen = false;
+    let mut options = args.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--script" => script = Some(options.next().ok_or("--script needs a file")?),
+            "--fen" => print_fen = true,
+            _ => return Err(format!("Unknown option '{}', usage: [--script <file>] [--fen]", option))
+        }
+    }
+    let mut input = match script {
+        Some(path) => CommandInput::open(path)?,
+        None => CommandInput::stdin()
+    };
+    let gamestate = play(&mut input);
     println!("Chess Finished!");
+    if print_fen {
+        println!("{}", gamestate.to_fen());
+    }
+    if input.script && input.errors > 0 {
+        return Err(format!("{} command(s) of {} failed", input.errors, input.source));
+    }
+    Ok(())
 }
 
-fn serialize(gs: &mut GameState) -> bool {
-    gs.serialize_me();
-    true
+/// It runs an EPD test suite given on the command line as `epd <file> [--depth N | --time SECONDS]`,
+/// searching to depth 6 when no limit is given
+fn run_epd(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or("Usage: epd <file> [--depth N | --time SECONDS]")?;
+    let mut limit = EpdLimit::DEPTH(6);
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(format!("{} needs a value", option))?;
+        limit = match option.as_str() {
+            "--depth" => EpdLimit::DEPTH(value.parse().map_err(|_| format!("Invalid depth '{}'", value))?),
+            "--time" => EpdLimit::TIME(std::time::Duration::from_secs_f64(value.parse().map_err(|_| format!("Invalid time '{}'", value))?)),
+            _ => return Err(format!("Unknown option '{}'", option))
+        };
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let (records, errors) = parse_epd(&text);
+    for error in errors {
+        println!("Skipped {}", error);
+    }
+    let mut engine = Engine::new();
+    run_suite(&mut engine, &records, limit);
+    Ok(())
 }
 
-/// It creates a new game state, prints the board, and then loops forever, calling player_move on the
-/// game state.
-fn play(){
+/// It serves tactics puzzles given on the command line as
+/// `puzzles <file.csv> [--rating <file.json>] [--theme <theme>]`, keeping the rating in
+/// puzzle_rating.json by default
+fn run_puzzles(args: &[String], input: &mut CommandInput, renderer: &BoardRenderer) -> Result<(), String> {
+    let path = args.first().ok_or("Usage: puzzles <file.csv> [--rating <file.json>] [--theme <theme>]")?;
+    let mut profile_path = "puzzle_rating.json".to_string();
+    let mut theme: Option<String> = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(format!("{} needs a value", option))?;
+        match option.as_str() {
+            "--rating" => profile_path = value.clone(),
+            "--theme" => theme = Some(value.clone()),
+            _ => return Err(format!("Unknown option '{}'", option))
+        }
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let puzzles = puzzle::parse_puzzles(&text)?;
+    puzzle::train(&puzzles, &profile_path, theme.as_deref(), input, renderer)
+}
+
+/// It hosts a network game on a port or joins one at an address, then plays it with moves read from the
+/// input
+///
+/// Arguments:
+///
+/// * `mode`: "host" or "join".
+/// * `arg`: The port to host on, on 127.0.0.1 unless an address such as "0.0.0.0:7878" is given, or the
+///   address to join.
+/// * `gamestate`: The position, the game to host starts here and the game played ends here.
+/// * `game`: The game record, replaced by the host's game when joining.
+/// * `input`: Where the moves are read from.
+/// * `renderer`: Draws the board during the game.
+fn network_game(mode: &str, arg: &str, gamestate: &mut GameState, game: &mut Game, input: &mut CommandInput, renderer: &BoardRenderer) -> Result<(), String> {
+    let (mut connection, color) = if mode == "host" {
+        let listener = listen(arg)?;
+        host(&listener, game)?
+    } else {
+        if arg.trim().is_empty() {
+            return Err("Usage: join <host:port>".to_string());
+        }
+        let (connection, joined, position, color) = join(arg.trim(), &game.black)?;
+        *game = joined;
+        *gamestate = position;
+        (connection, color)
+    };
+    let result = play_network(&mut connection, color, gamestate, game, input, renderer)?;
+    println!("Network game over: {:?}", result);
+    Ok(())
+}
+
+/// It runs the game server given on the command line as `serve [address] [--pgn <file>] [--ws <address>]`,
+/// listening on 127.0.0.1:8080, for WebSockets on the next port, and appending finished games to
+/// games.pgn by default
+fn run_server(args: &[String]) -> Result<(), String> {
+    let mut address = "127.0.0.1:8080".to_string();
+    let mut ws_address: Option<String> = None;
+    let mut pgn_path = Some("games.pgn".to_string());
+    let mut options = args.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--pgn" => pgn_path = Some(options.next().ok_or("--pgn needs a file")?.clone()),
+            "--no-pgn" => pgn_path = None,
+            "--ws" => ws_address = Some(options.next().ok_or("--ws needs an address")?.clone()),
+            arg => address = arg.to_string()
+        }
+    }
+    let server = server::GameServer::bind(&address, pgn_path)?;
+    let ws_address = ws_address.unwrap_or_else(|| match address.rsplit_once(':') {
+        Some((host, port)) => format!("{}:{}", host, port.parse::<u16>().map_or(0, |port| port.saturating_add(1))),
+        None => "127.0.0.1:0".to_string()
+    });
+    let ws_port = server.listen_websocket(&ws_address)?;
+    println!("Game server listening on port {}, WebSockets on port {}", server.port(), ws_port);
+    server.run();
+    Ok(())
+}
+
+/// It prints the game in the JSON model written by `save`
+fn serialize(game: &Game, clock: &Option<Clock>) -> Result<(), String> {
+    let json = game.to_json(clock)?;
+    println!("Serialized: '{}'", json);
+    Ok(())
+}
+
+/// The engine and the opening files loaded during a session.
+///
+/// Properties:
+///
+/// * `engine`: The engine playing `go` and analyzing games.
+/// * `book`: The opening book the engine plays from, loaded with `book`.
+/// * `openings`: The ECO database naming the opening of the game, loaded with `eco`.
+struct Resources {
+    engine: Engine,
+    book: Option<OpeningBook>,
+    openings: Option<EcoDatabase>,
+}
+
+/// It creates a new game state, prints the board, and then loops until the game ends or the input runs
+/// out, calling player_command on the game state.
+///
+/// Returns:
+///
+/// The final position.
+fn play(input: &mut CommandInput) -> GameState {
 
     let mut gamestate: GameState = GameState::new();
-    gamestate.print_board();
+    let mut game: Game = Game::new(gamestate);
+    let mut clock: Option<Clock> = None;
+    let mut renderer: BoardRenderer = BoardRenderer::new();
+    let mut resources = Resources { engine: Engine::new(), book: None, openings: EcoDatabase::find_default() };
     loop {
-        //gamestate.print_me();
-        gamestate.print_unicode();
-        if let gamestate = player_command(&mut gamestate) {
-            //Continue
-        } else {
-            println!("Something went wrong! Could not create new gamestate");
+        renderer.print(&gamestate, game.moves.last().copied());
+        if gamestate.variant.rules().has_pockets() {
+            println!("Pockets: White [{}]  Black [{}]", format_pocket(&gamestate, WHITE), format_pocket(&gamestate, BLACK));
+        }
+        if let Some(clock) = &clock {
+            println!("White: {}  Black: {}", clock.format_remaining(WHITE), clock.format_remaining(BLACK));
+        }
+        let player = gamestate.current_player;
+        let played = game.moves.len();
+        if player_command(&mut gamestate, &mut game, &mut clock, &mut resources, &mut renderer, input).is_none() {
+            if !input.finished {
+                println!("Something went wrong! Could not create new gamestate");
+            }
             break;
         }
+        if let Some(openings) = &resources.openings {
+            if game.moves.len() != played {
+                openings.tag(&mut game);
+            }
+        }
+        if let Some(clock) = clock.as_mut() {
+            // The full-screen interface presses the clock itself for every move it plays
+            let flagged = if gamestate.current_player != player && game.moves.len() <= played + 1 { clock.press() } else { clock.check_flag() };
+            if flagged != Color::UNCOLORED {
+                println!("{:?} ran out of time, {:?} wins!", flagged, flagged.opposite());
+                break;
+            }
+        }
+        if gamestate.current_player != player || game.moves.len() > played + 1 {
+            match gamestate.outcome() {
+                Some(Outcome::WIN(winner)) => {
+                    renderer.print(&gamestate, game.moves.last().copied());
+                    println!("{:?} wins!", winner);
+                    break;
+                },
+                Some(Outcome::DRAW) => {
+                    renderer.print(&gamestate, game.moves.last().copied());
+                    println!("Draw!");
+                    break;
+                },
+                None => {}
+            }
+        }
     }
+    gamestate
 }
 
 ///
@@ -41,7 +281,8 @@ fn play(){
 /// ### Returns 
 /// a tuple (bool, usize, usize) representing (validity, start index of move, target index of move)
 ///
-fn player_command(gamestate: &mut GameState) -> Option<usize> {
+fn player_command(gamestate: &mut GameState, game: &mut Game, clock: &mut Option<Clock>, resources: &mut Resources, renderer: &mut BoardRenderer, input: &mut CommandInput) -> Option<usize> {
+    let Resources { engine, book, openings } = resources;
 
     let mut command: bool = false;
     let turn = match gamestate.current_player {
@@ -50,23 +291,30 @@ fn player_command(gamestate: &mut GameState) -> Option<usize> {
         _ => {panic!("what?");}
     };
     println!("Debug: {}", gamestate.debug_flag);
-    let mut line = String::new();
     println!("Player: {}'s move: ", turn);
     if gamestate.checked_flag {
         println!("Checked: {:?}!", gamestate.checked_player);
     }
-    std::io::stdin().read_line(&mut line).unwrap();
+    let mut line = input.read_command()?;
+    if line.trim().is_empty() {
+        // Blank lines let scripts be laid out
+        return Some(0);
+    }
 
     if gamestate.debug_flag {
         if line == "place_piece\n" {
             println!("Enter: coord, color, piecetype");
-            line = String::new();
-            std::io::stdin().read_line(&mut line).unwrap();
+            line = input.read_command()?;
 
-            let command_iter: Vec<&str> = line.split(" ").collect();
+            let command_iter: Vec<&str> = line.split_whitespace().collect();
 
-            let coord = GameState::coordinate_translator_str(command_iter[0]);
-            gamestate.place_piece(coord, command_iter[1], command_iter[2].strip_suffix('\n')?);
+            match (command_iter.as_slice(), command_iter.first().and_then(|square| GameState::parse_square(square))) {
+                ([_, color, piece], Some(coord)) => match gamestate.place_piece(coord, color, piece) {
+                    Ok(()) => game.restart(*gamestate),
+                    Err(e) => input.error(&e)
+                },
+                _ => input.error("Expected a coordinate, a color and a piece type, such as 'e4 white knight'")
+            }
             command = true;
         } else if line == "leave_debug\n" {
             println!("left debug mode...");
@@ -74,29 +322,307 @@ fn player_command(gamestate: &mut GameState) -> Option<usize> {
             command = true;
         } else if line == "serialize\n"{
             println!("serializing...");
-            serialize(gamestate);
-            println!("done!");
+            match serialize(game, clock) {
+                Ok(()) => println!("done!"),
+                Err(e) => input.error(&e)
+            }
             line = String::new();
             command = true;
         } else if line == "black\n"{
             println!("swapping to black...");
             gamestate.current_player = BLACK;
+            game.restart(*gamestate);
             command = true;
         } else if line == "white\n" {
             println!("swapping to white...");
             gamestate.current_player = WHITE;
+            game.restart(*gamestate);
             command = true;
         } else if line == "new_game_custom\n" {
             println!("Enter a specific game state: ");
-            line = String::new();
-            std::io::stdin().read_line(&mut line).unwrap();
+            line = input.read_command()?;
             let arg: &str = line.strip_suffix('\n')?;
             println!("argument given: '{}'", arg);
-            gamestate.new_custom(arg);
+            let mut custom = *gamestate;
+            match custom.new_custom(arg) {
+                Ok(()) => {
+                    *gamestate = custom;
+                    game.restart(*gamestate);
+                },
+                Err(e) => input.error(&format!("Invalid game state: {}", e))
+            }
             return Some(0);
         }
     }
 
+    if line == "go\n" || line.starts_with("go ") {
+        let depth: Option<u32> = line.trim().strip_prefix("go").unwrap_or("").trim().parse().ok();
+        engine_move(gamestate, game, depth, clock, engine, book);
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("book ") {
+        let args: Vec<&str> = arg.split_whitespace().collect();
+        match args.first().map(|path| OpeningBook::open(path)) {
+            Some(Ok(mut new_book)) => {
+                if args.get(1) == Some(&"best") {
+                    new_book.selection = BookSelection::BEST;
+                }
+                println!("Loaded {} book entries", new_book.entries.len());
+                *book = Some(new_book);
+            },
+            Some(Err(e)) => input.error(&e),
+            None => input.error("Usage: book <file.bin> [best]")
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("makebook ") {
+        let args: Vec<&str> = arg.split_whitespace().collect();
+        if args.len() < 2 {
+            input.error("Usage: makebook <games.pgn> <book.bin> [plies]");
+        } else {
+            let plies: usize = args.get(2).and_then(|p| p.parse().ok()).unwrap_or(20);
+            match std::fs::read_to_string(args[0]) {
+                Ok(text) => {
+                    let new_book = OpeningBook::from_pgn(&text, plies);
+                    match new_book.save(args[1]) {
+                        Ok(()) => println!("Wrote {} book entries to {}", new_book.entries.len(), args[1]),
+                        Err(e) => input.error(&e)
+                    }
+                },
+                Err(e) => input.error(&format!("Could not read '{}': {}", args[0], e))
+            }
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("threads ") {
+        match arg.trim().parse::<usize>() {
+            Ok(threads) if threads > 0 => {
+                engine.threads = threads;
+                println!("Engine searches with {} threads", threads);
+            },
+            _ => input.error(&format!("Invalid number of threads: '{}'", arg.trim()))
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("syzygy ") {
+        match Tablebase::open(arg.trim()) {
+            Ok(tablebase) => {
+                println!("Found {} tablebase files for up to {} pieces", tablebase.table_count(), tablebase.max_pieces);
+                engine.tablebase = Some(tablebase);
+            },
+            Err(e) => input.error(&e)
+        }
+        command = true;
+    } else if line == "probe\n" {
+        match &engine.tablebase {
+            Some(tablebase) => match (tablebase.probe_wdl(gamestate), tablebase.probe_dtz(gamestate)) {
+                (Ok(wdl), Ok(dtz)) => println!("{:?} for {:?}, {} plies to zeroing", wdl, gamestate.current_player, dtz),
+                (Err(e), _) | (_, Err(e)) => input.error(&e)
+            },
+            None => input.error("No tablebase loaded, use: syzygy <directory>")
+        }
+        command = true;
+    } else if line == "chess960\n" || line.starts_with("chess960 ") {
+        let index: usize = match line.trim().strip_prefix("chess960").unwrap_or("").trim() {
+            "" => (RandomState::new().build_hasher().finish() % 960) as usize,
+            arg => match arg.parse() {
+                Ok(index) if index < 960 => index,
+                _ => {
+                    input.error(&format!("Invalid Chess960 position: '{}', use a number from 0 to 959", arg));
+                    return Some(0);
+                }
+            }
+        };
+        *gamestate = GameState::new_chess960(index);
+        game.restart(*gamestate);
+        println!("Chess960 position {}", index);
+        command = true;
+    } else if line == "variant\n" {
+        let names: Vec<&str> = VariantKind::ALL.iter().map(|variant| variant.rules().name()).collect();
+        println!("Playing {}, available variants: {}", gamestate.variant.rules().name(), names.join(", "));
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("variant ") {
+        match VariantKind::from_name(arg) {
+            Some(variant) => {
+                *gamestate = variant.start_position();
+                game.restart(*gamestate);
+                println!("New {} game started", variant.rules().name());
+            },
+            None => input.error(&format!("Unknown variant: '{}'", arg.trim()))
+        }
+        command = true;
+    } else if line == "crazyhouse\n" {
+        *gamestate = VariantKind::CRAZYHOUSE.start_position();
+        game.restart(*gamestate);
+        println!("Crazyhouse game started, drop pieces with e.g. 'P@e4'");
+        command = true;
+    } else if let Some((piecetype, tc)) = GameState::parse_drop(&line) {
+        let tcs = GameState::coordinate_translator_usize(tc);
+        if gamestate.do_valid_drop(piecetype, tc) {
+            game.record(MoveKind::DROP(piecetype, tc));
+            println!("Valid drop!: {:?} @ {}", piecetype, tcs);
+        } else {
+            input.error(&format!("Invalid drop!: {:?} @ {}", piecetype, tcs));
+        }
+        command = true;
+    } else if line == "analyze\n" || line.starts_with("analyze ") {
+        let mut depth = 4;
+        let mut path: Option<&str> = None;
+        for arg in line.split_whitespace().skip(1) {
+            match arg.parse() {
+                Ok(n) => depth = n,
+                Err(_) => path = Some(arg)
+            }
+        }
+        let result = match gamestate.outcome() {
+            Some(Outcome::WIN(WHITE)) => "1-0",
+            Some(Outcome::WIN(_)) => "0-1",
+            Some(Outcome::DRAW) => "1/2-1/2",
+            None => "*"
+        };
+        println!("Analyzing {} moves at depth {}...", game.moves.len(), depth);
+        let moves = analysis::analyze_game(engine, game, depth);
+        analysis::print_analysis(game, &moves);
+        let pgn = analysis::annotated_pgn(game, &moves, result).to_pgn();
+        match path {
+            Some(path) => match std::fs::write(path, pgn) {
+                Ok(()) => println!("Annotated game written to {}", path),
+                Err(e) => input.error(&format!("Could not write {}: {}", path, e))
+            },
+            None => print!("{}", pgn)
+        }
+        command = true;
+    } else if line == "eco\n" {
+        match (openings.as_ref(), game.metadata.get("ECO"), game.metadata.get("Opening")) {
+            (None, _, _) => println!("No ECO database loaded, load one with 'eco <file>'"),
+            (Some(_), Some(eco), Some(name)) => println!("{} {}", eco, name),
+            (Some(_), _, _) => println!("Unknown opening")
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("eco ") {
+        match EcoDatabase::load(arg.trim()) {
+            Ok(database) => {
+                println!("Loaded {} openings from {}", database.len(), arg.trim());
+                database.tag(game);
+                *openings = Some(database);
+            },
+            Err(e) => input.error(&e)
+        }
+        command = true;
+    } else if line == "threats\n" {
+        print!("{}", attacks::render_threats(gamestate));
+        command = true;
+    } else if line == "moves\n" || line == "moves --json\n" {
+        let moves = pgn::annotated_moves(gamestate);
+        if line.contains("--json") {
+            match serde_json::to_string_pretty(&moves) {
+                Ok(json) => println!("{}", json),
+                Err(e) => input.error(&e.to_string())
+            }
+        } else {
+            print_moves(&moves);
+        }
+        command = true;
+    } else if line == "setup\n" {
+        if let Some(position) = setup::run_setup(gamestate, input, renderer) {
+            *gamestate = position;
+            game.restart(*gamestate);
+        }
+        command = true;
+    } else if line == "undo\n" {
+        match game.undo() {
+            Ok(position) => {
+                *gamestate = position;
+                println!("Took back the last move");
+            },
+            Err(e) => input.error(&e)
+        }
+        command = true;
+    } else if line == "fen\n" {
+        println!("{}", gamestate.to_fen());
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("fen ") {
+        match GameState::from_fen(arg) {
+            Ok(position) => {
+                *gamestate = position;
+                game.restart(*gamestate);
+                println!("Position loaded");
+            },
+            Err(e) => input.error(&format!("Invalid FEN: {}", e))
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("save ") {
+        match game.save(arg.trim(), clock) {
+            Ok(()) => println!("Game saved to {}", arg.trim()),
+            Err(e) => input.error(&e)
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("load ") {
+        match Game::load(arg.trim()) {
+            Ok((loaded, position, saved_clock)) => {
+                *game = loaded;
+                *gamestate = position;
+                *clock = saved_clock;
+                if let Some(clock) = clock.as_mut() {
+                    clock.start(gamestate.current_player);
+                }
+                println!("Loaded {} vs {}, {} moves played", game.white, game.black, game.moves.len());
+            },
+            Err(e) => input.error(&format!("Could not load the game: {}", e))
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("name ") {
+        match arg.trim().split_once(' ') {
+            Some(("white", name)) => game.white = name.trim().to_string(),
+            Some(("black", name)) => game.black = name.trim().to_string(),
+            _ => input.error("Usage: name <white|black> <name>")
+        }
+        command = true;
+    } else if line == "board\n" || line.starts_with("board ") {
+        let args: Vec<&str> = line.split_whitespace().skip(1).collect();
+        match args.as_slice() {
+            ["flip"] => renderer.orientation = if renderer.is_flipped(gamestate) { Orientation::WHITE } else { Orientation::BLACK },
+            [side] if Orientation::from_name(side).is_some() => renderer.orientation = Orientation::from_name(side).unwrap(),
+            ["coordinates", "on"] => renderer.coordinates = true,
+            ["coordinates", "off"] => renderer.coordinates = false,
+            ["pieces", set] if PieceSet::from_name(set).is_some() => renderer.piece_set = PieceSet::from_name(set).unwrap(),
+            ["theme", theme] if Theme::from_name(theme).is_some() => renderer.theme = Theme::from_name(theme).unwrap(),
+            ["highlight", "on"] => (renderer.highlight_last_move, renderer.highlight_check) = (true, true),
+            ["highlight", "off"] => (renderer.highlight_last_move, renderer.highlight_check) = (false, false),
+            _ => input.error("Usage: board <white|black|auto|flip> | coordinates <on|off> | pieces <ascii|unicode|letters> | theme <plain|classic|blue|green> | highlight <on|off>")
+        }
+        command = true;
+    } else if let Some((mode, arg)) = line.split_once(' ').filter(|(mode, _)| *mode == "host" || *mode == "join") {
+        if let Err(e) = network_game(mode, arg, gamestate, game, input, renderer) {
+            input.error(&e);
+        }
+        command = true;
+    } else if let Some(arg) = line.strip_prefix("puzzles ") {
+        let args: Vec<String> = arg.split_whitespace().map(|arg| arg.to_string()).collect();
+        if let Err(e) = run_puzzles(&args, input, renderer) {
+            input.error(&e);
+        }
+        command = true;
+    } else if line == "tui\n" && input.script {
+        input.error("The full-screen interface needs a terminal");
+        command = true;
+    } else if line == "tui\n" {
+        if let Err(e) = tui::run(gamestate, game, clock, engine, openings.as_ref()) {
+            input.error(&e);
+        }
+        command = true;
+    } else if line == "uci\n" {
+        uci::uci_loop(engine, input);
+        std::process::exit(0);
+    } else if let Some(arg) = line.strip_prefix("clock ") {
+        match TimeControl::parse(arg) {
+            Ok(time_control) => {
+                let mut new_clock = Clock::new(time_control);
+                new_clock.start(gamestate.current_player);
+                *clock = Some(new_clock);
+                println!("Clocks started!");
+            },
+            Err(e) => input.error(&format!("Invalid time control: {}", e))
+        }
+        command = true;
+    }
+
     if line == "debug_mode\n" {
         println!("Entered debug mode");
         gamestate.debug_flag = true;
@@ -107,23 +633,119 @@ fn player_command(gamestate: &mut GameState) -> Option<usize> {
     } else {
 
         if !command {
-            let player_move_command_iter: Vec<&str> = line.split(" ").collect();
-            if player_move_command_iter.len() == 3 {
-                let valid_move = gamestate.move_validity_checker(player_move_command_iter[0], player_move_command_iter[2].strip_suffix('\n')?);
-                if valid_move {
-                    println!("Valid move!: {} -> {}", player_move_command_iter[0], player_move_command_iter[2].strip_suffix('\n')?);
-                    gamestate.do_valid_move(player_move_command_iter[0], player_move_command_iter[2].strip_suffix('\n')?);
+            let player_move_command_iter: Vec<&str> = line.split_whitespace().collect();
+            let squares: Vec<Option<usize>> = player_move_command_iter.iter().map(|square| GameState::parse_square(square)).collect();
+            if let [from, _, to] = player_move_command_iter.as_slice() {
+                if let Some((target, letter)) = to.split_once('=') {
+                    // A pawn reaching the last row may become another piece, as in "e7 - e8=N"
+                    let promotion = match letter.chars().collect::<Vec<char>>().as_slice() {
+                        [letter] => MoveKind::promotion_piece(*letter),
+                        _ => None
+                    };
+                    match (squares[0], GameState::parse_square(target), promotion) {
+                        (Some(pc), Some(tc), Some(piecetype)) if gamestate.is_legal_move_kind(MoveKind::promotion(pc, tc, piecetype)) => {
+                            println!("Valid move!: {} -> {}", from, to);
+                            let mv = MoveKind::promotion(pc, tc, piecetype);
+                            gamestate.make_move_kind(mv);
+                            game.record(mv);
+                        },
+                        _ => input.error(&format!("Invalid move!: {} -> {}", from, to))
+                    }
+                } else if let (Some(pc), Some(tc)) = (squares[0], squares[2]) {
+                    // Castling may be given as the king taking its own rook, or the other way around in Chess960
+                    let target = GameState::coordinate_translator_usize(gamestate.castling_input(pc, tc));
+                    let valid_move = gamestate.move_validity_checker(from, &target);
+                    if valid_move {
+                        println!("Valid move!: {} -> {}", from, target);
+                        warn_losing_capture(gamestate, from, &target);
+                        match gamestate.do_valid_move(from, &target) {
+                            Ok(()) => game.record(MoveKind::BOARD(pc, GameState::coordinate_translator_str(&target))),
+                            Err(message) => input.error(&message)
+                        }
+                    } else {
+                        input.error(&format!("Invalid move!: {} -> {}", from, to));
+                    }
                 } else {
-                    println!("Invalid move!: {} -> {}", player_move_command_iter[0], player_move_command_iter[2].strip_suffix('\n')?);
-                    
+                    input.error(&format!("Invalid move!: {} -> {}", from, to));
                 }
-            } else if player_move_command_iter.len() == 1 {
-                println!("Valid moves: {}", gamestate.get_moves_from_tile_as_string(GameState::coordinate_translator_str(player_move_command_iter[0].strip_suffix('\n')?)));
+            } else if let [Some(pc)] = squares.as_slice() {
+                println!("Valid moves: {}", gamestate.get_moves_from_tile_as_string(*pc));
+            } else {
+                input.error(&format!("Unknown command: '{}'", line.trim()));
             }
-
         }
     }
 
     Some(0)
 
-}
\ No newline at end of file
+}
+
+/// It lists the pieces in the Crazyhouse pocket of the given color, such as "PPN"
+fn format_pocket(gamestate: &GameState, color: Color) -> String {
+    let mut pocket = String::new();
+    for (count, letter) in gamestate.pockets[GameState::pocket_owner(color)].iter().zip("PNBRQ".chars()) {
+        pocket.extend(std::iter::repeat_n(letter, *count as usize));
+    }
+    pocket
+}
+
+/// It prints the legal moves grouped by the piece that moves, such as "Knight g1: Nf3, Nh3"
+fn print_moves(moves: &[pgn::AnnotatedMove]) {
+    if moves.is_empty() {
+        println!("No legal moves");
+        return;
+    }
+    let mut start = 0;
+    while start < moves.len() {
+        let (piece, from) = (&moves[start].piece, &moves[start].from);
+        let group: Vec<String> = moves[start..].iter()
+            .take_while(|mv| &mv.piece == piece && &mv.from == from)
+            .map(|mv| if mv.annotations.is_empty() { mv.san.clone() } else { format!("{} ({})", mv.san, mv.annotations.join(", ")) })
+            .collect();
+        println!("{} {}: {}", piece, from, group.join(", "));
+        start += group.len();
+    }
+    println!("{} legal moves", moves.len());
+}
+
+/// It prints a warning if the move captures a piece but loses material in the following exchange
+/// on the target square.
+fn warn_losing_capture(gamestate: &GameState, pc: &str, tc: &str) {
+    let pc_usize = GameState::coordinate_translator_str(pc);
+    let tc_usize = GameState::coordinate_translator_str(tc);
+    if gamestate.board[tc_usize].is_some() {
+        let balance = gamestate.see(pc_usize, tc_usize);
+        if balance < 0 {
+            println!("Warning: the capture on {} loses material ({} centipawns)", tc, balance);
+        }
+    }
+}
+
+/// It plays a move from the opening book, or lets the engine search the current position and plays the
+/// best move it finds. Without a depth the engine thinks for as long as the clock allows, or searches
+/// 3 plies when there is no clock.
+fn engine_move(gamestate: &mut GameState, game: &mut Game, depth: Option<u32>, clock: &Option<Clock>, engine: &Engine, book: &Option<OpeningBook>) {
+    if let Some(mv) = book.as_ref().and_then(|book| book.choose_move(gamestate)) {
+        println!("Book move: {}", format_move(gamestate, mv));
+        gamestate.make_move_kind(mv);
+        game.record(mv);
+        return;
+    }
+    let result = match (depth, clock) {
+        (Some(depth), _) => engine.search(gamestate, depth),
+        (None, Some(clock)) => engine.search_timed(gamestate, clock.think_time(gamestate.current_player), 64),
+        (None, None) => engine.search(gamestate, 3)
+    };
+    match result.best_move {
+        Some((pc, tc)) => {
+            let pcs = GameState::coordinate_translator_usize(pc);
+            let tcs = GameState::coordinate_translator_usize(tc);
+            println!("Engine move: {} -> {} (score {}, {} nodes)", pcs, tcs, result.score, result.nodes);
+            match gamestate.do_valid_move(&pcs, &tcs) {
+                Ok(()) => game.record(MoveKind::BOARD(pc, tc)),
+                Err(message) => println!("{}", message)
+            }
+        },
+        None => println!("No legal moves!")
+    }
+}
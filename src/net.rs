@@ -0,0 +1,353 @@
+use crate::game::{format_move, parse_move, Game, GameRecord};
+use crate::gamestate::{GameState, MoveKind};
+use crate::piece::Color;
+use crate::render::BoardRenderer;
+use crate::variant::Outcome;
+use serde::{Serialize, Deserialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// The version of the network protocol, both ends have to speak the same one.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A message between the two ends of a network game, sent as one line of JSON.
+///
+/// * `HELLO`: The joining player introduces themselves.
+/// * `START`: The host sends the game to play and the color of the joining player.
+/// * `MOVE`: A move, with the number of moves played before it and the position after it in FEN.
+/// * `RESYNC`: The position after a received move did not match, the sender of the move is asked for the game.
+/// * `SYNC`: The whole game, replacing the receiver's copy.
+/// * `RESIGN`: The sender gives up.
+/// * `BYE`: The sender leaves without finishing the game.
+#[derive(PartialEq, Clone, Serialize, Deserialize, Debug)]
+pub enum NetMessage {
+    HELLO { name: String, version: u32 },
+    START { record: GameRecord, color: Color },
+    MOVE { ply: usize, mv: MoveKind, fen: String },
+    RESYNC,
+    SYNC { record: GameRecord },
+    RESIGN,
+    BYE
+}
+
+/// How a network game ended.
+///
+/// * `FINISHED`: The rules ended the game.
+/// * `RESIGNED`: The given color resigned.
+/// * `LEFT`: A player left, or the connection was lost.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum NetResult {
+    FINISHED(Outcome),
+    RESIGNED(Color),
+    LEFT
+}
+
+/// A connection to the other player.
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+/// It wraps a connected socket
+    pub fn new(stream: TcpStream) -> Result<Connection, String> {
+        let writer = stream.try_clone().map_err(|e| format!("Could not use the connection: {}", e))?;
+        Ok(Connection { reader: BufReader::new(stream), writer })
+    }
+
+/// It sends a message as one line of JSON
+    pub fn send(&mut self, message: &NetMessage) -> Result<(), String> {
+        let line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+        writeln!(self.writer, "{}", line).and_then(|_| self.writer.flush()).map_err(|e| format!("The connection was lost: {}", e))
+    }
+
+/// It waits for the next message
+///
+/// Returns:
+///
+/// The message, or an error when the connection was closed or the line is not a message.
+    pub fn receive(&mut self) -> Result<NetMessage, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err("The other player disconnected".to_string()),
+            Ok(_) => serde_json::from_str(line.trim()).map_err(|e| format!("Received an invalid message: {}", e)),
+            Err(e) => Err(format!("The connection was lost: {}", e))
+        }
+    }
+}
+
+/// The address `listen` binds to when only a port is given. Players on other machines can only join
+/// when the host listens on an address they can reach, such as 0.0.0.0.
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+
+/// It opens the port the other player joins on
+///
+/// Arguments:
+///
+/// * `address`: A port such as "7878", listened on at DEFAULT_BIND_ADDRESS, or an address and port
+///   such as "0.0.0.0:7878". Port 0 picks a free port.
+///
+/// Returns:
+///
+/// The listening socket, or a message if the address is invalid or taken.
+pub fn listen(address: &str) -> Result<TcpListener, String> {
+    let address = address.trim();
+    let address = match address.parse::<u16>() {
+        Ok(port) => format!("{}:{}", DEFAULT_BIND_ADDRESS, port),
+        Err(_) if address.contains(':') => address.to_string(),
+        Err(_) => return Err(format!("Invalid port or address '{}', expected such as 7878 or 0.0.0.0:7878", address))
+    };
+    let listener = TcpListener::bind(&address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+    match listener.local_addr() {
+        Ok(local) => println!("Waiting for a player on {}...", local),
+        Err(_) => println!("Waiting for a player on {}...", address)
+    }
+    Ok(listener)
+}
+
+/// It waits for the other player to join, then sends them the game. The host plays White.
+///
+/// Arguments:
+///
+/// * `listener`: The socket opened by `listen`.
+/// * `game`: The game to play, the joining player's name is stored as Black.
+///
+/// Returns:
+///
+/// The connection and the color of the host.
+pub fn host(listener: &TcpListener, game: &mut Game) -> Result<(Connection, Color), String> {
+    let (stream, address) = listener.accept().map_err(|e| format!("Could not accept a player: {}", e))?;
+    let mut connection = Connection::new(stream)?;
+    match connection.receive()? {
+        NetMessage::HELLO { name, version } if version == PROTOCOL_VERSION => {
+            println!("{} joined from {}", name, address);
+            game.black = name;
+        },
+        NetMessage::HELLO { version, .. } => return Err(format!("The other player speaks protocol {}, this program speaks {}", version, PROTOCOL_VERSION)),
+        message => return Err(format!("Expected a greeting, received {:?}", message))
+    }
+    let record = game.to_record(&None)?;
+    connection.send(&NetMessage::START { record, color: Color::BLACK })?;
+    Ok((connection, Color::WHITE))
+}
+
+/// It connects to a hosting player and receives the game to play
+///
+/// Arguments:
+///
+/// * `address`: The host and port, such as "192.168.1.20:7878".
+/// * `name`: The name sent to the host.
+///
+/// Returns:
+///
+/// The connection, the game with its current position and the color of the joining player.
+pub fn join(address: &str, name: &str) -> Result<(Connection, Game, GameState, Color), String> {
+    let stream = TcpStream::connect(address).map_err(|e| format!("Could not connect to {}: {}", address, e))?;
+    let mut connection = Connection::new(stream)?;
+    connection.send(&NetMessage::HELLO { name: name.to_string(), version: PROTOCOL_VERSION })?;
+    match connection.receive()? {
+        NetMessage::START { record, color } => {
+            let (game, gamestate, _) = Game::from_record(&record)?;
+            println!("Joined {} vs {}, playing {:?}", game.white, game.black, color);
+            Ok((connection, game, gamestate, color))
+        },
+        message => Err(format!("Expected the game, received {:?}", message))
+    }
+}
+
+/// It checks a move of the player in turn with the same rules as the local client
+pub fn is_valid_move(gamestate: &mut GameState, mv: MoveKind) -> bool {
+    match mv {
+        MoveKind::BOARD(pc, tc) if pc < 64 && tc < 64 => {
+            let pcs = GameState::coordinate_translator_usize(pc);
+            let tcs = GameState::coordinate_translator_usize(tc);
+            gamestate.board[pc].is_some_and(|piece| piece.color == gamestate.current_player)
+                && gamestate.move_validity_checker(&pcs, &tcs)
+                && gamestate.legal_moves().contains(&(pc, tc))
+        },
+        MoveKind::BOARD(_, _) => false,
+        MoveKind::PROMOTION(pc, tc, _) if pc < 64 && tc < 64 => gamestate.legal_move_kinds().contains(&mv),
+        MoveKind::PROMOTION(_, _, _) => false,
+        MoveKind::DROP(piecetype, tc) => gamestate.is_legal_drop(piecetype, tc)
+    }
+}
+
+/// It plays a game against the other end of the connection. On the local player's turn a move such as
+/// "e2e4", "E2 -> E4" or "P@e4" is read from the input, "resign" gives up and "quit" leaves.
+///
+/// Arguments:
+///
+/// * `connection`: The connection to the other player.
+/// * `color`: The color of the local player.
+/// * `gamestate`: The position, updated with every move.
+/// * `game`: The game record, updated with every move.
+/// * `input`: Where the local player's moves are read from.
+/// * `renderer`: Draws the board before every local move.
+///
+/// Returns:
+///
+/// How the game ended, or an error if the other player sent something that could not be read.
+pub fn play_network(connection: &mut Connection, color: Color, gamestate: &mut GameState, game: &mut Game, input: &mut dyn BufRead, renderer: &BoardRenderer) -> Result<NetResult, String> {
+    loop {
+        if let Some(outcome) = gamestate.outcome() {
+            renderer.print(gamestate, game.moves.last().copied());
+            match outcome {
+                Outcome::WIN(winner) => println!("{:?} wins!", winner),
+                Outcome::DRAW => println!("Draw!")
+            }
+            return Ok(NetResult::FINISHED(outcome));
+        }
+
+        if gamestate.current_player == color {
+            renderer.print(gamestate, game.moves.last().copied());
+            println!("Your move ({:?}): ", color);
+            let mut line = String::new();
+            if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                let _ = connection.send(&NetMessage::BYE);
+                return Ok(NetResult::LEFT);
+            }
+            let text = line.trim().to_lowercase().replace("->", "").replace(' ', "");
+            match text.as_str() {
+                "resign" => {
+                    connection.send(&NetMessage::RESIGN)?;
+                    println!("You resigned, {:?} wins", color.opposite());
+                    return Ok(NetResult::RESIGNED(color));
+                },
+                "quit" => {
+                    let _ = connection.send(&NetMessage::BYE);
+                    return Ok(NetResult::LEFT);
+                },
+                _ => {}
+            }
+            match parse_move(gamestate, &text) {
+                Some(mv) if is_valid_move(gamestate, mv) => {
+                    let ply = game.moves.len();
+                    gamestate.make_move_kind(mv);
+                    game.record(mv);
+                    connection.send(&NetMessage::MOVE { ply, mv, fen: gamestate.to_fen() })?;
+                },
+                _ => println!("Invalid move: '{}'", line.trim())
+            }
+            continue;
+        }
+
+        println!("Waiting for {:?}...", color.opposite());
+        let message = match connection.receive() {
+            Ok(message) => message,
+            Err(e) => {
+                println!("{}", e);
+                return Ok(NetResult::LEFT);
+            }
+        };
+        match message {
+            NetMessage::MOVE { ply, mv, fen } => {
+                if ply != game.moves.len() || !is_valid_move(gamestate, mv) {
+                    println!("Received a move that does not fit the game, asking for the whole game");
+                    resync(connection, gamestate, game)?;
+                    continue;
+                }
+                println!("{:?} played {}", gamestate.current_player, format_move(gamestate, mv));
+                gamestate.make_move_kind(mv);
+                game.record(mv);
+                if gamestate.to_fen() != fen {
+                    println!("The positions differ after the move, asking for the whole game");
+                    resync(connection, gamestate, game)?;
+                }
+            },
+            NetMessage::RESYNC => connection.send(&NetMessage::SYNC { record: game.to_record(&None)? })?,
+            NetMessage::RESIGN => {
+                println!("{:?} resigned, you win!", color.opposite());
+                return Ok(NetResult::RESIGNED(color.opposite()));
+            },
+            NetMessage::BYE => {
+                println!("{:?} left the game", color.opposite());
+                return Ok(NetResult::LEFT);
+            },
+            message => return Err(format!("Unexpected message during the game: {:?}", message))
+        }
+    }
+}
+
+/// It asks the other player for the whole game and replaces the local copy with it
+fn resync(connection: &mut Connection, gamestate: &mut GameState, game: &mut Game) -> Result<(), String> {
+    connection.send(&NetMessage::RESYNC)?;
+    loop {
+        match connection.receive()? {
+            NetMessage::SYNC { record } => {
+                let (synced, position, _) = Game::from_record(&record)?;
+                *game = synced;
+                *gamestate = position;
+                return Ok(());
+            },
+            NetMessage::RESYNC => connection.send(&NetMessage::SYNC { record: game.to_record(&None)? })?,
+            message => return Err(format!("Expected the game, received {:?}", message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamestate::GameState;
+    use std::thread;
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn listen_defaults_to_localhost() {
+        let listener = listen("0").unwrap();
+        assert!(listener.local_addr().unwrap().ip().is_loopback());
+        assert!(listen("not a port").is_err());
+    }
+
+    #[test]
+    fn host_and_join_play_through_moves_resyncs_and_resignation() {
+        let listener = listen("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host_thread = thread::spawn(move || {
+            let mut game = Game::new(GameState::new());
+            game.white = "Ann".to_string();
+            let (mut connection, color) = host(&listener, &mut game).unwrap();
+            assert_eq!(color, Color::WHITE);
+            let mut gamestate = GameState::new();
+            let mut input: &[u8] = b"e2e4\nresign\n";
+            let result = play_network(&mut connection, color, &mut gamestate, &mut game, &mut input, &BoardRenderer::new()).unwrap();
+            (result, game, gamestate)
+        });
+
+        let (mut connection, game, gamestate, color) = join(&address, "Bob").unwrap();
+        assert_eq!(color, Color::BLACK);
+        assert_eq!((game.white.as_str(), game.black.as_str()), ("Ann", "Bob"));
+        assert_eq!(gamestate.to_fen(), GameState::new().to_fen());
+
+        // The host's first move, then this end asks for the whole game
+        let e2e4 = MoveKind::BOARD(square("e2"), square("e4"));
+        let mut after_e2e4 = GameState::new();
+        after_e2e4.make_move_kind(e2e4);
+        assert_eq!(connection.receive().unwrap(), NetMessage::MOVE { ply: 0, mv: e2e4, fen: after_e2e4.to_fen() });
+        connection.send(&NetMessage::RESYNC).unwrap();
+        match connection.receive().unwrap() {
+            NetMessage::SYNC { record } => assert_eq!(record.moves, vec!["e2e4"]),
+            message => panic!("Expected the game, received {:?}", message)
+        }
+
+        // A move sent with the wrong position makes the host ask for the whole game
+        let e7e5 = MoveKind::BOARD(square("e7"), square("e5"));
+        connection.send(&NetMessage::MOVE { ply: 1, mv: e7e5, fen: after_e2e4.to_fen() }).unwrap();
+        assert_eq!(connection.receive().unwrap(), NetMessage::RESYNC);
+        let mut synced = Game::new(GameState::new());
+        synced.white = "Ann".to_string();
+        synced.black = "Bob".to_string();
+        synced.record(e2e4);
+        synced.record(e7e5);
+        connection.send(&NetMessage::SYNC { record: synced.to_record(&None).unwrap() }).unwrap();
+
+        assert_eq!(connection.receive().unwrap(), NetMessage::RESIGN);
+        let (result, game, gamestate) = host_thread.join().unwrap();
+        assert_eq!(result, NetResult::RESIGNED(Color::WHITE));
+        assert_eq!(game.moves, vec![e2e4, e7e5]);
+        assert_eq!(gamestate.to_fen(), synced.replay().unwrap().to_fen());
+    }
+}
@@ -0,0 +1,543 @@
+use crate::gamestate::{GameState, MoveKind};
+use crate::piece::{Color, PieceType};
+use crate::game::format_move;
+use serde::Serialize;
+
+/// A game read from a PGN file.
+///
+/// Properties:
+///
+/// * `tags`: The tag pairs of the game, such as ("White", "Carlsen, Magnus").
+/// * `moves`: The moves of the main line in Standard Algebraic Notation.
+/// * `result`: The result written after the moves, "*" if there was none.
+/// * `annotations`: Comments and variations written after the move of the same index.
+#[derive(Clone, Debug, Default)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub result: String,
+    pub annotations: Vec<String>,
+}
+
+impl PgnGame {
+/// It returns the value of the tag with the given name, if the game has it
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+/// It writes the game as PGN, the tags followed by the numbered moves and their annotations wrapped at
+/// 80 columns. The move numbers start from the FEN tag when the game has one.
+    pub fn to_pgn(&self) -> String {
+        let mut text = String::new();
+        for (name, value) in &self.tags {
+            text.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        text.push('\n');
+
+        let start = self.tag("FEN").and_then(|fen| GameState::from_fen(fen).ok());
+        let mut number = start.map_or(1, |start| start.fullmove_number);
+        let mut white = start.is_none_or(|start| start.current_player == Color::WHITE);
+        let mut tokens: Vec<String> = Vec::new();
+        for (i, san) in self.moves.iter().enumerate() {
+            // Black's move needs its number again after a comment or variation
+            let annotated = i > 0 && self.annotations.get(i - 1).is_some_and(|text| !text.is_empty());
+            if white {
+                tokens.push(format!("{}. {}", number, san));
+            } else if i == 0 || annotated {
+                tokens.push(format!("{}... {}", number, san));
+            } else {
+                tokens.push(san.clone());
+            }
+            if let Some(text) = self.annotations.get(i) {
+                tokens.extend(text.split_whitespace().map(|word| word.to_string()));
+            }
+            if !white {
+                number += 1;
+            }
+            white = !white;
+        }
+        tokens.push(if self.result.is_empty() { "*".to_string() } else { self.result.clone() });
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > 80 {
+                text.push_str(&line);
+                text.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        text.push_str(&line);
+        text.push('\n');
+        text
+    }
+}
+
+/// It reads every game of a PGN collection. Comments, variations, numeric annotation glyphs and move
+/// numbers are skipped, only the main line is kept.
+///
+/// Arguments:
+///
+/// * `text`: The contents of the PGN file.
+///
+/// Returns:
+///
+/// The games in the order they appear in the file.
+pub fn parse_pgn(text: &str) -> Vec<PgnGame> {
+    let mut games: Vec<PgnGame> = Vec::new();
+    let mut game = PgnGame::default();
+    let mut in_movetext = false;
+    let mut comment_depth = 0;
+    let mut variation_depth = 0;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if comment_depth == 0 && variation_depth == 0 && trimmed.starts_with('[') {
+            if in_movetext {
+                games.push(std::mem::take(&mut game));
+                in_movetext = false;
+            }
+            if let Some((name, value)) = parse_tag(trimmed) {
+                game.tags.push((name, value));
+            }
+            continue;
+        }
+        if comment_depth == 0 && trimmed.starts_with('%') {
+            continue;
+        }
+
+        let mut token = String::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if comment_depth > 0 {
+                if c == '}' {
+                    comment_depth = 0;
+                }
+                continue;
+            }
+            match c {
+                '{' => {
+                    finish_token(&mut token, &mut game, &mut in_movetext, variation_depth);
+                    comment_depth = 1;
+                },
+                ';' => {
+                    finish_token(&mut token, &mut game, &mut in_movetext, variation_depth);
+                    break;
+                },
+                '(' => {
+                    finish_token(&mut token, &mut game, &mut in_movetext, variation_depth);
+                    variation_depth += 1;
+                },
+                ')' => {
+                    finish_token(&mut token, &mut game, &mut in_movetext, variation_depth);
+                    variation_depth = (variation_depth - 1).max(0);
+                },
+                c if c.is_whitespace() => finish_token(&mut token, &mut game, &mut in_movetext, variation_depth),
+                '.' if token.chars().all(|d| d.is_ascii_digit()) => {
+                    // Move numbers such as "12." and "12..."
+                    while chars.peek() == Some(&'.') {
+                        chars.next();
+                    }
+                    token.clear();
+                    in_movetext = true;
+                },
+                c => token.push(c)
+            }
+        }
+        finish_token(&mut token, &mut game, &mut in_movetext, variation_depth);
+    }
+    if in_movetext || !game.tags.is_empty() {
+        games.push(game);
+    }
+    for game in games.iter_mut() {
+        if game.result.is_empty() {
+            game.result = game.tag("Result").unwrap_or("*").to_string();
+        }
+    }
+    games
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.trim_end().strip_suffix(']')?;
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+fn finish_token(token: &mut String, game: &mut PgnGame, in_movetext: &mut bool, variation_depth: i32) {
+    if token.is_empty() {
+        return;
+    }
+    *in_movetext = true;
+    if variation_depth == 0 {
+        match token.as_str() {
+            "1-0" | "0-1" | "1/2-1/2" | "*" => game.result = token.clone(),
+            t if t.starts_with('$') => {},
+            t if t.chars().all(|c| c.is_ascii_digit()) => {},
+            t => game.moves.push(t.to_string())
+        }
+    }
+    token.clear();
+}
+
+/// It finds the legal move of the current player written in Standard Algebraic Notation, such as
+/// `e4`, `Nbd7`, `exd5`, `R1e2+`, `e8=N` or `O-O-O`
+///
+/// Arguments:
+///
+/// * `gamestate`: The position the move is played in.
+/// * `san`: The move to find.
+///
+/// Returns:
+///
+/// The move, None if no legal move or more than one matches. A pawn reaching the last row without a
+/// promotion piece becomes a queen.
+pub fn parse_san(gamestate: &mut GameState, san: &str) -> Option<MoveKind> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal_moves = gamestate.legal_moves();
+
+    let castling = match san {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None
+    };
+    if let Some(short) = castling {
+        return legal_moves.into_iter().find(|&(pc, tc)| gamestate.is_castling(pc, tc) && (tc > pc) == short).map(|(pc, tc)| MoveKind::BOARD(pc, tc));
+    }
+
+    let (san, promotion) = match san.split_once('=') {
+        Some((san, letter)) if letter.chars().count() == 1 => (san, Some(MoveKind::promotion_piece(letter.chars().next()?)?)),
+        Some(_) => return None,
+        None => (san, None)
+    };
+    if san.len() < 2 {
+        return None;
+    }
+    let chars: Vec<char> = san.chars().collect();
+    let (piece_letter, rest) = match chars[0] {
+        'N' | 'B' | 'R' | 'Q' | 'K' => (chars[0], &chars[1..]),
+        _ => ('P', &chars[..])
+    };
+    if rest.len() < 2 {
+        return None;
+    }
+    let file = "abcdefgh".find(rest[rest.len() - 2])?;
+    let rank = "12345678".find(rest[rest.len() - 1])?;
+    let tc = rank * 8 + file;
+
+    let mut from_file: Option<usize> = None;
+    let mut from_rank: Option<usize> = None;
+    for &c in &rest[..rest.len() - 2] {
+        match c {
+            'a'..='h' => from_file = "abcdefgh".find(c),
+            '1'..='8' => from_rank = "12345678".find(c),
+            'x' | ':' | '-' => {},
+            _ => return None
+        }
+    }
+
+    let mut candidates = legal_moves.into_iter().filter(|&(pc, t)| {
+        if t != tc {
+            return false;
+        }
+        if from_file.is_some_and(|f| GameState::coord_x(pc) != f) || from_rank.is_some_and(|r| GameState::coord_y(pc) != r) {
+            return false;
+        }
+        match gamestate.board[pc] {
+            Some(piece) => piece_letter == match piece.piecetype {
+                PieceType::PAWN(_) => 'P',
+                PieceType::KNIGHT => 'N',
+                PieceType::BISHOP => 'B',
+                PieceType::ROOK(_) => 'R',
+                PieceType::QUEEN => 'Q',
+                PieceType::KING(_) => 'K',
+                PieceType::NONE => '?'
+            },
+            None => false
+        }
+    });
+    let (pc, tc) = candidates.next()?;
+    if candidates.next().is_some() {
+        return None;
+    }
+    match promotion {
+        _ if gamestate.is_promotion(pc, tc) => Some(MoveKind::promotion(pc, tc, promotion.unwrap_or(PieceType::QUEEN))),
+        Some(_) => None,
+        None => Some(MoveKind::BOARD(pc, tc))
+    }
+}
+
+/// It writes a legal move of the current player in Standard Algebraic Notation, such as `Nbd7`, `exd5`,
+/// `e8=Q+`, `e8=N` or `O-O`. Drops are written as `N@e4`.
+///
+/// Arguments:
+///
+/// * `gamestate`: The position the move is played in.
+/// * `mv`: The move to write.
+///
+/// Returns:
+///
+/// The move, with `+` when it gives check and `#` when it gives checkmate.
+pub fn format_san(gamestate: &mut GameState, mv: MoveKind) -> String {
+    let mut san = match mv {
+        MoveKind::DROP(piecetype, tc) => format!("{}@{}", piece_letter(piecetype).unwrap_or('P'), GameState::coordinate_translator_usize(tc).to_lowercase()),
+        MoveKind::BOARD(pc, tc) if gamestate.is_castling(pc, tc) => {
+            if tc > pc { "O-O".to_string() } else { "O-O-O".to_string() }
+        },
+        MoveKind::BOARD(pc, tc) | MoveKind::PROMOTION(pc, tc, _) => {
+            let piecetype = match gamestate.board[pc] {
+                Some(piece) => piece.piecetype,
+                None => return String::new()
+            };
+            let target = GameState::coordinate_translator_usize(tc).to_lowercase();
+            let capture = gamestate.is_capture(pc, tc);
+            match piece_letter(piecetype) {
+                None => {
+                    let from_file = (b'a' + GameState::coord_x(pc) as u8) as char;
+                    let promotion = match mv {
+                        MoveKind::PROMOTION(_, _, piecetype) => format!("={}", piece_letter(piecetype).unwrap_or('Q')),
+                        _ if gamestate.is_promotion(pc, tc) => "=Q".to_string(),
+                        _ => String::new()
+                    };
+                    if capture {
+                        format!("{}x{}{}", from_file, target, promotion)
+                    } else {
+                        format!("{}{}", target, promotion)
+                    }
+                },
+                Some(letter) => {
+                    // Name the file, the rank or both when another piece of the same type can reach the target
+                    let others: Vec<usize> = gamestate.legal_moves().into_iter()
+                        .filter(|&(other, t)| t == tc && other != pc && gamestate.board[other].is_some_and(|piece| piece_letter(piece.piecetype) == Some(letter)))
+                        .map(|(other, _)| other)
+                        .collect();
+                    let same_file = others.iter().any(|&other| GameState::coord_x(other) == GameState::coord_x(pc));
+                    let same_rank = others.iter().any(|&other| GameState::coord_y(other) == GameState::coord_y(pc));
+                    let square = GameState::coordinate_translator_usize(pc).to_lowercase();
+                    let disambiguation = if others.is_empty() {
+                        ""
+                    } else if !same_file {
+                        &square[..1]
+                    } else if !same_rank {
+                        &square[1..]
+                    } else {
+                        &square[..]
+                    };
+                    format!("{}{}{}{}", letter, disambiguation, if capture { "x" } else { "" }, target)
+                }
+            }
+        }
+    };
+    let mut next_state: GameState = *gamestate;
+    next_state.debug_flag = false;
+    next_state.make_move_kind(mv);
+    if next_state.checked_flag {
+        san.push(if next_state.legal_move_kinds().is_empty() { '#' } else { '+' });
+    }
+    san
+}
+
+/// A legal move with what it does, as listed by the `moves` command.
+///
+/// Properties:
+///
+/// * `piece`: The moving piece, such as "Knight".
+/// * `from`: The tile the piece stands on, "pocket" for Crazyhouse drops.
+/// * `san`: The move in Standard Algebraic Notation.
+/// * `uci`: The move in UCI notation, or as `P@e4` for drops.
+/// * `annotations`: What the move does: "capture", "check", "mate", "promotion", "castle" or "drop".
+#[derive(Clone, Serialize, Debug)]
+pub struct AnnotatedMove {
+    pub piece: String,
+    pub from: String,
+    pub san: String,
+    pub uci: String,
+    pub annotations: Vec<&'static str>,
+}
+
+/// It lists every legal move of the player in turn, ordered by piece from the king down to the pawns,
+/// then by the tile the piece stands on
+pub fn annotated_moves(gamestate: &mut GameState) -> Vec<AnnotatedMove> {
+    let mut moves: Vec<(usize, usize, AnnotatedMove)> = Vec::new();
+    for mv in gamestate.legal_move_kinds() {
+        let mut annotations: Vec<&'static str> = Vec::new();
+        let (piecetype, from) = match mv {
+            MoveKind::BOARD(pc, tc) | MoveKind::PROMOTION(pc, tc, _) => {
+                let piecetype = match gamestate.board[pc] {
+                    Some(piece) => piece.piecetype,
+                    None => continue
+                };
+                if gamestate.is_castling(pc, tc) {
+                    annotations.push("castle");
+                } else if gamestate.is_capture(pc, tc) {
+                    annotations.push("capture");
+                }
+                if gamestate.is_promotion(pc, tc) {
+                    annotations.push("promotion");
+                }
+                (piecetype, GameState::coordinate_translator_usize(pc).to_lowercase())
+            },
+            MoveKind::DROP(piecetype, _) => {
+                annotations.push("drop");
+                (piecetype, "pocket".to_string())
+            }
+        };
+        let uci = format_move(gamestate, mv);
+        let san = format_san(gamestate, mv);
+        if san.ends_with('#') {
+            annotations.push("mate");
+        } else if san.ends_with('+') {
+            annotations.push("check");
+        }
+        let rank = "KQRBNP".find(piece_letter(piecetype).unwrap_or('P')).unwrap_or(5);
+        let origin = match mv {
+            MoveKind::BOARD(pc, _) | MoveKind::PROMOTION(pc, _, _) => pc,
+            MoveKind::DROP(_, _) => 64
+        };
+        let piece = match piecetype {
+            PieceType::PAWN(_) => "Pawn",
+            PieceType::KNIGHT => "Knight",
+            PieceType::BISHOP => "Bishop",
+            PieceType::ROOK(_) => "Rook",
+            PieceType::QUEEN => "Queen",
+            PieceType::KING(_) => "King",
+            PieceType::NONE => "?"
+        };
+        moves.push((rank, origin, AnnotatedMove { piece: piece.to_string(), from, san, uci, annotations }));
+    }
+    moves.sort_by_key(|(rank, origin, _)| (*rank, *origin));
+    moves.into_iter().map(|(_, _, mv)| mv).collect()
+}
+
+/// The letter of a piece in SAN, None for pawns
+fn piece_letter(piecetype: PieceType) -> Option<char> {
+    match piecetype {
+        PieceType::KNIGHT => Some('N'),
+        PieceType::BISHOP => Some('B'),
+        PieceType::ROOK(_) => Some('R'),
+        PieceType::QUEEN => Some('Q'),
+        PieceType::KING(_) => Some('K'),
+        PieceType::PAWN(_) | PieceType::NONE => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    fn position(fen: &str) -> GameState {
+        let mut gamestate = GameState::from_fen(fen).unwrap();
+        gamestate.debug_flag = false;
+        gamestate
+    }
+
+    #[test]
+    fn moves_are_disambiguated_by_file_rank_or_both() {
+        let mut gamestate = position("4k3/8/8/8/8/4K3/8/R6R w - - 0 1");
+        assert_eq!(parse_san(&mut gamestate, "Rad1"), Some(MoveKind::BOARD(square("a1"), square("d1"))));
+        assert_eq!(parse_san(&mut gamestate, "Rhd1"), Some(MoveKind::BOARD(square("h1"), square("d1"))));
+        assert_eq!(parse_san(&mut gamestate, "Rd1"), None);
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("a1"), square("d1"))), "Rad1");
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("a1"), square("a2"))), "Ra2");
+
+        let mut gamestate = position("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1");
+        assert_eq!(parse_san(&mut gamestate, "R1a3"), Some(MoveKind::BOARD(square("a1"), square("a3"))));
+        assert_eq!(parse_san(&mut gamestate, "R5a3"), Some(MoveKind::BOARD(square("a5"), square("a3"))));
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("a5"), square("a3"))), "R5a3");
+
+        let mut gamestate = position("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1");
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("a1"), square("b2"))), "Qa1b2");
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("a3"), square("b2"))), "Q3b2");
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("c1"), square("b2"))), "Qcb2");
+        assert_eq!(parse_san(&mut gamestate, "Qa1b2"), Some(MoveKind::BOARD(square("a1"), square("b2"))));
+        assert_eq!(parse_san(&mut gamestate, "Qab2"), None);
+    }
+
+    #[test]
+    fn castling_is_written_as_o_o_and_o_o_o() {
+        let mut gamestate = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let short = parse_san(&mut gamestate, "O-O").unwrap();
+        let long = parse_san(&mut gamestate, "0-0-0").unwrap();
+        assert!(matches!(short, MoveKind::BOARD(pc, tc) if pc == square("e1") && tc > pc));
+        assert!(matches!(long, MoveKind::BOARD(pc, tc) if pc == square("e1") && tc < pc));
+        assert_eq!(format_san(&mut gamestate, short), "O-O");
+        assert_eq!(format_san(&mut gamestate, long), "O-O-O");
+
+        let mut gamestate = position("r3k2r/8/8/8/8/8/8/R3K2R w Qkq - 0 1");
+        assert_eq!(parse_san(&mut gamestate, "O-O"), None);
+    }
+
+    #[test]
+    fn promotions_name_the_new_piece() {
+        let mut gamestate = position("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(parse_san(&mut gamestate, "b8=Q+"), Some(MoveKind::BOARD(square("b7"), square("b8"))));
+        assert_eq!(parse_san(&mut gamestate, "b8"), Some(MoveKind::BOARD(square("b7"), square("b8"))));
+        assert_eq!(parse_san(&mut gamestate, "b8=N"), Some(MoveKind::PROMOTION(square("b7"), square("b8"), PieceType::KNIGHT)));
+        assert_eq!(parse_san(&mut gamestate, "bxa8=R"), Some(MoveKind::PROMOTION(square("b7"), square("a8"), PieceType::ROOK(true))));
+        assert_eq!(parse_san(&mut gamestate, "b8=K"), None);
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("b7"), square("b8"))), "b8=Q+");
+        assert_eq!(format_san(&mut gamestate, MoveKind::PROMOTION(square("b7"), square("b8"), PieceType::KNIGHT)), "b8=N");
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("b7"), square("a8"))), "bxa8=Q+");
+
+        // Only pawns reaching the last row promote
+        let mut gamestate = position("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(parse_san(&mut gamestate, "e4=Q"), None);
+    }
+
+    #[test]
+    fn check_and_mate_are_marked() {
+        let mut gamestate = position("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2");
+        let mate = parse_san(&mut gamestate, "Qh4#").unwrap();
+        assert_eq!(parse_san(&mut gamestate, "Qh4"), Some(mate));
+        assert_eq!(format_san(&mut gamestate, mate), "Qh4#");
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("f8"), square("b4"))), "Bb4");
+
+        let mut gamestate = position("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        assert_eq!(format_san(&mut gamestate, MoveKind::BOARD(square("a1"), square("a8"))), "Ra8+");
+    }
+
+    #[test]
+    fn every_legal_move_survives_a_round_trip() {
+        for fen in [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "r3k2r/1P4P1/8/3pP3/8/2N3N1/8/R3K2R w KQkq d6 0 1",
+            "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1",
+            "r3k3/8/8/8/8/8/8/4K2R[Nn] w - - 0 1",
+        ] {
+            let mut gamestate = position(fen);
+            for mv in gamestate.legal_move_kinds() {
+                let san = format_san(&mut gamestate, mv);
+                if let MoveKind::DROP(..) = mv {
+                    continue;
+                }
+                assert_eq!(parse_san(&mut gamestate, &san), Some(mv), "{} in {}", san, fen);
+            }
+        }
+    }
+
+    #[test]
+    fn annotated_moves_list_what_each_move_does() {
+        let mut gamestate = GameState::new();
+        let moves = annotated_moves(&mut gamestate);
+        assert_eq!(moves.len(), 20);
+        let json = serde_json::to_value(&moves).unwrap();
+        assert_eq!(json[0], serde_json::json!({ "piece": "Knight", "from": "b1", "san": "Na3", "uci": "b1a3", "annotations": [] }));
+        assert_eq!(json[19]["piece"], "Pawn");
+
+        let mut gamestate = position("r3k3/1P6/8/8/8/8/8/R3K2R w KQ - 0 1");
+        let moves = annotated_moves(&mut gamestate);
+        let find = |san: &str| moves.iter().find(|mv| mv.san == san).unwrap_or_else(|| panic!("{} is missing", san));
+        assert_eq!(find("O-O").annotations, vec!["castle"]);
+        assert_eq!(find("bxa8=Q+").annotations, vec!["capture", "promotion", "check"]);
+        assert_eq!(find("b8=N").annotations, vec!["promotion"]);
+        assert_eq!(find("Rxa8+").annotations, vec!["capture", "check"]);
+        assert_eq!(moves[0].piece, "King");
+    }
+}
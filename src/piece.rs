@@ -11,6 +11,25 @@ pub enum PieceType {
     NONE
 }
 
+impl PieceType {
+/// It returns the material value of the piece type in centipawns
+///
+/// Returns:
+///
+/// The value of the piece type, the king is given a value large enough to never be traded.
+    pub fn value(&self) -> i32 {
+        match self {
+            PieceType::PAWN(_) => 100,
+            PieceType::KNIGHT => 320,
+            PieceType::BISHOP => 330,
+            PieceType::ROOK(_) => 500,
+            PieceType::QUEEN => 900,
+            PieceType::KING(_) => 20000,
+            PieceType::NONE => 0
+        }
+    }
+}
+
 /// Creating an enum with the name Color.
 #[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug)]
 pub enum Color {
@@ -31,6 +50,15 @@ impl Color {
         }
     }
 
+    // Returns the color of the other player.
+    pub fn opposite(&self) -> Color {
+        match self {
+            Color::WHITE => Color::BLACK,
+            Color::BLACK => Color::WHITE,
+            Color::UNCOLORED => Color::UNCOLORED
+        }
+    }
+
     pub fn match_bool_as_color(b: bool) -> Color {
         match b {
             true => Color::WHITE,
@@ -0,0 +1,406 @@
+use crate::game::parse_move;
+use crate::gamestate::{GameState, MoveKind};
+use crate::pgn::{format_san, parse_san};
+use crate::render::BoardRenderer;
+use crate::variant::Outcome;
+use serde::{Serialize, Deserialize};
+use std::io::BufRead;
+
+/// The rating a new player starts with.
+pub const START_RATING: f64 = 1500.0;
+
+/// How far a rating moves after one puzzle, as in Elo.
+const K_FACTOR: f64 = 32.0;
+
+/// A tactics puzzle from a CSV file in the Lichess format.
+///
+/// Properties:
+///
+/// * `id`: The PuzzleId column.
+/// * `fen`: The position before the opponent's first move.
+/// * `moves`: The solution in UCI notation, the opponent's move first, then alternating.
+/// * `rating`: The difficulty of the puzzle.
+/// * `themes`: Tags such as "mateIn2" or "fork".
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Puzzle {
+    pub id: String,
+    pub fen: String,
+    pub moves: Vec<String>,
+    pub rating: u32,
+    pub themes: Vec<String>,
+}
+
+/// The player's puzzle rating and history, kept in a JSON file between sessions.
+///
+/// Properties:
+///
+/// * `rating`: The puzzle rating, updated like an Elo rating after every puzzle.
+/// * `played`: The number of puzzles tried.
+/// * `solved`: The number of puzzles solved.
+/// * `seen`: The ids of the puzzles already tried, they are not served again.
+#[derive(PartialEq, Clone, Serialize, Deserialize, Debug)]
+pub struct PuzzleProfile {
+    pub rating: f64,
+    pub played: u32,
+    pub solved: u32,
+    #[serde(default)]
+    pub seen: Vec<String>,
+}
+
+/// How an attempt at a puzzle ended.
+///
+/// * `SOLVED`: Every move was found, or the player found another mate.
+/// * `FAILED`: A wrong move was played or the player gave up.
+/// * `QUIT`: The player left before finishing, the puzzle does not count.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum PuzzleResult {
+    SOLVED,
+    FAILED,
+    QUIT
+}
+
+impl Default for PuzzleProfile {
+    fn default() -> Self {
+        PuzzleProfile { rating: START_RATING, played: 0, solved: 0, seen: Vec::new() }
+    }
+}
+
+impl PuzzleProfile {
+/// It reads a profile from a JSON file, a missing file gives a new profile
+    pub fn load(path: &str) -> Result<PuzzleProfile, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{} is not a puzzle profile: {}", path, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PuzzleProfile::default()),
+            Err(e) => Err(format!("Could not read {}: {}", path, e))
+        }
+    }
+
+/// It writes the profile to a JSON file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+
+/// It counts an attempt at a puzzle and moves the rating towards the puzzle's rating
+///
+/// Arguments:
+///
+/// * `puzzle`: The puzzle tried.
+/// * `solved`: True if it was solved.
+///
+/// Returns:
+///
+/// The change of the rating.
+    pub fn record(&mut self, puzzle: &Puzzle, solved: bool) -> f64 {
+        let expected = 1.0 / (1.0 + 10f64.powf((puzzle.rating as f64 - self.rating) / 400.0));
+        let change = K_FACTOR * (if solved { 1.0 } else { 0.0 } - expected);
+        self.rating += change;
+        self.played += 1;
+        if solved {
+            self.solved += 1;
+        }
+        if !self.seen.contains(&puzzle.id) {
+            self.seen.push(puzzle.id.clone());
+        }
+        change
+    }
+
+/// It picks the puzzle not tried yet whose rating is closest to the player's rating
+///
+/// Arguments:
+///
+/// * `puzzles`: The puzzles to choose from.
+/// * `theme`: Only puzzles with this theme are served, if given.
+    pub fn next_puzzle<'a>(&self, puzzles: &'a [Puzzle], theme: Option<&str>) -> Option<&'a Puzzle> {
+        puzzles.iter()
+            .filter(|puzzle| !self.seen.contains(&puzzle.id))
+            .filter(|puzzle| theme.is_none_or(|theme| puzzle.themes.iter().any(|t| t.eq_ignore_ascii_case(theme))))
+            .min_by_key(|puzzle| (puzzle.rating as f64 - self.rating).abs() as u64)
+    }
+}
+
+impl Puzzle {
+/// It sets up the puzzle's position and reads its moves
+///
+/// Returns:
+///
+/// The position before the opponent's first move and the moves of the solution, or a message if the
+/// position or one of the moves is not valid.
+    pub fn start(&self) -> Result<(GameState, Vec<MoveKind>), String> {
+        let start = GameState::from_fen(&self.fen)?;
+        let mut gamestate = start;
+        let mut moves: Vec<MoveKind> = Vec::new();
+        for text in &self.moves {
+            let mv = parse_move(&gamestate, text).ok_or(format!("Puzzle {}: cannot read the move '{}'", self.id, text))?;
+            if !gamestate.legal_move_kinds().contains(&mv) {
+                return Err(format!("Puzzle {}: the move '{}' is not legal", self.id, text));
+            }
+            gamestate.make_move_kind(mv);
+            moves.push(mv);
+        }
+        if moves.len() < 2 {
+            return Err(format!("Puzzle {}: the solution needs the opponent's move and a reply", self.id));
+        }
+        Ok((start, moves))
+    }
+}
+
+/// It reads one line of a Lichess puzzle CSV:
+/// `PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags`
+///
+/// Returns:
+///
+/// The puzzle, or a message describing what is wrong with the line.
+pub fn parse_puzzle_line(line: &str) -> Result<Puzzle, String> {
+    let fields: Vec<&str> = line.trim().split(',').collect();
+    if fields.len() < 4 {
+        return Err("A puzzle needs at least the PuzzleId, FEN, Moves and Rating columns".to_string());
+    }
+    let rating = fields[3].trim().parse().map_err(|_| format!("Invalid rating '{}'", fields[3]))?;
+    Ok(Puzzle {
+        id: fields[0].trim().to_string(),
+        fen: fields[1].trim().to_string(),
+        moves: fields[2].split_whitespace().map(|mv| mv.to_string()).collect(),
+        rating,
+        themes: fields.get(7).map_or(Vec::new(), |themes| themes.split_whitespace().map(|theme| theme.to_string()).collect()),
+    })
+}
+
+/// It reads every puzzle of a CSV file, skipping the header and empty lines
+///
+/// Returns:
+///
+/// The puzzles, or a message naming the first line that could not be read.
+pub fn parse_puzzles(text: &str) -> Result<Vec<Puzzle>, String> {
+    text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with("PuzzleId"))
+        .map(|(i, line)| parse_puzzle_line(line).map_err(|e| format!("Line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// It plays one puzzle: the opponent's moves are made automatically and the player's replies are read
+/// from the input, in UCI notation or SAN. A reply that differs from the solution still solves the
+/// puzzle if it gives checkmate. "hint" names the piece to move, "skip" gives up and "quit" leaves.
+///
+/// Arguments:
+///
+/// * `puzzle`: The puzzle to play.
+/// * `input`: Where the player's moves are read from.
+/// * `renderer`: Draws the board before every move of the player.
+///
+/// Returns:
+///
+/// How the attempt ended, or an error if the puzzle cannot be set up.
+pub fn play_puzzle(puzzle: &Puzzle, input: &mut dyn BufRead, renderer: &BoardRenderer) -> Result<PuzzleResult, String> {
+    let (mut gamestate, moves) = puzzle.start()?;
+    let mut last_move: Option<MoveKind> = None;
+    let mut ply = 0;
+    while ply < moves.len() {
+        if ply % 2 == 0 {
+            println!("Opponent plays {}", format_san(&mut gamestate, moves[ply]));
+            gamestate.make_move_kind(moves[ply]);
+            last_move = Some(moves[ply]);
+            ply += 1;
+            continue;
+        }
+
+        renderer.print(&gamestate, last_move);
+        println!("Your move ({:?}): ", gamestate.current_player);
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(PuzzleResult::QUIT);
+        }
+        let expected = moves[ply];
+        let text = line.trim();
+        match text {
+            "quit" => return Ok(PuzzleResult::QUIT),
+            "skip" => {
+                println!("The solution was {}", solution(gamestate, &moves[ply..]));
+                return Ok(PuzzleResult::FAILED);
+            },
+            "hint" => {
+                if let MoveKind::BOARD(pc, _) | MoveKind::PROMOTION(pc, _, _) = expected {
+                    println!("Move the piece on {}", GameState::coordinate_translator_usize(pc).to_lowercase());
+                }
+                continue;
+            },
+            _ => {}
+        }
+        let legal = gamestate.legal_move_kinds();
+        let mv = parse_move(&gamestate, &text.to_lowercase())
+            .or_else(|| parse_san(&mut gamestate, text))
+            .filter(|mv| legal.contains(mv));
+        let mv = match mv {
+            Some(mv) => mv,
+            None => {
+                println!("Not a legal move: '{}'", text);
+                continue;
+            }
+        };
+
+        let mover = gamestate.current_player;
+        let san = format_san(&mut gamestate, mv);
+        let mut next = gamestate;
+        next.make_move_kind(mv);
+        if mv != expected && next.outcome() == Some(Outcome::WIN(mover)) {
+            println!("{} is checkmate too!", san);
+            return Ok(PuzzleResult::SOLVED);
+        }
+        if mv != expected {
+            println!("{} is not the solution, it was {}", san, solution(gamestate, &moves[ply..]));
+            return Ok(PuzzleResult::FAILED);
+        }
+        println!("{} is correct", san);
+        gamestate = next;
+        last_move = Some(mv);
+        ply += 1;
+    }
+    Ok(PuzzleResult::SOLVED)
+}
+
+/// It writes the rest of a solution in SAN
+fn solution(mut gamestate: GameState, moves: &[MoveKind]) -> String {
+    let mut v: Vec<String> = Vec::new();
+    for &mv in moves {
+        v.push(format_san(&mut gamestate, mv));
+        gamestate.make_move_kind(mv);
+    }
+    v.join(" ")
+}
+
+/// It serves puzzles matching the player's rating until they run out or the player quits, saving the
+/// profile after every puzzle
+///
+/// Arguments:
+///
+/// * `puzzles`: The puzzles to serve.
+/// * `profile_path`: The JSON file holding the player's rating.
+/// * `theme`: Only puzzles with this theme are served, if given.
+/// * `input`: Where the player's moves are read from.
+/// * `renderer`: Draws the board.
+pub fn train(puzzles: &[Puzzle], profile_path: &str, theme: Option<&str>, input: &mut dyn BufRead, renderer: &BoardRenderer) -> Result<(), String> {
+    let mut profile = PuzzleProfile::load(profile_path)?;
+    println!("Puzzle rating {:.0}, {} of {} solved", profile.rating, profile.solved, profile.played);
+    loop {
+        let puzzle = match profile.next_puzzle(puzzles, theme) {
+            Some(puzzle) => puzzle,
+            None => {
+                println!("No puzzles left");
+                return Ok(());
+            }
+        };
+        println!("Puzzle {} rated {} ({})", puzzle.id, puzzle.rating, puzzle.themes.join(", "));
+        let result = match play_puzzle(puzzle, input, renderer) {
+            Ok(result) => result,
+            Err(e) => {
+                // A broken puzzle is skipped instead of ending the session
+                println!("{}", e);
+                profile.seen.push(puzzle.id.clone());
+                continue;
+            }
+        };
+        if result == PuzzleResult::QUIT {
+            return Ok(());
+        }
+        let change = profile.record(puzzle, result == PuzzleResult::SOLVED);
+        println!("{}! Puzzle rating {:.0} ({:+.0})", if result == PuzzleResult::SOLVED { "Solved" } else { "Failed" }, profile.rating, change);
+        profile.save(profile_path)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Black's knight move is answered by a back rank mate, Rd8 mates just as well as Re8
+    const BACK_RANK: &str = "00001,1n4k1/5ppp/8/8/8/8/8/3RR1K1 b - - 0 1,b8a6 e1e8,1200,75,90,100,mate mateIn1 backRankMate,,";
+
+    fn puzzle(id: &str, rating: u32, themes: &[&str]) -> Puzzle {
+        Puzzle {
+            id: id.to_string(),
+            fen: "1n4k1/5ppp/8/8/8/8/8/3RR1K1 b - - 0 1".to_string(),
+            moves: vec!["b8a6".to_string(), "e1e8".to_string()],
+            rating,
+            themes: themes.iter().map(|theme| theme.to_string()).collect(),
+        }
+    }
+
+    fn play(puzzle: &Puzzle, input: &str) -> PuzzleResult {
+        play_puzzle(puzzle, &mut Cursor::new(input.to_string()), &BoardRenderer::new()).unwrap()
+    }
+
+    #[test]
+    fn puzzle_lines_are_read_with_their_themes() {
+        let puzzle = parse_puzzle_line(BACK_RANK).unwrap();
+        assert_eq!(puzzle.id, "00001");
+        assert_eq!(puzzle.fen, "1n4k1/5ppp/8/8/8/8/8/3RR1K1 b - - 0 1");
+        assert_eq!(puzzle.moves, vec!["b8a6", "e1e8"]);
+        assert_eq!(puzzle.rating, 1200);
+        assert_eq!(puzzle.themes, vec!["mate", "mateIn1", "backRankMate"]);
+
+        // The themes column may be missing
+        assert!(parse_puzzle_line("00002,8/8/8/8/8/8/8/8 w - - 0 1,e2e4 e7e5,900").unwrap().themes.is_empty());
+        assert!(parse_puzzle_line("00003,fen,e2e4").is_err());
+        assert!(parse_puzzle_line("PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags").is_err());
+
+        let text = format!("PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags\n\n{}\n", BACK_RANK);
+        assert_eq!(parse_puzzles(&text).unwrap(), vec![puzzle]);
+        assert!(parse_puzzles("00004,fen,e2e4,high").unwrap_err().starts_with("Line 1: "));
+    }
+
+    #[test]
+    fn puzzles_with_illegal_moves_do_not_start() {
+        let mut broken = puzzle("00005", 1500, &[]);
+        assert!(broken.start().is_ok());
+        broken.moves = vec!["b8a6".to_string(), "a1a8".to_string()];
+        assert_eq!(broken.start().unwrap_err(), "Puzzle 00005: the move 'a1a8' is not legal");
+        broken.moves = vec!["b8a6".to_string(), "e1e9".to_string()];
+        assert!(broken.start().is_err());
+        broken.moves = vec!["b8a6".to_string()];
+        assert!(broken.start().is_err());
+        broken.moves = vec!["b8a6".to_string(), "e1e8".to_string()];
+        broken.fen = "1n4k1/5ppp/8 b - - 0 1".to_string();
+        assert!(broken.start().is_err());
+    }
+
+    #[test]
+    fn rating_moves_like_elo() {
+        let mut profile = PuzzleProfile::default();
+        assert_eq!(profile.record(&puzzle("a", 1500, &[]), true), 16.0);
+        assert_eq!(profile.rating, 1516.0);
+        assert_eq!(profile.record(&puzzle("a", 1516, &[]), false), -16.0);
+        assert_eq!(profile.rating, 1500.0);
+        // Solving a puzzle rated 400 points higher gains ten elevenths of the K factor
+        let change = profile.record(&puzzle("b", 1900, &[]), true);
+        assert!((change - 32.0 * 10.0 / 11.0).abs() < 1e-9);
+        assert_eq!((profile.played, profile.solved), (3, 2));
+        assert_eq!(profile.seen, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn next_puzzle_is_the_closest_unseen_one_with_the_theme() {
+        let puzzles = vec![puzzle("far", 2100, &["fork"]), puzzle("near", 1550, &["mateIn1"]), puzzle("fork", 1700, &["Fork", "short"])];
+        let mut profile = PuzzleProfile::default();
+        assert_eq!(profile.next_puzzle(&puzzles, None).unwrap().id, "near");
+        assert_eq!(profile.next_puzzle(&puzzles, Some("fork")).unwrap().id, "fork");
+        profile.seen.push("fork".to_string());
+        assert_eq!(profile.next_puzzle(&puzzles, Some("fork")).unwrap().id, "far");
+        profile.seen.push("far".to_string());
+        assert_eq!(profile.next_puzzle(&puzzles, Some("fork")), None);
+        assert_eq!(profile.next_puzzle(&puzzles, Some("pin")), None);
+    }
+
+    #[test]
+    fn another_mate_solves_the_puzzle() {
+        let back_rank = parse_puzzle_line(BACK_RANK).unwrap();
+        assert_eq!(play(&back_rank, "e1e8\n"), PuzzleResult::SOLVED);
+        assert_eq!(play(&back_rank, "Re8#\n"), PuzzleResult::SOLVED);
+        assert_eq!(play(&back_rank, "Rd8\n"), PuzzleResult::SOLVED);
+        assert_eq!(play(&back_rank, "hint\na1a8\nd1d8\n"), PuzzleResult::SOLVED);
+        assert_eq!(play(&back_rank, "d1d7\n"), PuzzleResult::FAILED);
+        assert_eq!(play(&back_rank, "skip\n"), PuzzleResult::FAILED);
+        assert_eq!(play(&back_rank, "quit\n"), PuzzleResult::QUIT);
+        assert_eq!(play(&back_rank, ""), PuzzleResult::QUIT);
+    }
+}
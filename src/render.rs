@@ -0,0 +1,277 @@
+use crate::gamestate::{GameState, MoveKind};
+use crate::piece::{ChessPiece, Color, PieceType};
+
+const WHITE: Color = Color::WHITE;
+const BLACK: Color = Color::BLACK;
+
+/// Which side of the board is drawn at the bottom.
+///
+/// * `WHITE`: White's first row at the bottom.
+/// * `BLACK`: Black's first row at the bottom.
+/// * `AUTO`: The player in turn at the bottom.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Orientation {
+    WHITE,
+    BLACK,
+    AUTO
+}
+
+/// How pieces are written.
+///
+/// * `ASCII`: FEN letters, upper case for White and lower case for Black.
+/// * `UNICODE`: Chess symbols, hollow for White and filled for Black.
+/// * `LETTERS`: The color and the piece, such as "wN" and "bQ".
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum PieceSet {
+    ASCII,
+    UNICODE,
+    LETTERS
+}
+
+/// The colors of the tiles, drawn with ANSI escape codes.
+///
+/// * `PLAIN`: No colors, highlighted tiles are marked with brackets instead.
+/// * `CLASSIC`: Brown tiles.
+/// * `BLUE`: Blue and grey tiles.
+/// * `GREEN`: Green and cream tiles.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Theme {
+    PLAIN,
+    CLASSIC,
+    BLUE,
+    GREEN
+}
+
+/// Why a tile stands out from the others.
+///
+/// * `NONE`: It does not.
+/// * `LASTMOVE`: The last move came from or went to it.
+/// * `CHECK`: A checked king stands on it.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Highlight {
+    NONE,
+    LASTMOVE,
+    CHECK
+}
+
+impl Orientation {
+/// It finds an orientation by its name, ignoring case
+    pub fn from_name(name: &str) -> Option<Orientation> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "white" => Some(Orientation::WHITE),
+            "black" => Some(Orientation::BLACK),
+            "auto" => Some(Orientation::AUTO),
+            _ => None
+        }
+    }
+}
+
+impl PieceSet {
+/// It finds a piece set by its name, ignoring case
+    pub fn from_name(name: &str) -> Option<PieceSet> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "ascii" => Some(PieceSet::ASCII),
+            "unicode" => Some(PieceSet::UNICODE),
+            "letters" => Some(PieceSet::LETTERS),
+            _ => None
+        }
+    }
+
+/// It writes a tile's content, every symbol of a set has the same width
+    fn symbol(&self, tile: Option<ChessPiece>) -> String {
+        let piece = match tile {
+            Some(piece) => piece,
+            None => return match self {
+                PieceSet::ASCII => ".".to_string(),
+                PieceSet::UNICODE => "·".to_string(),
+                PieceSet::LETTERS => "  ".to_string()
+            }
+        };
+        let letter = match piece.piecetype {
+            PieceType::PAWN(_) => 'P',
+            PieceType::KNIGHT => 'N',
+            PieceType::BISHOP => 'B',
+            PieceType::ROOK(_) => 'R',
+            PieceType::QUEEN => 'Q',
+            PieceType::KING(_) => 'K',
+            PieceType::NONE => '?'
+        };
+        match self {
+            PieceSet::ASCII if piece.color == WHITE => letter.to_string(),
+            PieceSet::ASCII => letter.to_ascii_lowercase().to_string(),
+            PieceSet::LETTERS => format!("{}{}", if piece.color == WHITE { 'w' } else { 'b' }, letter),
+            PieceSet::UNICODE => {
+                let white = "♙♘♗♖♕♔";
+                let black = "♟♞♝♜♛♚";
+                let index = "PNBRQK".find(letter).unwrap_or(0);
+                let glyphs = if piece.color == WHITE { white } else { black };
+                glyphs.chars().nth(index).unwrap_or('?').to_string()
+            }
+        }
+    }
+
+/// The number of columns a symbol of the set takes
+    fn width(&self) -> usize {
+        if *self == PieceSet::LETTERS { 2 } else { 1 }
+    }
+}
+
+impl Theme {
+/// It finds a theme by its name, ignoring case
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "plain" | "none" => Some(Theme::PLAIN),
+            "classic" => Some(Theme::CLASSIC),
+            "blue" => Some(Theme::BLUE),
+            "green" => Some(Theme::GREEN),
+            _ => None
+        }
+    }
+
+/// The 256-color codes of the light tiles, the dark tiles, the tiles of the last move and the checked
+/// king's tile
+    fn colors(&self) -> Option<[u8; 4]> {
+        match self {
+            Theme::PLAIN => None,
+            Theme::CLASSIC => Some([180, 137, 185, 160]),
+            Theme::BLUE => Some([152, 67, 186, 167]),
+            Theme::GREEN => Some([187, 65, 186, 167])
+        }
+    }
+
+/// It draws one tile, three or four columns wide
+    fn paint(&self, symbol: &str, color: Option<Color>, light: bool, highlight: Highlight) -> String {
+        match self.colors() {
+            None => match highlight {
+                Highlight::NONE => format!(" {} ", symbol),
+                Highlight::LASTMOVE => format!("[{}]", symbol),
+                Highlight::CHECK => format!("({})", symbol)
+            },
+            Some([light_tile, dark_tile, last_move, check]) => {
+                let background = match highlight {
+                    Highlight::CHECK => check,
+                    Highlight::LASTMOVE => last_move,
+                    Highlight::NONE if light => light_tile,
+                    Highlight::NONE => dark_tile
+                };
+                // The tile colors already show empty tiles, so they are left blank
+                let (foreground, symbol) = match color {
+                    Some(WHITE) => ("\x1b[1;38;5;231m", symbol.to_string()),
+                    Some(BLACK) => ("\x1b[1;38;5;16m", symbol.to_string()),
+                    _ => ("", " ".repeat(symbol.chars().count()))
+                };
+                format!("\x1b[48;5;{}m{} {} \x1b[0m", background, foreground, symbol)
+            }
+        }
+    }
+}
+
+/// Draws the board as text for the line-based client.
+///
+/// Properties:
+///
+/// * `orientation`: Which side is at the bottom.
+/// * `coordinates`: True to write the row numbers on the left and the file letters below.
+/// * `piece_set`: How pieces are written.
+/// * `theme`: The colors of the tiles.
+/// * `highlight_last_move`: True to mark the tiles the last move came from and went to.
+/// * `highlight_check`: True to mark the tile of a checked king.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct BoardRenderer {
+    pub orientation: Orientation,
+    pub coordinates: bool,
+    pub piece_set: PieceSet,
+    pub theme: Theme,
+    pub highlight_last_move: bool,
+    pub highlight_check: bool,
+}
+
+impl Default for BoardRenderer {
+    fn default() -> Self {
+        BoardRenderer::new()
+    }
+}
+
+impl BoardRenderer {
+/// It creates a renderer drawing Unicode pieces without colors from White's side, with coordinates and
+/// highlights
+    pub fn new() -> BoardRenderer {
+        BoardRenderer {
+            orientation: Orientation::WHITE,
+            coordinates: true,
+            piece_set: PieceSet::UNICODE,
+            theme: Theme::PLAIN,
+            highlight_last_move: true,
+            highlight_check: true,
+        }
+    }
+
+/// It tells whether Black's side is drawn at the bottom for the given position
+    pub fn is_flipped(&self, gamestate: &GameState) -> bool {
+        match self.orientation {
+            Orientation::WHITE => false,
+            Orientation::BLACK => true,
+            Orientation::AUTO => gamestate.current_player == BLACK
+        }
+    }
+
+/// It draws the board
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to draw.
+/// * `last_move`: The move that led to the position, if it should be highlighted.
+///
+/// Returns:
+///
+/// The board as lines of text, each ended by a newline.
+    pub fn render(&self, gamestate: &GameState, last_move: Option<MoveKind>) -> String {
+        let flipped = self.is_flipped(gamestate);
+        let rows: Vec<usize> = if flipped { (0..8).collect() } else { (0..8).rev().collect() };
+        let files: Vec<usize> = if flipped { (0..8).rev().collect() } else { (0..8).collect() };
+        let moved: Vec<usize> = match last_move {
+            Some(MoveKind::BOARD(pc, tc) | MoveKind::PROMOTION(pc, tc, _)) if self.highlight_last_move => vec![pc, tc],
+            Some(MoveKind::DROP(_, tc)) if self.highlight_last_move => vec![tc],
+            _ => Vec::new()
+        };
+        let checked = match (self.highlight_check && gamestate.checked_flag, gamestate.checked_player) {
+            (true, WHITE) => gamestate.wkc,
+            (true, BLACK) => gamestate.bkc,
+            _ => 64
+        };
+
+        let mut text = String::new();
+        for &row in &rows {
+            if self.coordinates {
+                text.push_str(&format!("{} ", row + 1));
+            }
+            for &file in &files {
+                let tile = row * 8 + file;
+                let highlight = if tile == checked {
+                    Highlight::CHECK
+                } else if moved.contains(&tile) {
+                    Highlight::LASTMOVE
+                } else {
+                    Highlight::NONE
+                };
+                let piece = gamestate.board[tile];
+                text.push_str(&self.theme.paint(&self.piece_set.symbol(piece), piece.map(|piece| piece.color), (row + file) % 2 == 1, highlight));
+            }
+            text.push('\n');
+        }
+        if self.coordinates {
+            text.push_str("  ");
+            for &file in &files {
+                let letter = (b'a' + file as u8) as char;
+                text.push_str(&format!(" {:<width$} ", letter, width = self.piece_set.width()));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+/// It prints the board, see render
+    pub fn print(&self, gamestate: &GameState, last_move: Option<MoveKind>) {
+        print!("{}", self.render(gamestate, last_move));
+    }
+}
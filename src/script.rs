@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read};
+
+/// Where the commands of a game are read from: the terminal, a script file given with `--script` or
+/// commands piped to the standard input. Scripts are echoed and their errors are reported with the
+/// line they come from.
+///
+/// Properties:
+///
+/// * `reader`: The commands.
+/// * `source`: The name used in error messages, the file name or "<stdin>".
+/// * `script`: False when a person types the commands.
+/// * `line`: The number of lines read so far, the line of the last command.
+/// * `errors`: The number of commands that failed.
+/// * `finished`: True once the input has ended.
+pub struct CommandInput {
+    reader: Box<dyn BufRead>,
+    pub source: String,
+    pub script: bool,
+    pub line: usize,
+    pub errors: usize,
+    pub finished: bool,
+}
+
+impl CommandInput {
+/// It reads commands from the standard input, as a script if it is not a terminal
+    pub fn stdin() -> CommandInput {
+        let script = !std::io::stdin().is_terminal();
+        CommandInput::new(Box::new(std::io::stdin().lock()), "<stdin>", script)
+    }
+
+/// It reads commands from a script file
+    pub fn open(path: &str) -> Result<CommandInput, String> {
+        let file = File::open(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Ok(CommandInput::new(Box::new(BufReader::new(file)), path, true))
+    }
+
+/// It reads commands from any reader
+///
+/// Arguments:
+///
+/// * `reader`: The commands.
+/// * `source`: The name used in error messages.
+/// * `script`: True if the commands should be echoed and errors reported with line numbers.
+    pub fn new(reader: Box<dyn BufRead>, source: &str, script: bool) -> CommandInput {
+        CommandInput { reader, source: source.to_string(), script, line: 0, errors: 0, finished: false }
+    }
+
+/// It reads the next command. Scripts are echoed so that their output reads like a session. Lines
+/// starting with '#' are comments that explain a script, they are echoed and skipped.
+///
+/// Returns:
+///
+/// The command ending in a single '\n' whatever the line ending of the input was, or None once the
+/// input has ended.
+    pub fn read_command(&mut self) -> Option<String> {
+        loop {
+            let mut line = String::new();
+            match self.read_line(&mut line) {
+                Ok(0) => {
+                    self.finished = true;
+                    return None;
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    self.finished = true;
+                    self.error(&format!("Could not read the command: {}", e));
+                    return None;
+                }
+            }
+            if !line.ends_with('\n') {
+                // The last line of a file may have no line break, it is still a line
+                self.line += 1;
+            }
+            let command = format!("{}\n", line.trim_end_matches(['\r', '\n']));
+            if command.starts_with('#') {
+                if self.script {
+                    print!("{}", command);
+                }
+                continue;
+            }
+            if self.script {
+                print!("> {}", command);
+            }
+            return Some(command);
+        }
+    }
+
+/// It reports a failed command, naming the script and line in scripts
+    pub fn error(&mut self, message: &str) {
+        self.errors += 1;
+        if self.script {
+            println!("{}:{}: {}", self.source, self.line, message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+impl Read for CommandInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// Lines are counted as they are consumed, so that they stay right when puzzles or network games
+/// read from the input too.
+impl BufRead for CommandInput {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Ok(buffer) = self.reader.fill_buf() {
+            self.line += buffer[..amount.min(buffer.len())].iter().filter(|&&b| b == b'\n').count();
+        }
+        self.reader.consume(amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn script(text: &str) -> CommandInput {
+        CommandInput::new(Box::new(Cursor::new(text.to_string())), "test.txt", true)
+    }
+
+    #[test]
+    fn commands_are_numbered_by_their_line() {
+        let mut input = script("e2e4\r\n# White opens\n\n  # indented is no comment\nbad move\n");
+        assert_eq!(input.read_command().as_deref(), Some("e2e4\n"));
+        assert_eq!(input.line, 1);
+        assert_eq!(input.read_command().as_deref(), Some("\n"));
+        assert_eq!(input.line, 3);
+        assert_eq!(input.read_command().as_deref(), Some("  # indented is no comment\n"));
+        assert_eq!(input.read_command().as_deref(), Some("bad move\n"));
+        assert_eq!(input.line, 5);
+        input.error("Invalid move!");
+        assert_eq!(input.errors, 1);
+        assert!(!input.finished);
+    }
+
+    #[test]
+    fn input_ends_after_the_last_line() {
+        let mut input = script("new\nquit");
+        assert_eq!(input.read_command().as_deref(), Some("new\n"));
+        // The last line counts even without a line break
+        assert_eq!(input.read_command().as_deref(), Some("quit\n"));
+        assert_eq!(input.line, 2);
+        assert!(!input.finished);
+        assert_eq!(input.read_command(), None);
+        assert!(input.finished);
+        assert_eq!(input.line, 2);
+
+        let mut input = script("# only a comment\n");
+        assert_eq!(input.read_command(), None);
+        assert_eq!(input.line, 1);
+        assert!(input.finished);
+    }
+
+    #[test]
+    fn lines_read_by_others_are_counted() {
+        let mut input = script("puzzle\ne2e4\nd2d4\nquit\n");
+        assert_eq!(input.read_command().as_deref(), Some("puzzle\n"));
+        let mut answer = String::new();
+        input.read_line(&mut answer).unwrap();
+        assert_eq!(answer, "e2e4\n");
+        let mut rest = String::new();
+        input.by_ref().take(5).read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "d2d4\n");
+        assert_eq!(input.line, 3);
+        assert_eq!(input.read_command().as_deref(), Some("quit\n"));
+        assert_eq!(input.line, 4);
+    }
+}
@@ -0,0 +1,584 @@
+use crate::gamestate::{GameState, MoveKind, UNDERPROMOTION_PIECES};
+use crate::piece::{Color, PieceType};
+use crate::syzygy::Tablebase;
+use crate::transposition::{position_hash, Bound, TableData, TranspositionTable};
+use crate::variant::{Outcome, VariantKind};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Score of a checkmate, mates found closer to the root score higher.
+pub const MATE_SCORE: i32 = 100_000;
+
+/// Score of a position the tablebase knows to be won, wins closer to zeroing score higher.
+pub const TABLEBASE_WIN: i32 = MATE_SCORE / 2;
+const INFINITY: i32 = 1_000_000;
+
+/// Margin added on top of the captured piece when delta pruning in the quiescence search.
+const DELTA_MARGIN: i32 = 200;
+
+/// Bonus for pawns per rank they have advanced, seen from White's side.
+const PAWN_RANK_BONUS: [i32; 8] = [0, 0, 5, 10, 20, 35, 60, 0];
+
+/// Bonus for knights and bishops per file/rank, rewarding the centre.
+const CENTER_BONUS: [i32; 8] = [-10, -5, 0, 5, 5, 0, -5, -10];
+
+/// The result of a search
+///
+/// Properties:
+///
+/// * `best_move`: The best move found as (piece coordinate, target coordinate), None without legal moves.
+/// * `promotion`: The piece a pawn becomes when the best move is a promotion, a queen otherwise.
+/// * `score`: The score of the position in centipawns from the side to move's perspective.
+/// * `nodes`: The number of positions visited, including the quiescence search.
+#[derive(Copy, Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<(usize, usize)>,
+    pub promotion: PieceType,
+    pub score: i32,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+/// It returns the best move with its promotion piece, None without legal moves
+    pub fn best_move_kind(&self) -> Option<MoveKind> {
+        self.best_move.map(|(pc, tc)| MoveKind::promotion(pc, tc, self.promotion))
+    }
+}
+
+/// It evaluates the position from the perspective of the current player, counting material and
+/// rewarding advanced pawns and centralized minor pieces
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to evaluate.
+///
+/// Returns:
+///
+/// The score in centipawns, positive when the current player is better.
+pub fn evaluate(gamestate: &GameState) -> i32 {
+    let mut score: i32 = 0;
+    for i in 0..64 {
+        if let Some(piece) = gamestate.board[i] {
+            let x = GameState::coord_x(i);
+            let y = GameState::coord_y(i);
+            let rank = match piece.color {
+                Color::BLACK => 7 - y,
+                _ => y
+            };
+            let mut value = piece.piecetype.value();
+            match piece.piecetype {
+                PieceType::PAWN(_) => value += PAWN_RANK_BONUS[rank],
+                PieceType::KNIGHT | PieceType::BISHOP => value += CENTER_BONUS[x] + CENTER_BONUS[y],
+                _ => {}
+            }
+            if piece.color == gamestate.current_player {
+                score += value;
+            } else {
+                score -= value;
+            }
+        }
+    }
+    score
+}
+
+/// Size of the transposition table of a new engine in megabytes.
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/// The bookkeeping of one search thread.
+///
+/// Properties:
+///
+/// * `nodes`: The number of positions visited by this thread.
+/// * `deadline`: The moment the search has to stop, None for a search without time limit.
+/// * `stop`: Raised when the search is over, shared by all threads of the search.
+/// * `stopped`: Set once the stop flag is seen, the scores of an unfinished iteration are thrown away.
+/// * `table`: The transposition table shared by all threads.
+struct SearchContext<'a> {
+    nodes: u64,
+    deadline: Option<Instant>,
+    stop: &'a AtomicBool,
+    stopped: bool,
+    table: &'a TranspositionTable,
+}
+
+impl SearchContext<'_> {
+    fn visit(&mut self) {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(256) {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    self.stop.store(true, Ordering::Relaxed);
+                }
+            }
+            if self.stop.load(Ordering::Relaxed) {
+                self.stopped = true;
+            }
+        }
+    }
+}
+
+/// The engine, searching with one or more threads (Lazy SMP). All threads search the same position
+/// and only share the transposition table, the helper threads fill it with positions the main thread
+/// then finds ready. With one thread the search is deterministic.
+///
+/// Properties:
+///
+/// * `threads`: The number of threads to search with.
+/// * `tablebase`: Endgame tablebases consulted at the root instead of searching, if any.
+/// * `table`: The transposition table, kept between searches.
+pub struct Engine {
+    pub threads: usize,
+    pub tablebase: Option<Tablebase>,
+    table: TranspositionTable,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+/// It creates a single threaded engine with a transposition table of `DEFAULT_HASH_MB` megabytes
+    pub fn new() -> Engine {
+        Engine {
+            threads: 1,
+            tablebase: None,
+            table: TranspositionTable::new(DEFAULT_HASH_MB),
+        }
+    }
+
+/// It replaces the transposition table with an empty one of the given size
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.table = TranspositionTable::new(megabytes);
+    }
+
+/// It forgets everything learned in earlier searches, for example when a new game starts
+    pub fn clear(&mut self) {
+        self.table.clear();
+    }
+
+/// It searches the position to a fixed depth with alpha-beta, resolving captures at the leaves with a
+/// quiescence search
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to search, it is not modified.
+/// * `depth`: The number of plies to search before dropping into the quiescence search.
+///
+/// Returns:
+///
+/// The best move and its score.
+    pub fn search(&self, gamestate: &GameState, depth: u32) -> SearchResult {
+        self.run(gamestate, depth.max(1), None)
+    }
+
+/// It searches the position with iterative deepening until the given time is used up, returning the
+/// result of the deepest iteration that was finished. The first iteration is always finished so that
+/// there is a move to play.
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to search, it is not modified.
+/// * `time`: The time the search may take.
+/// * `max_depth`: The depth at which to stop even when there is time left.
+///
+/// Returns:
+///
+/// The best move and its score.
+    pub fn search_timed(&self, gamestate: &GameState, time: Duration, max_depth: u32) -> SearchResult {
+        self.run(gamestate, max_depth.max(1), Some(time))
+    }
+
+/// It follows the best moves stored in the transposition table from the position, the line the
+/// engine expects after a search of the position
+///
+/// Arguments:
+///
+/// * `gamestate`: The position searched last.
+/// * `length`: The most moves to return.
+///
+/// Returns:
+///
+/// The expected moves, stopping early at a position the table does not know or that repeats.
+    pub fn principal_variation(&self, gamestate: &GameState, length: usize) -> Vec<(usize, usize)> {
+        let mut position: GameState = *gamestate;
+        position.debug_flag = false;
+        let mut seen: Vec<u64> = Vec::new();
+        let mut line: Vec<(usize, usize)> = Vec::new();
+        while line.len() < length {
+            let hash = position_hash(&position);
+            if seen.contains(&hash) {
+                break;
+            }
+            seen.push(hash);
+            let mv = match self.table.probe(hash).and_then(|entry| entry.best_move) {
+                Some(mv) if position.legal_moves().contains(&mv) => mv,
+                _ => break
+            };
+            position.make_move(mv.0, mv.1);
+            line.push(mv);
+        }
+        line
+    }
+
+    fn run(&self, gamestate: &GameState, max_depth: u32, time: Option<Duration>) -> SearchResult {
+        if let Some(result) = self.tablebase_move(gamestate) {
+            return result;
+        }
+        let started = Instant::now();
+        let stop = AtomicBool::new(false);
+        let helper_nodes = AtomicU64::new(0);
+
+        let mut result = std::thread::scope(|scope| {
+            for id in 1..self.threads.max(1) {
+                let (stop, helper_nodes) = (&stop, &helper_nodes);
+                scope.spawn(move || {
+                    let mut context = SearchContext { nodes: 0, deadline: None, stop, stopped: false, table: &self.table };
+                    // Half of the helpers start one ply deeper so the threads do not all walk in step
+                    for depth in (1 + id as u32 % 2)..max_depth + 1 {
+                        search_root(gamestate, depth, &mut context);
+                        if context.stopped {
+                            break;
+                        }
+                    }
+                    helper_nodes.fetch_add(context.nodes, Ordering::Relaxed);
+                });
+            }
+
+            let mut context = SearchContext { nodes: 0, deadline: None, stop: &stop, stopped: false, table: &self.table };
+            let mut result = search_root(gamestate, 1, &mut context);
+            context.deadline = time.map(|time| started + time);
+            for depth in 2..max_depth + 1 {
+                if let Some(time) = time {
+                    // An iteration takes several times as long as the one before, do not start one that cannot finish
+                    if started.elapsed() * 2 >= time {
+                        break;
+                    }
+                }
+                if result.best_move.is_none() {
+                    break;
+                }
+                let iteration = search_root(gamestate, depth, &mut context);
+                if context.stopped {
+                    break;
+                }
+                result = iteration;
+            }
+            stop.store(true, Ordering::Relaxed);
+            result.nodes = context.nodes;
+            result
+        });
+        result.nodes += helper_nodes.load(Ordering::Relaxed);
+        result
+    }
+
+    // With few enough pieces the tablebase knows the best move, there is nothing to search
+    fn tablebase_move(&self, gamestate: &GameState) -> Option<SearchResult> {
+        let tablebase = self.tablebase.as_ref()?;
+        // The tables only know the standard rules
+        if gamestate.variant != VariantKind::STANDARD {
+            return None;
+        }
+        let (best_move, dtz) = tablebase.best_move(gamestate).ok()??;
+        let score = match dtz.signum() {
+            1 => TABLEBASE_WIN - dtz,
+            -1 => -TABLEBASE_WIN - dtz,
+            _ => 0
+        };
+        Some(SearchResult { best_move: Some(best_move), promotion: PieceType::QUEEN, score, nodes: 0 })
+    }
+}
+
+fn search_root(gamestate: &GameState, depth: u32, context: &mut SearchContext) -> SearchResult {
+    let mut root: GameState = *gamestate;
+    root.debug_flag = false;
+    let hash = position_hash(&root);
+
+    let mut moves = ordered_moves(&mut root, false);
+    if moves.is_empty() {
+        let score = no_moves_score(&root, 0);
+        return SearchResult { best_move: None, promotion: PieceType::QUEEN, score, nodes: 1 };
+    }
+    if let Some(entry) = context.table.probe(hash) {
+        move_to_front(&mut moves, entry.best_move);
+    }
+
+    let mut alpha = -INFINITY;
+    let mut best_move = moves[0];
+    let mut best_promotion = PieceType::QUEEN;
+    // Underpromotions are only tried at the root, deeper in the tree a queen is as good
+    'moves: for (pc, tc) in moves {
+        let promotions: &[PieceType] = if root.is_promotion(pc, tc) { &UNDERPROMOTION_PIECES } else { &[] };
+        for &promotion in std::iter::once(&PieceType::QUEEN).chain(promotions) {
+            let mut child: GameState = root;
+            child.make_move_promoting(pc, tc, promotion);
+            let score = -negamax(&mut child, depth - 1, -INFINITY, -alpha, 1, context);
+            if context.stopped {
+                break 'moves;
+            }
+            if score > alpha {
+                alpha = score;
+                best_move = (pc, tc);
+                best_promotion = promotion;
+            }
+        }
+    }
+    if !context.stopped {
+        context.table.store(hash, TableData { best_move: Some(best_move), depth, score: alpha, bound: Bound::EXACT });
+    }
+
+    SearchResult { best_move: Some(best_move), promotion: best_promotion, score: alpha, nodes: context.nodes }
+}
+
+fn negamax(gamestate: &mut GameState, depth: u32, mut alpha: i32, beta: i32, ply: i32, context: &mut SearchContext) -> i32 {
+    if depth == 0 {
+        return quiescence(gamestate, alpha, beta, ply, context);
+    }
+    context.visit();
+    if context.stopped {
+        return 0;
+    }
+    if let Some(score) = variant_score(gamestate, ply) {
+        return score;
+    }
+
+    let hash = position_hash(gamestate);
+    let mut table_move = None;
+    if let Some(entry) = context.table.probe(hash) {
+        table_move = entry.best_move;
+        if entry.depth >= depth {
+            let score = score_from_table(entry.score, ply);
+            match entry.bound {
+                Bound::EXACT => return score,
+                Bound::LOWER if score >= beta => return score,
+                Bound::UPPER if score <= alpha => return score,
+                _ => {}
+            }
+        }
+    }
+
+    let mut moves = ordered_moves(gamestate, false);
+    if moves.is_empty() {
+        return no_moves_score(gamestate, ply);
+    }
+    move_to_front(&mut moves, table_move);
+
+    let original_alpha = alpha;
+    let mut best_move = moves[0];
+    for (pc, tc) in moves {
+        let mut child: GameState = *gamestate;
+        child.make_move(pc, tc);
+        let score = -negamax(&mut child, depth - 1, -beta, -alpha, ply + 1, context);
+        if context.stopped {
+            return 0;
+        }
+        if score >= beta {
+            context.table.store(hash, TableData { best_move: Some((pc, tc)), depth, score: score_to_table(beta, ply), bound: Bound::LOWER });
+            return beta;
+        }
+        if score > alpha {
+            alpha = score;
+            best_move = (pc, tc);
+        }
+    }
+    let bound = if alpha > original_alpha { Bound::EXACT } else { Bound::UPPER };
+    context.table.store(hash, TableData { best_move: Some(best_move), depth, score: score_to_table(alpha, ply), bound });
+    alpha
+}
+
+/// It scores a position in which a rule of the variant, such as a king on the hill, has already
+/// decided the game
+fn variant_score(gamestate: &GameState, ply: i32) -> Option<i32> {
+    let winner = gamestate.variant.rules().winner(gamestate)?;
+    Some(if winner == gamestate.current_player { MATE_SCORE - ply } else { -MATE_SCORE + ply })
+}
+
+/// It scores a position in which the current player has no moves left, checkmate and stalemate in
+/// standard chess
+fn no_moves_score(gamestate: &GameState, ply: i32) -> i32 {
+    match gamestate.variant.rules().no_moves_outcome(gamestate) {
+        Outcome::WIN(winner) if winner == gamestate.current_player => MATE_SCORE - ply,
+        Outcome::WIN(_) => -MATE_SCORE + ply,
+        Outcome::DRAW => 0
+    }
+}
+
+/// Mate scores are stored relative to the position rather than the root, so that they stay correct
+/// when the position is reached at another distance from the root
+fn score_to_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_SCORE - 1000 {
+        score + ply
+    } else if score < -MATE_SCORE + 1000 {
+        score - ply
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_SCORE - 1000 {
+        score - ply
+    } else if score < -MATE_SCORE + 1000 {
+        score + ply
+    } else {
+        score
+    }
+}
+
+fn move_to_front(moves: &mut [(usize, usize)], first: Option<(usize, usize)>) {
+    if let Some(first) = first {
+        if let Some(i) = moves.iter().position(|&m| m == first) {
+            moves[..i + 1].rotate_right(1);
+        }
+    }
+}
+
+/// It searches captures only until the position is quiet, so that the static evaluation is never taken
+/// in the middle of an exchange. The current player may always "stand pat" and decline to capture.
+/// Captures that cannot raise the score above alpha even when winning the piece outright are skipped
+/// (delta pruning), as are captures that lose material according to the static exchange evaluation.
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to search.
+/// * `alpha`: The lower bound of the search window.
+/// * `beta`: The upper bound of the search window.
+/// * `context`: The bookkeeping of the search.
+///
+/// Returns:
+///
+/// The score of the position from the current player's perspective.
+fn quiescence(gamestate: &mut GameState, mut alpha: i32, beta: i32, ply: i32, context: &mut SearchContext) -> i32 {
+    context.visit();
+    if context.stopped {
+        return 0;
+    }
+    if let Some(score) = variant_score(gamestate, ply) {
+        return score;
+    }
+
+    let stand_pat = evaluate(gamestate);
+    if stand_pat >= beta {
+        return beta;
+    }
+    if stand_pat + PieceType::QUEEN.value() + DELTA_MARGIN < alpha {
+        return alpha;
+    }
+    if stand_pat > alpha {
+        alpha = stand_pat;
+    }
+
+    for (pc, tc) in ordered_moves(gamestate, true) {
+        // An empty target tile is an en passant capture, which takes a pawn the exchange evaluation
+        // does not see
+        let captured_value = match gamestate.board[tc] {
+            Some(target) => target.piecetype.value(),
+            None => PieceType::PAWN(true).value()
+        };
+        if stand_pat + captured_value + DELTA_MARGIN < alpha {
+            continue;
+        }
+        if gamestate.board[tc].is_some() && gamestate.see(pc, tc) < 0 {
+            continue;
+        }
+        let mut child: GameState = *gamestate;
+        child.make_move(pc, tc);
+        let score = -quiescence(&mut child, -beta, -alpha, ply + 1, context);
+        if score >= beta {
+            return beta;
+        }
+        if score > alpha {
+            alpha = score;
+        }
+    }
+    alpha
+}
+
+/// It returns the legal moves of the current player with captures first, ordered by most valuable
+/// victim and least valuable attacker
+fn ordered_moves(gamestate: &mut GameState, captures_only: bool) -> Vec<(usize, usize)> {
+    let mut moves = gamestate.legal_moves();
+    if captures_only {
+        moves.retain(|&(pc, tc)| gamestate.is_capture(pc, tc));
+    }
+    moves.sort_by_key(|&(pc, tc)| {
+        match (gamestate.board[pc], gamestate.board[tc]) {
+            (Some(attacker), Some(victim)) if attacker.color != victim.color => attacker.piecetype.value() / 100 - victim.piecetype.value() * 10,
+            _ => 0
+        }
+    });
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIDDLEGAME: &str = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn single_thread_search_is_deterministic() {
+        let gamestate = GameState::from_fen(MIDDLEGAME).unwrap();
+        let first = Engine::new().search(&gamestate, 4);
+        let second = Engine::new().search(&gamestate, 4);
+        assert_eq!(first.best_move, second.best_move);
+        assert_eq!(first.score, second.score);
+        assert_eq!(first.nodes, second.nodes);
+
+        // Clearing the table makes a used engine search exactly like a new one
+        let mut engine = Engine::new();
+        engine.search(&gamestate, 3);
+        engine.clear();
+        let third = engine.search(&gamestate, 4);
+        assert_eq!((third.best_move, third.score, third.nodes), (first.best_move, first.score, first.nodes));
+    }
+
+    #[test]
+    fn multi_threaded_search_finds_mate() {
+        let gamestate = GameState::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4").unwrap();
+        let mut engine = Engine::new();
+        engine.threads = 4;
+        let result = engine.search(&gamestate, 3);
+        assert_eq!(result.best_move, Some((square("h5"), square("f7"))));
+        assert_eq!(result.score, MATE_SCORE - 1);
+        assert!(result.nodes > 0);
+    }
+
+    #[test]
+    fn multi_threaded_search_plays_a_legal_move() {
+        let mut gamestate = GameState::from_fen(MIDDLEGAME).unwrap();
+        let mut engine = Engine::new();
+        engine.threads = 3;
+        let result = engine.search(&gamestate, 4);
+        let best_move = result.best_move.expect("the position has legal moves");
+        assert!(gamestate.legal_moves().contains(&best_move));
+        assert!(result.score.abs() < MATE_SCORE - 1000);
+    }
+
+    #[test]
+    fn quiescence_searches_en_passant_captures() {
+        let mut gamestate = GameState::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+        let mut captured = gamestate;
+        captured.make_move(square("e5"), square("d6"));
+        let stop = AtomicBool::new(false);
+        let table = TranspositionTable::new(1);
+        let mut context = SearchContext { nodes: 0, deadline: None, stop: &stop, stopped: false, table: &table };
+        let score = quiescence(&mut gamestate, -INFINITY, INFINITY, 0, &mut context);
+        assert!(score > evaluate(&gamestate));
+        assert_eq!(score, -evaluate(&captured));
+    }
+
+    #[test]
+    fn quiescence_scores_variant_wins_by_distance_from_the_root() {
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/8/3K4/8/8/8 b - - 0 1", VariantKind::KINGOFTHEHILL).unwrap();
+        let stop = AtomicBool::new(false);
+        let table = TranspositionTable::new(1);
+        let mut context = SearchContext { nodes: 0, deadline: None, stop: &stop, stopped: false, table: &table };
+        assert_eq!(quiescence(&mut gamestate, -INFINITY, INFINITY, 3, &mut context), -MATE_SCORE + 3);
+    }
+}
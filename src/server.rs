@@ -0,0 +1,968 @@
+use crate::clock::{Clock, TimeControl};
+use crate::game::{format_move, parse_move, Game};
+use crate::gamestate::{GameState, MoveKind};
+use crate::net::is_valid_move;
+use crate::pgn::format_san;
+use crate::piece::Color;
+use crate::variant::{Outcome, VariantKind};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response};
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request as HandshakeRequest, Response as HandshakeResponse};
+use tungstenite::{Message, WebSocket};
+
+/// The longest a client may wait for new events in one request.
+const MAX_POLL: Duration = Duration::from_secs(60);
+
+/// Something that happened in a game, numbered in the order it happened.
+///
+/// * `JOINED`: A player took a seat.
+/// * `MOVED`: A move was played, in UCI and SAN, with the position after it.
+/// * `DRAWOFFERED`: The given color offers a draw, until the other player moves or accepts.
+/// * `FINISHED`: The game ended with the given result, such as "1-0", and the reason.
+#[derive(PartialEq, Clone, Serialize, Deserialize, Debug)]
+pub enum GameEvent {
+    JOINED { color: Color, name: String },
+    MOVED { ply: usize, mv: MoveKind, uci: String, san: String, fen: String },
+    DRAWOFFERED { color: Color },
+    FINISHED { result: String, reason: String }
+}
+
+/// A message the server sends over a WebSocket.
+///
+/// * `EVENT`: An event of the game with its number, the same numbers `/events?since=N` uses.
+/// * `CLOCK`: The time left for both players, sent every second while a clock runs.
+/// * `REPLY`: The answer to an action sent over the socket, with the same status and body as over HTTP.
+#[derive(PartialEq, Clone, Serialize, Deserialize, Debug)]
+pub enum ServerMessage {
+    EVENT { index: usize, event: GameEvent },
+    CLOCK { white_ms: u64, black_ms: u64, running: Color },
+    REPLY { status: u16, body: serde_json::Value }
+}
+
+/// A message a client sends over a WebSocket, the actions take the same fields as the HTTP requests.
+///
+/// * `MOVE`: Play a move, `{"token", "move"}`.
+/// * `RESIGN`: Give up, `{"token"}`.
+/// * `DRAW`: Offer a draw, or accept the one offered, `{"token"}`.
+/// * `RESUME`: Send the events again from after the given ply, after the client lost track.
+#[derive(Deserialize, Debug)]
+pub enum ClientMessage {
+    MOVE(PlayerRequest),
+    RESIGN(PlayerRequest),
+    DRAW(PlayerRequest),
+    RESUME { ply: usize }
+}
+
+/// A game hosted by the server.
+///
+/// Properties:
+///
+/// * `id`: The number the game is addressed by.
+/// * `game`: The moves played so far and the player names.
+/// * `gamestate`: The current position.
+/// * `tokens`: The secret of the white and the black player, None while the seat is free.
+/// * `events`: Everything that happened in the game, for clients catching up.
+/// * `result`: The result once the game is over.
+/// * `spectators`: The number of clients streaming the game.
+/// * `clock`: The clock, if the game is played with one. It starts once both players are seated.
+/// * `draw_offer`: The player offering a draw, if any.
+pub struct Room {
+    pub id: u64,
+    pub game: Game,
+    pub gamestate: GameState,
+    pub tokens: [Option<String>; 2],
+    pub events: Vec<GameEvent>,
+    pub result: Option<String>,
+    pub spectators: usize,
+    pub clock: Option<Clock>,
+    pub draw_offer: Option<Color>,
+}
+
+/// The games of the server, shared by the threads answering requests. Every change to a game is
+/// announced on `changed` so waiting clients wake up.
+struct Lobby {
+    rooms: Mutex<BTreeMap<u64, Room>>,
+    changed: Condvar,
+    pgn_path: Option<String>,
+}
+
+/// A game server answering HTTP requests with JSON.
+///
+/// | Request | Body | Answer |
+/// | --- | --- | --- |
+/// | `GET /games` | | Every game with its players and status |
+/// | `POST /games` | `{"name", "color"?, "variant"?, "fen"?, "time"?}` | `{"id", "token", "color"}` |
+/// | `POST /games/{id}/join` | `{"name"}` | `{"id", "token", "color"}` |
+/// | `GET /games/{id}` | | The position, moves, players and result |
+/// | `POST /games/{id}/moves` | `{"token", "move"}` | The game after the move |
+/// | `POST /games/{id}/resign` | `{"token"}` | The game after resigning |
+/// | `POST /games/{id}/draw` | `{"token"}` | The game after offering or accepting a draw |
+/// | `GET /games/{id}/events?since=N&timeout=S` | | The events from number N, waiting up to S seconds for one |
+/// | `GET /games/{id}/stream` | | One JSON event per line as they happen, until the game ends |
+/// | `GET /games/{id}/pgn` | | The game as PGN |
+///
+/// Anyone may read a game, so spectators only need its id. Finished games are appended to the PGN
+/// file given when the server was created. Browsers can follow a game live over a WebSocket, see
+/// `listen_websocket`.
+pub struct GameServer {
+    server: tiny_http::Server,
+    lobby: Arc<Lobby>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CreateRequest {
+    name: String,
+    color: Option<String>,
+    variant: Option<String>,
+    fen: Option<String>,
+    time: Option<String>,
+}
+
+/// The fields of the requests of a player: the name when joining, the token for actions in the game
+/// and the move when moving.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct PlayerRequest {
+    name: String,
+    token: String,
+    #[serde(rename = "move")]
+    mv: String,
+}
+
+/// An answer to a request: the status code and the JSON body.
+type Reply = (u16, serde_json::Value);
+
+fn error(status: u16, message: &str) -> Reply {
+    (status, json!({ "error": message }))
+}
+
+impl GameServer {
+/// It binds the server to an address without answering requests yet
+///
+/// Arguments:
+///
+/// * `address`: The address to listen on, such as "127.0.0.1:8080". Port 0 picks a free port.
+/// * `pgn_path`: The file finished games are appended to, None to keep them only in memory.
+    pub fn bind(address: &str, pgn_path: Option<String>) -> Result<GameServer, String> {
+        let server = tiny_http::Server::http(address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+        let lobby = Arc::new(Lobby { rooms: Mutex::new(BTreeMap::new()), changed: Condvar::new(), pgn_path });
+        Ok(GameServer { server, lobby })
+    }
+
+/// The port the server listens on
+    pub fn port(&self) -> u16 {
+        self.server.server_addr().to_ip().map_or(0, |address| address.port())
+    }
+
+/// It answers requests until the process ends, each request in its own thread so clients waiting for
+/// events do not hold up the others
+    pub fn run(&self) {
+        for request in self.server.incoming_requests() {
+            let lobby = Arc::clone(&self.lobby);
+            std::thread::spawn(move || lobby.handle(request));
+        }
+    }
+
+/// It accepts WebSocket connections on a second address, each in its own thread. A client connects to
+/// `/games/{id}?ply=N` and receives every event after ply N as an EVENT message, then the new events
+/// as they happen and a CLOCK message every second while the clock runs. Players can send their
+/// actions over the same socket, see ClientMessage.
+///
+/// Arguments:
+///
+/// * `address`: The address to listen on, port 0 picks a free port.
+///
+/// Returns:
+///
+/// The port the WebSocket listener was bound to.
+    pub fn listen_websocket(&self, address: &str) -> Result<u16, String> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let lobby = Arc::clone(&self.lobby);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let lobby = Arc::clone(&lobby);
+                std::thread::spawn(move || lobby.websocket(stream));
+            }
+        });
+        Ok(port)
+    }
+}
+
+impl Lobby {
+    fn handle(&self, mut request: Request) {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        let method = request.method().clone();
+        let mut body = String::new();
+        if method == Method::Post && request.as_reader().read_to_string(&mut body).is_err() {
+            let _ = respond(request, error(400, "The body is not text"));
+            return;
+        }
+
+        let id = segments.get(1).and_then(|id| id.parse::<u64>().ok());
+        let reply = match (&method, segments.as_slice(), id) {
+            (Method::Get, ["games"], _) => self.list(),
+            (Method::Post, ["games"], _) => self.create(&body),
+            (Method::Get, ["games", _], Some(id)) => self.with_room(id, |room| (200, room.summary())),
+            (Method::Post, ["games", _, "join"], Some(id)) => self.join(id, &body),
+            (Method::Post, ["games", _, action @ ("moves" | "resign" | "draw")], Some(id)) => match serde_json::from_str::<PlayerRequest>(&body) {
+                Ok(request) => self.act(id, action, &request),
+                Err(e) => error(400, &format!("Invalid request: {}", e))
+            },
+            (Method::Get, ["games", _, "events"], Some(id)) => self.events(id, query),
+            (Method::Get, ["games", _, "stream"], Some(id)) => {
+                self.stream(id, request);
+                return;
+            },
+            (Method::Get, ["games", _, "pgn"], Some(id)) => {
+                let pgn = self.rooms.lock().unwrap().get(&id).map(|room| room.pgn());
+                let response = match pgn {
+                    Some(pgn) => Response::from_string(pgn).with_header(header("Content-Type", "application/x-chess-pgn")),
+                    None => Response::from_string("No such game").with_status_code(404)
+                };
+                let _ = request.respond(response);
+                return;
+            },
+            (_, ["games", ..], _) => error(404, "No such game or action"),
+            _ => error(404, "Unknown path")
+        };
+        let _ = respond(request, reply);
+    }
+
+    fn with_room<F: FnOnce(&mut Room) -> Reply>(&self, id: u64, action: F) -> Reply {
+        match self.rooms.lock().unwrap().get_mut(&id) {
+            Some(room) => {
+                room.check_time(&self.pgn_path);
+                action(room)
+            },
+            None => error(404, "No such game")
+        }
+    }
+
+    fn list(&self) -> Reply {
+        let rooms = self.rooms.lock().unwrap();
+        let games: Vec<serde_json::Value> = rooms.values().map(|room| json!({
+            "id": room.id,
+            "white": room.seat_name(Color::WHITE),
+            "black": room.seat_name(Color::BLACK),
+            "variant": room.gamestate.variant.rules().name(),
+            "moves": room.game.moves.len(),
+            "spectators": room.spectators,
+            "status": room.status(),
+        })).collect();
+        (200, json!({ "games": games }))
+    }
+
+    fn create(&self, body: &str) -> Reply {
+        let request: CreateRequest = match serde_json::from_str(if body.trim().is_empty() { "{}" } else { body }) {
+            Ok(request) => request,
+            Err(e) => return error(400, &format!("Invalid request: {}", e))
+        };
+        let variant = match request.variant.as_deref().map(VariantKind::from_name) {
+            Some(None) => return error(400, "Unknown variant"),
+            Some(Some(variant)) => variant,
+            None => VariantKind::STANDARD
+        };
+        let gamestate = match &request.fen {
+            Some(fen) => match GameState::from_fen_variant(fen, variant) {
+                Ok(gamestate) => gamestate,
+                Err(e) => return error(400, &format!("Invalid FEN: {}", e))
+            },
+            None => variant.start_position()
+        };
+        let clock = match request.time.as_deref().map(TimeControl::parse) {
+            Some(Ok(time_control)) => Some(Clock::new(time_control)),
+            Some(Err(e)) => return error(400, &format!("Invalid time control: {}", e)),
+            None => None
+        };
+        let color = match request.color.as_deref() {
+            Some("black") => Color::BLACK,
+            Some("white") | None => Color::WHITE,
+            Some(_) => return error(400, "The color must be white or black")
+        };
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let id = rooms.keys().next_back().map_or(1, |last| last + 1);
+        let mut room = Room {
+            id,
+            game: Game::new(gamestate),
+            gamestate,
+            tokens: [None, None],
+            events: Vec::new(),
+            result: None,
+            spectators: 0,
+            clock,
+            draw_offer: None,
+        };
+        let token = room.seat(color, &request.name);
+        rooms.insert(id, room);
+        self.changed.notify_all();
+        (201, json!({ "id": id, "token": token, "color": color }))
+    }
+
+    fn join(&self, id: u64, body: &str) -> Reply {
+        let request: PlayerRequest = serde_json::from_str(body).unwrap_or_default();
+        let reply = self.with_room(id, |room| {
+            let color = match room.tokens {
+                [None, _] => Color::WHITE,
+                [_, None] => Color::BLACK,
+                _ => return error(409, "The game is full, watch it instead")
+            };
+            let token = room.seat(color, &request.name);
+            (200, json!({ "id": id, "token": token, "color": color }))
+        });
+        self.changed.notify_all();
+        reply
+    }
+
+/// It carries out an action of a player: "moves", "resign" or "draw"
+    fn act(&self, id: u64, action: &str, request: &PlayerRequest) -> Reply {
+        let reply = match action {
+            "moves" => self.play(id, request),
+            "resign" => self.resign(id, request),
+            _ => self.draw(id, request)
+        };
+        self.changed.notify_all();
+        reply
+    }
+
+    fn play(&self, id: u64, request: &PlayerRequest) -> Reply {
+        self.with_room(id, |room| {
+            if room.result.is_some() {
+                return error(409, "The game is over");
+            }
+            match room.color_of(&request.token) {
+                None => return error(403, "Unknown player token"),
+                Some(color) if color != room.gamestate.current_player => return error(409, "It is not your turn"),
+                Some(_) => {}
+            }
+            if room.tokens.iter().any(|token| token.is_none()) {
+                return error(409, "Waiting for the other player");
+            }
+            let mv = match parse_move(&room.gamestate, &request.mv) {
+                Some(mv) if is_valid_move(&mut room.gamestate, mv) => mv,
+                _ => return error(400, &format!("Illegal move '{}'", request.mv))
+            };
+            let san = format_san(&mut room.gamestate, mv);
+            let uci = format_move(&room.gamestate, mv);
+            room.gamestate.make_move_kind(mv);
+            room.game.record(mv);
+            room.draw_offer = None;
+            room.events.push(GameEvent::MOVED { ply: room.game.moves.len(), mv, uci, san, fen: room.gamestate.to_fen() });
+            if let Some(clock) = room.clock.as_mut() {
+                let flagged = clock.press();
+                if flagged != Color::UNCOLORED {
+                    room.flag(flagged, &self.pgn_path);
+                    return (200, room.summary());
+                }
+            }
+            let reason = if room.gamestate.legal_move_kinds().is_empty() {
+                if room.gamestate.checked_flag { "checkmate" } else { "no moves left" }
+            } else {
+                room.gamestate.variant.rules().name()
+            };
+            match room.gamestate.outcome() {
+                Some(Outcome::WIN(Color::WHITE)) => room.finish("1-0", reason, &self.pgn_path),
+                Some(Outcome::WIN(_)) => room.finish("0-1", reason, &self.pgn_path),
+                Some(Outcome::DRAW) => room.finish("1/2-1/2", reason, &self.pgn_path),
+                None => {}
+            }
+            (200, room.summary())
+        })
+    }
+
+    fn resign(&self, id: u64, request: &PlayerRequest) -> Reply {
+        self.with_room(id, |room| {
+            if room.result.is_some() {
+                return error(409, "The game is over");
+            }
+            match room.color_of(&request.token) {
+                Some(Color::WHITE) => room.finish("0-1", "White resigned", &self.pgn_path),
+                Some(_) => room.finish("1-0", "Black resigned", &self.pgn_path),
+                None => return error(403, "Unknown player token")
+            }
+            (200, room.summary())
+        })
+    }
+
+/// It offers a draw, or agrees to one when the other player offered it
+    fn draw(&self, id: u64, request: &PlayerRequest) -> Reply {
+        self.with_room(id, |room| {
+            if room.result.is_some() {
+                return error(409, "The game is over");
+            }
+            let color = match room.color_of(&request.token) {
+                Some(color) => color,
+                None => return error(403, "Unknown player token")
+            };
+            if room.draw_offer == Some(color.opposite()) {
+                room.finish("1/2-1/2", "draw agreed", &self.pgn_path);
+            } else if room.draw_offer.is_none() {
+                room.draw_offer = Some(color);
+                room.events.push(GameEvent::DRAWOFFERED { color });
+            }
+            (200, room.summary())
+        })
+    }
+
+/// It answers with the events from number `since` on, waiting up to `timeout` seconds for one when
+/// there are none yet
+    fn events(&self, id: u64, query: &str) -> Reply {
+        let parameter = |name: &str| query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('=')?.parse::<u64>().ok());
+        let since = parameter("since").unwrap_or(0) as usize;
+        let timeout = Duration::from_secs(parameter("timeout").unwrap_or(30)).min(MAX_POLL);
+        let deadline = Instant::now() + timeout;
+        let mut rooms = self.rooms.lock().unwrap();
+        loop {
+            let room = match rooms.get_mut(&id) {
+                Some(room) => room,
+                None => return error(404, "No such game")
+            };
+            room.check_time(&self.pgn_path);
+            let now = Instant::now();
+            if room.events.len() > since || room.result.is_some() || now >= deadline {
+                let events = room.events.get(since..).unwrap_or(&[]);
+                return (200, json!({ "events": events, "next": since + events.len() }));
+            }
+            rooms = self.changed.wait_timeout(rooms, deadline - now).unwrap().0;
+        }
+    }
+
+/// It keeps the connection open and writes every event as one line of JSON until the game ends or the
+/// client goes away
+    fn stream(&self, id: u64, request: Request) {
+        if !self.rooms.lock().unwrap().contains_key(&id) {
+            let _ = respond(request, error(404, "No such game"));
+            return;
+        }
+        let mut writer = request.into_writer();
+        if write!(writer, "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n").and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(&id) {
+            room.spectators += 1;
+        }
+        let mut sent = 0;
+        let mut rooms = self.rooms.lock().unwrap();
+        while let Some(room) = rooms.get(&id) {
+            let lines: Vec<String> = room.events[sent..].iter().filter_map(|event| serde_json::to_string(event).ok()).collect();
+            sent = room.events.len();
+            let finished = room.result.is_some();
+            drop(rooms);
+            let written = lines.iter().try_for_each(|line| writeln!(writer, "{}", line)).and_then(|_| writer.flush());
+            if written.is_err() || finished {
+                break;
+            }
+            rooms = self.rooms.lock().unwrap();
+            // Waking up now and then notices clients that went away while nothing happened
+            if rooms.get(&id).is_some_and(|room| room.events.len() == sent) {
+                rooms = self.changed.wait_timeout(rooms, Duration::from_secs(15)).unwrap().0;
+            }
+        }
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(&id) {
+            room.spectators = room.spectators.saturating_sub(1);
+        }
+    }
+}
+
+impl Lobby {
+/// It follows a game for one WebSocket client until the game ends or the client leaves
+    fn websocket(&self, stream: TcpStream) {
+        let mut path = String::new();
+        let accepted = tungstenite::accept_hdr(stream, RequestPath(&mut path));
+        let mut socket = match accepted {
+            Ok(socket) => socket,
+            Err(_) => return
+        };
+        let (path, query) = path.split_once('?').unwrap_or((&path, ""));
+        let id = path.trim_start_matches("/games/").trim_end_matches("/ws").parse::<u64>().ok();
+        let ply = query.split('&').find_map(|pair| pair.strip_prefix("ply=")?.parse::<usize>().ok()).unwrap_or(0);
+        let mut sent = match id.and_then(|id| self.rooms.lock().unwrap().get(&id).map(|room| room.events_after_ply(ply))) {
+            Some(sent) => sent,
+            None => {
+                let _ = socket.send(Message::text(json!({ "error": "No such game" }).to_string()));
+                let _ = socket.close(None);
+                return;
+            }
+        };
+        let id = id.unwrap_or_default();
+        // Short reads let the loop push events and clock ticks while waiting for the client
+        let _ = socket.get_ref().set_read_timeout(Some(Duration::from_millis(100)));
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(&id) {
+            room.spectators += 1;
+        }
+        let mut last_tick = Instant::now() - Duration::from_secs(1);
+        loop {
+            let (messages, finished) = {
+                let mut rooms = self.rooms.lock().unwrap();
+                let room = match rooms.get_mut(&id) {
+                    Some(room) => room,
+                    None => break
+                };
+                room.check_time(&self.pgn_path);
+                let mut messages: Vec<ServerMessage> = room.events[sent.min(room.events.len())..].iter().enumerate()
+                    .map(|(i, event)| ServerMessage::EVENT { index: sent + i, event: event.clone() })
+                    .collect();
+                sent = room.events.len();
+                if last_tick.elapsed() >= Duration::from_secs(1) && room.clock.as_ref().is_some_and(|clock| clock.running != Color::UNCOLORED) {
+                    messages.extend(room.clock_message());
+                    last_tick = Instant::now();
+                }
+                (messages, room.result.is_some())
+            };
+            if !send_all(&mut socket, &messages) {
+                break;
+            }
+            if finished {
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                break;
+            }
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    let reply = match serde_json::from_str::<ClientMessage>(&text) {
+                        Ok(ClientMessage::MOVE(request)) => self.act(id, "moves", &request),
+                        Ok(ClientMessage::RESIGN(request)) => self.act(id, "resign", &request),
+                        Ok(ClientMessage::DRAW(request)) => self.act(id, "draw", &request),
+                        Ok(ClientMessage::RESUME { ply }) => {
+                            sent = self.rooms.lock().unwrap().get(&id).map_or(0, |room| room.events_after_ply(ply));
+                            continue;
+                        },
+                        Err(e) => error(400, &format!("Invalid message: {}", e))
+                    };
+                    if !send_all(&mut socket, &[ServerMessage::REPLY { status: reply.0, body: reply.1 }]) {
+                        break;
+                    }
+                },
+                Ok(Message::Close(_)) => {
+                    // Sends the answer to the closing handshake that tungstenite queued
+                    let _ = socket.flush();
+                    break;
+                },
+                Ok(_) => {},
+                Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
+                Err(_) => break
+            }
+        }
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(&id) {
+            room.spectators = room.spectators.saturating_sub(1);
+        }
+    }
+}
+
+/// Remembers the path a WebSocket client asked for during the handshake.
+struct RequestPath<'a>(&'a mut String);
+
+impl Callback for RequestPath<'_> {
+    fn on_request(self, request: &HandshakeRequest, response: HandshakeResponse) -> Result<HandshakeResponse, ErrorResponse> {
+        *self.0 = request.uri().to_string();
+        Ok(response)
+    }
+}
+
+/// It sends messages over a WebSocket as JSON text, returning false when the client is gone
+fn send_all(socket: &mut WebSocket<TcpStream>, messages: &[ServerMessage]) -> bool {
+    messages.iter().all(|message| {
+        let text = serde_json::to_string(message).unwrap_or_default();
+        socket.send(Message::text(text)).is_ok()
+    })
+}
+
+impl Room {
+/// It gives a seat to a player and returns the secret they send with their moves
+    fn seat(&mut self, color: Color, name: &str) -> String {
+        let token = format!("{:016x}{:016x}", RandomState::new().build_hasher().finish(), RandomState::new().build_hasher().finish());
+        let name = if name.trim().is_empty() { format!("{:?}", color) } else { name.trim().to_string() };
+        if color == Color::WHITE {
+            self.game.white = name.clone();
+        } else {
+            self.game.black = name.clone();
+        }
+        self.tokens[GameState::pocket_owner(color)] = Some(token.clone());
+        self.events.push(GameEvent::JOINED { color, name });
+        if self.tokens.iter().all(|token| token.is_some()) {
+            if let Some(clock) = self.clock.as_mut() {
+                clock.start(self.gamestate.current_player);
+            }
+        }
+        token
+    }
+
+    fn color_of(&self, token: &str) -> Option<Color> {
+        [Color::WHITE, Color::BLACK].into_iter().find(|&color| self.tokens[GameState::pocket_owner(color)].as_deref() == Some(token))
+    }
+
+    fn seat_name(&self, color: Color) -> Option<String> {
+        let name = if color == Color::WHITE { &self.game.white } else { &self.game.black };
+        self.tokens[GameState::pocket_owner(color)].as_ref().map(|_| name.clone())
+    }
+
+    fn status(&self) -> &'static str {
+        if self.result.is_some() {
+            "finished"
+        } else if self.tokens.iter().any(|token| token.is_none()) {
+            "waiting"
+        } else {
+            "playing"
+        }
+    }
+
+/// It ends the game when the player in turn has run out of time
+    fn check_time(&mut self, pgn_path: &Option<String>) {
+        let flagged = match self.clock.as_mut() {
+            Some(clock) if self.result.is_none() => clock.check_flag(),
+            _ => return
+        };
+        if flagged != Color::UNCOLORED {
+            self.flag(flagged, pgn_path);
+        }
+    }
+
+/// It ends the game lost on time by the given player
+    fn flag(&mut self, flagged: Color, pgn_path: &Option<String>) {
+        let result = if flagged == Color::WHITE { "0-1" } else { "1-0" };
+        self.finish(result, &format!("{:?} ran out of time", flagged), pgn_path);
+    }
+
+/// The remaining time of both players for clients, None without a clock
+    fn clock_message(&self) -> Option<ServerMessage> {
+        self.clock.as_ref().map(|clock| ServerMessage::CLOCK {
+            white_ms: clock.remaining(Color::WHITE).as_millis() as u64,
+            black_ms: clock.remaining(Color::BLACK).as_millis() as u64,
+            running: clock.running,
+        })
+    }
+
+/// The number of the first event after the given ply, so a client that has seen the game up to that
+/// ply can pick up from there. A ply the client can not have seen starts it from the beginning.
+    fn events_after_ply(&self, ply: usize) -> usize {
+        if ply == 0 || ply > self.game.moves.len() {
+            return 0;
+        }
+        self.events.iter().position(|event| matches!(event, GameEvent::MOVED { ply: moved, .. } if *moved == ply)).map_or(0, |index| index + 1)
+    }
+
+/// It ends the game and appends it to the PGN file of the server
+    fn finish(&mut self, result: &str, reason: &str, pgn_path: &Option<String>) {
+        self.result = Some(result.to_string());
+        self.draw_offer = None;
+        if let Some(clock) = self.clock.as_mut() {
+            clock.stop();
+        }
+        self.events.push(GameEvent::FINISHED { result: result.to_string(), reason: reason.to_string() });
+        if let Some(path) = pgn_path {
+            let written = std::fs::OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| writeln!(file, "{}", self.pgn()));
+            if let Err(e) = written {
+                println!("Could not write game {} to {}: {}", self.id, path, e);
+            }
+        }
+    }
+
+    fn pgn(&self) -> String {
+        let mut game = self.game.clone();
+        game.metadata.entry("Site".to_string()).or_insert("Game server".to_string());
+        game.metadata.entry("Round".to_string()).or_insert(self.id.to_string());
+        game.to_pgn(self.result.as_deref().unwrap_or("*")).to_pgn()
+    }
+
+    fn summary(&self) -> serde_json::Value {
+        let mut gamestate = self.game.start;
+        let moves: Vec<String> = self.game.moves.iter().map(|&mv| {
+            let text = format_move(&gamestate, mv);
+            gamestate.make_move_kind(mv);
+            text
+        }).collect();
+        json!({
+            "id": self.id,
+            "white": self.seat_name(Color::WHITE),
+            "black": self.seat_name(Color::BLACK),
+            "variant": self.gamestate.variant.rules().name(),
+            "fen": self.gamestate.to_fen(),
+            "turn": self.gamestate.current_player,
+            "check": self.gamestate.checked_flag,
+            "moves": moves,
+            "status": self.status(),
+            "result": self.result,
+            "spectators": self.spectators,
+            "draw_offer": self.draw_offer,
+            "clock": self.clock.as_ref().map(|clock| json!({
+                "white_ms": clock.remaining(Color::WHITE).as_millis() as u64,
+                "black_ms": clock.remaining(Color::BLACK).as_millis() as u64,
+                "running": clock.running,
+            })),
+        })
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Header names and values are ASCII")
+}
+
+fn respond(request: Request, (status, body): Reply) -> std::io::Result<()> {
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+        .with_header(header("Access-Control-Allow-Origin", "*"));
+    request.respond(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// A server on a free port answering requests in the background, appending to its own PGN file
+    fn start_server(name: &str) -> (u16, String) {
+        let (server, pgn_path) = bind_server(name);
+        let port = server.port();
+        std::thread::spawn(move || server.run());
+        (port, pgn_path)
+    }
+
+    fn bind_server(name: &str) -> (GameServer, String) {
+        let pgn_path = std::env::temp_dir().join(format!("chess-server-{}-{}.pgn", name, std::process::id())).to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&pgn_path);
+        (GameServer::bind("127.0.0.1:0", Some(pgn_path.clone())).unwrap(), pgn_path)
+    }
+
+    /// It sends one HTTP request and returns the status and the JSON body
+    fn request(port: u16, method: &str, path: &str, body: &str) -> (u16, serde_json::Value) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", method, path, body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap_or(serde_json::Value::String(body.to_string())))
+    }
+
+    fn post(port: u16, path: &str, body: serde_json::Value) -> (u16, serde_json::Value) {
+        request(port, "POST", path, &body.to_string())
+    }
+
+    /// It creates a game and seats a second player, returning the id and the white and black tokens
+    fn seated_game(port: u16) -> (u64, String, String) {
+        let (status, created) = post(port, "/games", json!({ "name": "Ann" }));
+        assert_eq!(status, 201);
+        assert_eq!(created["color"], json!("WHITE"));
+        let id = created["id"].as_u64().unwrap();
+        let (status, joined) = post(port, &format!("/games/{}/join", id), json!({ "name": "Bob" }));
+        assert_eq!(status, 200);
+        assert_eq!(joined["color"], json!("BLACK"));
+        (id, created["token"].as_str().unwrap().to_string(), joined["token"].as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn players_create_join_and_move() {
+        let (port, _) = start_server("moves");
+        let (status, created) = post(port, "/games", json!({ "name": "Ann" }));
+        assert_eq!(status, 201);
+        let id = created["id"].as_u64().unwrap();
+        let white = created["token"].as_str().unwrap().to_string();
+
+        let (status, body) = post(port, &format!("/games/{}/moves", id), json!({ "token": white, "move": "e2e4" }));
+        assert_eq!((status, body["error"].as_str()), (409, Some("Waiting for the other player")));
+
+        let (status, joined) = post(port, &format!("/games/{}/join", id), json!({ "name": "Bob" }));
+        assert_eq!(status, 200);
+        let black = joined["token"].as_str().unwrap().to_string();
+        assert_eq!(post(port, &format!("/games/{}/join", id), json!({ "name": "Cy" })).0, 409);
+
+        let (status, game) = post(port, &format!("/games/{}/moves", id), json!({ "token": white, "move": "e2e4" }));
+        assert_eq!(status, 200);
+        assert_eq!(game["moves"], json!(["e2e4"]));
+        assert_eq!(game["turn"], json!("BLACK"));
+        assert_eq!(game["fen"], json!("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"));
+
+        let (status, game) = post(port, &format!("/games/{}/moves", id), json!({ "token": black, "move": "c7c5" }));
+        assert_eq!(status, 200);
+        assert_eq!(game["moves"], json!(["e2e4", "c7c5"]));
+
+        let (status, game) = request(port, "GET", &format!("/games/{}", id), "");
+        assert_eq!(status, 200);
+        assert_eq!((game["white"].as_str(), game["black"].as_str(), game["status"].as_str()), (Some("Ann"), Some("Bob"), Some("playing")));
+
+        let (status, games) = request(port, "GET", "/games", "");
+        assert_eq!(status, 200);
+        assert_eq!(games["games"][0]["moves"], json!(2));
+        assert_eq!(request(port, "GET", "/games/99", "").0, 404);
+    }
+
+    #[test]
+    fn illegal_moves_and_wrong_players_are_refused() {
+        let (port, _) = start_server("illegal");
+        let (id, white, black) = seated_game(port);
+        let path = format!("/games/{}/moves", id);
+
+        let (status, body) = post(port, &path, json!({ "token": white, "move": "e2e5" }));
+        assert_eq!((status, body["error"].as_str()), (400, Some("Illegal move 'e2e5'")));
+        assert_eq!(post(port, &path, json!({ "token": white, "move": "nonsense" })).0, 400);
+        let (status, body) = post(port, &path, json!({ "token": black, "move": "e7e5" }));
+        assert_eq!((status, body["error"].as_str()), (409, Some("It is not your turn")));
+        assert_eq!(post(port, &path, json!({ "token": "forged", "move": "e2e4" })).0, 403);
+        assert_eq!(request(port, "POST", &path, "not json").0, 400);
+
+        let (_, game) = request(port, "GET", &format!("/games/{}", id), "");
+        assert_eq!(game["moves"], json!([]));
+    }
+
+    #[test]
+    fn events_long_poll_wakes_up_on_a_move() {
+        let (port, _) = start_server("events");
+        let (id, white, _) = seated_game(port);
+
+        let (status, body) = request(port, "GET", &format!("/games/{}/events?since=0", id), "");
+        assert_eq!(status, 200);
+        assert_eq!(body["next"], json!(2));
+        assert!(body["events"][0]["JOINED"].is_object());
+
+        let poll = std::thread::spawn(move || request(port, "GET", &format!("/games/{}/events?since=2&timeout=10", id), ""));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!poll.is_finished());
+        post(port, &format!("/games/{}/moves", id), json!({ "token": white, "move": "g1f3" }));
+        let (status, body) = poll.join().unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body["next"], json!(3));
+        assert_eq!(body["events"][0]["MOVED"]["san"], json!("Nf3"));
+        assert_eq!(body["events"][0]["MOVED"]["ply"], json!(1));
+
+        // Without new events the poll gives up after the timeout
+        let (_, body) = request(port, "GET", &format!("/games/{}/events?since=3&timeout=1", id), "");
+        assert_eq!(body["events"], json!([]));
+    }
+
+    #[test]
+    fn finished_games_are_appended_to_the_pgn_file() {
+        let (port, pgn_path) = start_server("pgn");
+        let (id, white, black) = seated_game(port);
+        post(port, &format!("/games/{}/moves", id), json!({ "token": white, "move": "e2e4" }));
+        let (status, game) = post(port, &format!("/games/{}/resign", id), json!({ "token": black }));
+        assert_eq!(status, 200);
+        assert_eq!((game["result"].as_str(), game["status"].as_str()), (Some("1-0"), Some("finished")));
+        assert_eq!(post(port, &format!("/games/{}/moves", id), json!({ "token": black, "move": "e7e5" })).0, 409);
+
+        let (id, white, black) = seated_game(port);
+        for (token, mv) in [(&white, "f2f3"), (&black, "e7e5"), (&white, "g2g4"), (&black, "d8h4")] {
+            assert_eq!(post(port, &format!("/games/{}/moves", id), json!({ "token": token, "move": mv })).0, 200, "{}", mv);
+        }
+        let (_, game) = request(port, "GET", &format!("/games/{}", id), "");
+        assert_eq!(game["result"], json!("0-1"));
+
+        let pgn = std::fs::read_to_string(&pgn_path).unwrap();
+        assert_eq!(pgn.matches("[Event ").count(), 2);
+        assert!(pgn.contains("[White \"Ann\"]") && pgn.contains("[Black \"Bob\"]"));
+        assert!(pgn.contains("[Result \"1-0\"]") && pgn.contains("1. e4 1-0"));
+        assert!(pgn.contains("[Result \"0-1\"]") && pgn.contains("2. g4 Qh4# 0-1"));
+        std::fs::remove_file(&pgn_path).unwrap();
+    }
+
+    type Client = WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>;
+
+    fn connect(port: u16, path: &str) -> Client {
+        let (socket, _) = tungstenite::connect(format!("ws://127.0.0.1:{}{}", port, path)).unwrap();
+        if let tungstenite::stream::MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        }
+        socket
+    }
+
+    fn receive(socket: &mut Client) -> ServerMessage {
+        loop {
+            match socket.read().unwrap() {
+                Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                _ => continue
+            }
+        }
+    }
+
+    /// It reads messages until one that is not a clock tick arrives
+    fn receive_skipping_clock(socket: &mut Client) -> ServerMessage {
+        loop {
+            match receive(socket) {
+                ServerMessage::CLOCK { .. } => continue,
+                message => return message
+            }
+        }
+    }
+
+    fn send(socket: &mut Client, message: serde_json::Value) {
+        socket.send(Message::text(message.to_string())).unwrap();
+    }
+
+    #[test]
+    fn websocket_clients_resume_from_a_ply_and_play_moves() {
+        let (server, pgn_path) = bind_server("websocket");
+        let ws_port = server.listen_websocket("127.0.0.1:0").unwrap();
+        let port = server.port();
+        std::thread::spawn(move || server.run());
+
+        let (status, created) = post(port, "/games", json!({ "name": "Ann", "time": "5+0" }));
+        assert_eq!(status, 201);
+        let id = created["id"].as_u64().unwrap();
+        let white = created["token"].as_str().unwrap().to_string();
+        let (_, joined) = post(port, &format!("/games/{}/join", id), json!({ "name": "Bob" }));
+        let black = joined["token"].as_str().unwrap().to_string();
+        post(port, &format!("/games/{}/moves", id), json!({ "token": white, "move": "e2e4" }));
+        post(port, &format!("/games/{}/moves", id), json!({ "token": black, "move": "c7c5" }));
+
+        // Having seen the first ply, the client only gets the events after it
+        let mut socket = connect(ws_port, &format!("/games/{}?ply=1", id));
+        match receive_skipping_clock(&mut socket) {
+            ServerMessage::EVENT { index: 3, event: GameEvent::MOVED { ply: 2, san, .. } } => assert_eq!(san, "c5"),
+            message => panic!("Expected the move of ply 2, received {:?}", message)
+        }
+
+        send(&mut socket, json!({ "MOVE": { "token": white, "move": "g1f3" } }));
+        match receive_skipping_clock(&mut socket) {
+            ServerMessage::REPLY { status: 200, body } => assert_eq!(body["moves"], json!(["e2e4", "c7c5", "g1f3"])),
+            message => panic!("Expected the answer to the move, received {:?}", message)
+        }
+        assert!(matches!(receive_skipping_clock(&mut socket), ServerMessage::EVENT { index: 4, event: GameEvent::MOVED { ply: 3, .. } }));
+        send(&mut socket, json!({ "MOVE": { "token": black, "move": "c5c3" } }));
+        assert!(matches!(receive_skipping_clock(&mut socket), ServerMessage::REPLY { status: 400, .. }));
+
+        // Black's clock runs now and is reported every second
+        let (white_ms, black_ms) = loop {
+            if let ServerMessage::CLOCK { white_ms, black_ms, running } = receive(&mut socket) {
+                assert_eq!(running, Color::BLACK);
+                break (white_ms, black_ms);
+            }
+        };
+        assert!(white_ms <= 300_000 && black_ms < 300_000);
+
+        send(&mut socket, json!({ "RESUME": { "ply": 0 } }));
+        for (expected, color) in [(0, Color::WHITE), (1, Color::BLACK)] {
+            match receive_skipping_clock(&mut socket) {
+                ServerMessage::EVENT { index, event: GameEvent::JOINED { color: joined, .. } } => assert_eq!((index, joined), (expected, color)),
+                message => panic!("Expected the events from the start, received {:?}", message)
+            }
+        }
+        for index in 2..5 {
+            assert!(matches!(receive_skipping_clock(&mut socket), ServerMessage::EVENT { index: i, .. } if i == index));
+        }
+
+        send(&mut socket, json!({ "RESIGN": { "token": black } }));
+        assert!(matches!(receive_skipping_clock(&mut socket), ServerMessage::REPLY { status: 200, .. }));
+        match receive_skipping_clock(&mut socket) {
+            ServerMessage::EVENT { event: GameEvent::FINISHED { result, .. }, .. } => assert_eq!(result, "1-0"),
+            message => panic!("Expected the end of the game, received {:?}", message)
+        }
+        assert!(matches!(socket.read(), Ok(Message::Close(_)) | Err(_)));
+        std::fs::remove_file(&pgn_path).unwrap();
+
+        let mut missing = connect(ws_port, "/games/99");
+        assert!(matches!(missing.read().unwrap(), Message::Text(text) if text.contains("No such game")));
+    }
+}
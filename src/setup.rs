@@ -0,0 +1,350 @@
+use crate::gamestate::GameState;
+use crate::piece::{ChessPiece, Color, PieceType};
+use crate::render::BoardRenderer;
+use crate::script::CommandInput;
+use crate::variant::VariantKind;
+
+/// The commands of the setup mode, printed by `help`.
+const SETUP_HELP: &str = "Setup commands:
+  add <piece> <square>     add a piece, such as 'add N f3', 'add k e8' or 'add white knight f3'
+  remove <square>          remove the piece on a square
+  move <from> <to>         move a piece, replacing what stands on the target
+  clear                    remove every piece
+  start                    set up the start position of the variant
+  turn <white|black>       set the player to move
+  castling <KQkq|->        set the castling rights, X-FEN rook files such as 'Hb' are accepted
+  enpassant <square|->     set the en passant square
+  show                     print the board and its FEN
+  done                     check the position and play it
+  cancel                   leave without changing the game";
+
+/// A position being set up by hand. Nothing is checked while editing, the position is only checked
+/// when it is finished.
+///
+/// Properties:
+///
+/// * `board`: The pieces, indexed like `GameState::board`.
+/// * `current_player`: The player to move.
+/// * `castling`: The letters of the castling rights as in a FEN, unused places hold '-'.
+/// * `en_passant`: The tile a pawn passed over with a double step in the last move, if any.
+/// * `variant`: The rules the position is played with.
+/// * `pockets`: The Crazyhouse pockets, kept as they were, empty outside Crazyhouse.
+/// * `promoted`: The Crazyhouse pieces that were pawns, as a bitboard. They follow the piece when it is
+///   moved and are dropped when it is removed or replaced.
+/// * `checks_given`: The checks White and Black have given in Three-check, kept as they were.
+#[derive(Copy, Clone, Debug)]
+pub struct PositionEditor {
+    pub board: [Option<ChessPiece>; 64],
+    pub current_player: Color,
+    pub castling: [char; 4],
+    pub en_passant: Option<usize>,
+    pub variant: VariantKind,
+    pub pockets: [[u8; 5]; 2],
+    pub promoted: u64,
+    pub checks_given: [u8; 2],
+}
+
+/// It reads a piece written as a FEN letter, upper case for White, or as a color and a name such as
+/// "white knight"
+pub fn parse_piece(text: &str) -> Option<ChessPiece> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let (color, name) = match words.as_slice() {
+        [letter] if letter.chars().count() == 1 => {
+            let c = letter.chars().next()?;
+            (if c.is_ascii_uppercase() { Color::WHITE } else { Color::BLACK }, c.to_ascii_lowercase().to_string())
+        },
+        [color, name] => (match color.to_lowercase().as_str() {
+            "white" | "w" => Color::WHITE,
+            "black" | "b" => Color::BLACK,
+            _ => return None
+        }, name.to_lowercase()),
+        _ => return None
+    };
+    let piecetype = match name.as_str() {
+        "p" | "pawn" => PieceType::PAWN(true),
+        "n" | "knight" => PieceType::KNIGHT,
+        "b" | "bishop" => PieceType::BISHOP,
+        "r" | "rook" => PieceType::ROOK(true),
+        "q" | "queen" => PieceType::QUEEN,
+        "k" | "king" => PieceType::KING(true),
+        _ => return None
+    };
+    Some(ChessPiece::new(piecetype, color))
+}
+
+impl PositionEditor {
+/// It starts editing from a position
+    pub fn new(gamestate: &GameState) -> PositionEditor {
+        let fen = gamestate.to_fen();
+        let castling = fen.split_whitespace().nth(2).unwrap_or("-");
+        let mut editor = PositionEditor {
+            board: gamestate.board,
+            current_player: gamestate.current_player,
+            castling: ['-'; 4],
+            en_passant: gamestate.en_passant,
+            variant: gamestate.variant,
+            pockets: gamestate.pockets,
+            promoted: gamestate.promoted,
+            checks_given: gamestate.checks_given,
+        };
+        // A FEN is only written for a valid position, so the rights always fit
+        let _ = editor.set_castling(castling);
+        editor
+    }
+
+/// It sets the castling rights from a FEN field such as "KQkq", "Kq" or "-"
+    pub fn set_castling(&mut self, text: &str) -> Result<(), String> {
+        let text = text.trim();
+        if text == "-" {
+            self.castling = ['-'; 4];
+            return Ok(());
+        }
+        if text.chars().count() > 4 || !text.chars().all(|c| "KQkqABCDEFGHabcdefgh".contains(c)) {
+            return Err(format!("Invalid castling rights '{}', expected letters of KQkq or '-'", text));
+        }
+        self.castling = ['-'; 4];
+        for (i, c) in text.chars().enumerate() {
+            self.castling[i] = c;
+        }
+        Ok(())
+    }
+
+/// It writes the castling rights as in a FEN
+    pub fn castling_text(&self) -> String {
+        let text: String = self.castling.iter().filter(|&&c| c != '-').collect();
+        if text.is_empty() { "-".to_string() } else { text }
+    }
+
+/// It returns the position with the board as it is, unchecked, for drawing and writing as FEN
+    pub fn preview(&self) -> GameState {
+        let mut gamestate = GameState::new();
+        gamestate.board = self.board;
+        gamestate.prev_board = self.board;
+        gamestate.current_player = self.current_player;
+        gamestate.variant = self.variant;
+        gamestate.wkc = gamestate.find_white_king();
+        gamestate.bkc = gamestate.find_black_king();
+        gamestate.pockets = self.pockets;
+        gamestate.promoted = self.promoted;
+        gamestate.checks_given = self.checks_given;
+        gamestate
+    }
+
+/// It writes the edited position in Forsyth-Edwards Notation
+    pub fn to_fen(&self) -> String {
+        let preview = self.preview().to_fen();
+        let board = preview.split_whitespace().next().unwrap_or("");
+        let en_passant = self.en_passant.map_or("-".to_string(), |coord| GameState::coordinate_translator_usize(coord).to_lowercase());
+        format!("{} {} {} {} 0 1", board, if self.current_player == Color::BLACK { "b" } else { "w" }, self.castling_text(), en_passant)
+    }
+
+/// It checks the position and turns it into one that can be played: each player needs the number of
+/// kings the variant asks for, usually one, the player not to move may not be in check, pawns may not
+/// stand on the first or last row, the en passant square has to follow a pawn's double step and
+/// every castling right needs its king and rook on their first row
+///
+/// Returns:
+///
+/// The position, or a message naming what is wrong with it.
+    pub fn finish(&self) -> Result<GameState, String> {
+        let mut gamestate = GameState::from_fen_variant(&self.to_fen(), self.variant)?;
+        // The FEN carries the promoted pieces but not the checks given
+        gamestate.checks_given = self.checks_given;
+        Ok(gamestate)
+    }
+
+/// It carries out one setup command, see SETUP_HELP
+///
+/// Returns:
+///
+/// A message describing what is wrong with the command, the position is then left as it was.
+    pub fn command(&mut self, line: &str) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let square = |text: &str| GameState::parse_square(text).ok_or(format!("Invalid square '{}'", text));
+        match words.as_slice() {
+            ["add" | "put", piece @ .., target] if !piece.is_empty() => {
+                let piece = parse_piece(&piece.join(" ")).ok_or(format!("Invalid piece '{}'", piece.join(" ")))?;
+                let coord = square(target)?;
+                self.board[coord] = Some(piece);
+                self.promoted &= !(1 << coord);
+            },
+            ["remove" | "rm", target] => {
+                let coord = square(target)?;
+                if self.board[coord].take().is_none() {
+                    return Err(format!("There is no piece on {}", target));
+                }
+                self.promoted &= !(1 << coord);
+            },
+            ["move" | "mv", from, to] => {
+                let (from, to) = (square(from)?, square(to)?);
+                let piece = self.board[from].take().ok_or(format!("There is no piece on {}", words[1]))?;
+                self.board[to] = Some(piece);
+                let promoted = self.promoted & (1 << from) != 0;
+                self.promoted &= !(1 << from) & !(1 << to);
+                if promoted {
+                    self.promoted |= 1 << to;
+                }
+            },
+            ["clear"] => {
+                self.board = [None; 64];
+                self.promoted = 0;
+                self.castling = ['-'; 4];
+                self.en_passant = None;
+            },
+            ["start"] => *self = PositionEditor::new(&self.variant.start_position()),
+            ["turn", color] => self.current_player = match color.to_lowercase().as_str() {
+                "white" | "w" => Color::WHITE,
+                "black" | "b" => Color::BLACK,
+                _ => return Err(format!("Invalid color '{}'", color))
+            },
+            ["castling", rights] => self.set_castling(rights)?,
+            ["enpassant" | "ep", "-"] => self.en_passant = None,
+            ["enpassant" | "ep", target] => self.en_passant = Some(square(target)?),
+            _ => return Err(format!("Unknown setup command '{}', 'help' lists the commands", line.trim()))
+        }
+        Ok(())
+    }
+}
+
+/// It runs the setup mode, reading commands until the position is finished or the editing is cancelled
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to start editing from.
+/// * `input`: Where the commands are read from.
+/// * `renderer`: Draws the board after every change.
+///
+/// Returns:
+///
+/// The checked position, or None if the editing was cancelled or the input ended.
+pub fn run_setup(gamestate: &GameState, input: &mut CommandInput, renderer: &BoardRenderer) -> Option<GameState> {
+    let mut editor = PositionEditor::new(gamestate);
+    println!("{}", SETUP_HELP);
+    renderer.print(&editor.preview(), None);
+    loop {
+        println!("setup> ");
+        let line = input.read_command()?;
+        match line.trim() {
+            "" => continue,
+            "help" => println!("{}", SETUP_HELP),
+            "cancel" => {
+                println!("Setup cancelled");
+                return None;
+            },
+            "show" => {
+                renderer.print(&editor.preview(), None);
+                println!("{}", editor.to_fen());
+            },
+            "done" => match editor.finish() {
+                Ok(position) => {
+                    println!("Position set up: {}", position.to_fen());
+                    return Some(position);
+                },
+                Err(e) => input.error(&format!("The position can not be played: {}", e))
+            },
+            command => match editor.command(command) {
+                Ok(()) => renderer.print(&editor.preview(), None),
+                Err(e) => input.error(&e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(fen: &str) -> PositionEditor {
+        PositionEditor::new(&GameState::from_fen(fen).unwrap())
+    }
+
+    fn run(editor: &mut PositionEditor, commands: &[&str]) {
+        for command in commands {
+            editor.command(command).unwrap();
+        }
+    }
+
+    #[test]
+    fn each_player_needs_one_king() {
+        let mut position = editor("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        assert!(position.finish().is_ok());
+        run(&mut position, &["remove e8"]);
+        assert!(position.finish().is_err());
+        run(&mut position, &["add k e8", "add k a8"]);
+        assert!(position.finish().is_err());
+        run(&mut position, &["remove a8", "add white king a1"]);
+        assert!(position.finish().is_err());
+
+        // Horde asks for no white king at all
+        let mut horde = PositionEditor::new(&VariantKind::HORDE.start_position());
+        assert!(horde.finish().is_ok());
+        run(&mut horde, &["add K e1"]);
+        assert!(horde.finish().is_err());
+    }
+
+    #[test]
+    fn player_not_to_move_may_not_be_in_check() {
+        let mut position = editor("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        run(&mut position, &["add R e2"]);
+        assert_eq!(position.finish().unwrap_err(), "The player who is not to move is in check");
+        run(&mut position, &["turn black"]);
+        let gamestate = position.finish().unwrap();
+        assert!(gamestate.checked_flag);
+        assert_eq!(gamestate.checked_player, Color::BLACK);
+    }
+
+    #[test]
+    fn pawns_may_not_stand_on_the_first_or_last_row() {
+        for square in ["a1", "h8"] {
+            let mut position = editor("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+            run(&mut position, &[&format!("add P {}", square)]);
+            assert!(position.finish().is_err(), "{}", square);
+        }
+    }
+
+    #[test]
+    fn en_passant_square_needs_a_pawn_that_just_moved_two_tiles() {
+        let mut position = editor("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1");
+        run(&mut position, &["enpassant d6"]);
+        assert_eq!(position.finish().unwrap().en_passant, GameState::parse_square("d6"));
+        run(&mut position, &["enpassant e6"]);
+        assert!(position.finish().is_err());
+        run(&mut position, &["enpassant d3"]);
+        assert!(position.finish().is_err());
+        run(&mut position, &["enpassant d6", "add n d7"]);
+        assert!(position.finish().is_err());
+    }
+
+    #[test]
+    fn castling_rights_need_king_and_rook_on_the_first_row() {
+        let mut position = editor("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+        run(&mut position, &["castling KQkq"]);
+        assert_eq!(position.finish().unwrap().castling_rights(), [true; 4]);
+        run(&mut position, &["remove h1"]);
+        assert!(position.finish().is_err());
+        run(&mut position, &["castling Qkq", "move e8 e7"]);
+        assert!(position.finish().is_err());
+        run(&mut position, &["castling Q"]);
+        assert!(position.finish().is_ok());
+        assert!(position.command("castling KQx").is_err());
+    }
+
+    #[test]
+    fn checks_given_and_promoted_pieces_are_kept() {
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/8/8/8/8/4K2R w - - 0 1", VariantKind::THREECHECK).unwrap();
+        gamestate.checks_given = [2, 1];
+        let mut position = PositionEditor::new(&gamestate);
+        run(&mut position, &["move h1 h2"]);
+        assert_eq!(position.finish().unwrap().checks_given, [2, 1]);
+
+        let gamestate = GameState::from_fen("4k3/8/8/8/8/8/8/Q~N~2K3[p] w - - 0 1").unwrap();
+        let mut position = PositionEditor::new(&gamestate);
+        run(&mut position, &["move a1 d4", "remove b1"]);
+        let finished = position.finish().unwrap();
+        assert_eq!(finished.variant, VariantKind::CRAZYHOUSE);
+        assert_eq!(finished.promoted, 1 << GameState::parse_square("d4").unwrap());
+        assert_eq!(finished.pockets, gamestate.pockets);
+        run(&mut position, &["add Q d4"]);
+        assert_eq!(position.finish().unwrap().promoted, 0);
+    }
+}
@@ -0,0 +1,1147 @@
+use crate::gamestate::GameState;
+use crate::piece::{ChessPiece, Color, PieceType};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The outcome of a position with perfect play, seen from the side to move. A cursed win can only be
+/// won by breaking the fifty-move rule, a blessed loss is saved by it.
+///
+/// * `LOSS`: The side to move loses.
+/// * `BLESSEDLOSS`: The side to move loses, but the fifty-move rule draws the game.
+/// * `DRAW`: The game is drawn.
+/// * `CURSEDWIN`: The side to move wins, but the fifty-move rule draws the game.
+/// * `WIN`: The side to move wins.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub enum Wdl {
+    LOSS,
+    BLESSEDLOSS,
+    DRAW,
+    CURSEDWIN,
+    WIN
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::LOSS,
+            -1 => Wdl::BLESSEDLOSS,
+            1 => Wdl::CURSEDWIN,
+            2 => Wdl::WIN,
+            _ => Wdl::DRAW
+        }
+    }
+}
+
+/// A move as (piece coordinate, target coordinate) with its distance to zeroing.
+pub type ScoredMove = ((usize, usize), i32);
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// Flags of a compressed table, all but the last one only used by DTZ tables
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Endgame tablebases in the Syzygy format, read from the `.rtbw` (win/draw/loss) and `.rtbz`
+/// (distance to zeroing) files of a local directory. Tables are loaded the first time they are needed.
+///
+/// Properties:
+///
+/// * `directory`: The directory the tables are read from.
+/// * `files`: The table files found in the directory, by file name such as "KQvK.rtbw".
+/// * `max_pieces`: The number of pieces, kings included, of the largest table found.
+/// * `tables`: The tables loaded so far.
+pub struct Tablebase {
+    pub directory: PathBuf,
+    files: HashMap<String, PathBuf>,
+    pub max_pieces: usize,
+    tables: Mutex<HashMap<String, Arc<Table>>>,
+}
+
+impl Tablebase {
+/// It looks for Syzygy tables in the given directory
+///
+/// Arguments:
+///
+/// * `directory`: The directory holding the `.rtbw` and `.rtbz` files.
+///
+/// Returns:
+///
+/// The tablebase, or a message describing why the directory can not be used.
+    pub fn open(directory: &str) -> Result<Tablebase, String> {
+        let entries = std::fs::read_dir(directory).map_err(|e| format!("Could not read '{}': {}", directory, e))?;
+        let mut files: HashMap<String, PathBuf> = HashMap::new();
+        let mut max_pieces = 0;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let material = match name.strip_suffix(".rtbw").or_else(|| name.strip_suffix(".rtbz")) {
+                Some(material) => material,
+                None => continue
+            };
+            let valid = material.split_once('v').is_some_and(|(white, black)| {
+                white.starts_with('K') && black.starts_with('K') && material.chars().all(|c| "KQRBNPv".contains(c))
+            });
+            if valid {
+                max_pieces = max_pieces.max(material.len() - 1);
+                files.insert(name, entry.path());
+            }
+        }
+        if files.is_empty() {
+            return Err(format!("No Syzygy tables found in '{}'", directory));
+        }
+        Ok(Tablebase { directory: PathBuf::from(directory), files, max_pieces, tables: Mutex::new(HashMap::new()) })
+    }
+
+/// It returns the number of table files found
+    pub fn table_count(&self) -> usize {
+        self.files.len()
+    }
+
+/// It looks up whether the position is won, drawn or lost with perfect play
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to look up, it must have no castling rights.
+///
+/// Returns:
+///
+/// The outcome for the side to move, or why the position can not be looked up.
+    pub fn probe_wdl(&self, gamestate: &GameState) -> Result<Wdl, String> {
+        self.check_position(gamestate)?;
+        let (value, _) = self.search(gamestate, false)?;
+        Ok(Wdl::from_value(value))
+    }
+
+/// It looks up the distance to zeroing of the position, the number of plies until the winning side
+/// can make a capture or pawn move that keeps the win. Draws have a distance of 0.
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to look up, it must have no castling rights.
+///
+/// Returns:
+///
+/// The distance in plies, positive when the side to move wins and negative when it loses. Results
+/// beyond the fifty-move rule are counted 100 plies further away, so that cursed wins and blessed
+/// losses are above 100.
+    pub fn probe_dtz(&self, gamestate: &GameState) -> Result<i32, String> {
+        self.check_position(gamestate)?;
+        self.dtz(gamestate)
+    }
+
+/// It finds the move that keeps the best outcome and, when winning, zeroes the fifty-move counter
+/// soonest. When losing it resists for as long as possible.
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to find a move for.
+///
+/// Returns:
+///
+/// The move and its distance to zeroing seen from the side to move, None if there is no legal move.
+    pub fn best_move(&self, gamestate: &GameState) -> Result<Option<ScoredMove>, String> {
+        self.check_position(gamestate)?;
+        let mut state: GameState = *gamestate;
+        state.debug_flag = false;
+        let mut best: Option<ScoredMove> = None;
+        let rank = |dtz: i32| -> i32 {
+            match dtz.signum() {
+                1 => 1000 - dtz,
+                -1 => -1000 - dtz,
+                _ => 0
+            }
+        };
+        for (pc, tc) in state.legal_moves() {
+            let zeroing = is_zeroing(&state, pc, tc);
+            let mut next: GameState = state;
+            next.make_move(pc, tc);
+            let mut dtz = if zeroing {
+                dtz_before_zeroing(-self.search(&next, false)?.0)
+            } else {
+                let dtz = -self.dtz(&next)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && next.checked_flag && next.legal_moves().is_empty() {
+                dtz = 1;
+            }
+            if best.is_none_or(|(_, best_dtz)| rank(dtz) > rank(best_dtz)) {
+                best = Some(((pc, tc), dtz));
+            }
+        }
+        Ok(best)
+    }
+
+    fn check_position(&self, gamestate: &GameState) -> Result<(), String> {
+        if gamestate.piece_count() > self.max_pieces {
+            return Err(format!("The tablebase has no tables for {} pieces", gamestate.piece_count()));
+        }
+        if gamestate.castling_rights().iter().any(|&right| right) {
+            return Err("The tablebase has no positions where castling is still possible".to_string());
+        }
+        Ok(())
+    }
+
+    // The tables do not know about en passant and may store any value when the best move is a
+    // capture, so captures (and pawn moves for the distance to zeroing) are searched first
+    fn search(&self, gamestate: &GameState, check_zeroing: bool) -> Result<(i32, bool), String> {
+        let mut state: GameState = *gamestate;
+        state.debug_flag = false;
+        let moves = state.legal_moves();
+        let mut best_value = -2;
+        let mut move_count = 0;
+        for &(pc, tc) in moves.iter() {
+            if !(state.is_capture(pc, tc) || (check_zeroing && is_pawn(&state, pc))) {
+                continue;
+            }
+            move_count += 1;
+            let mut next: GameState = state;
+            next.make_move(pc, tc);
+            let value = -self.search(&next, false)?.0;
+            if value > best_value {
+                best_value = value;
+                if value >= 2 {
+                    return Ok((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best_value
+        } else {
+            self.probe_table(&state, false, 0)?.unwrap_or(0)
+        };
+        if best_value >= value {
+            return Ok((best_value, best_value > 0 || no_more_moves));
+        }
+        Ok((value, false))
+    }
+
+    fn dtz(&self, gamestate: &GameState) -> Result<i32, String> {
+        let (wdl, zeroing_best) = self.search(gamestate, true)?;
+        if wdl == 0 {
+            return Ok(0);
+        }
+        if zeroing_best {
+            return Ok(dtz_before_zeroing(wdl));
+        }
+        if let Some(dtz) = self.probe_table(gamestate, true, wdl)? {
+            let cursed = if wdl.abs() == 1 { 100 } else { 0 };
+            return Ok((dtz + cursed) * wdl.signum());
+        }
+
+        // The table only stores the other side to move, look one ply ahead instead
+        let mut state: GameState = *gamestate;
+        state.debug_flag = false;
+        let mut min_dtz = 0xFFFF;
+        for (pc, tc) in state.legal_moves() {
+            let zeroing = is_zeroing(&state, pc, tc);
+            let mut next: GameState = state;
+            next.make_move(pc, tc);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&next, false)?.0)
+            } else {
+                -self.dtz(&next)?
+            };
+            if dtz == 1 && next.checked_flag && next.legal_moves().is_empty() {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Ok(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    fn table(&self, name: &str) -> Result<Arc<Table>, String> {
+        let mut tables = self.tables.lock().map_err(|_| "The tablebase is unusable".to_string())?;
+        if let Some(table) = tables.get(name) {
+            return Ok(table.clone());
+        }
+        let path = self.files.get(name).ok_or(format!("Missing table {}", name))?;
+        let bytes = std::fs::read(path).map_err(|e| format!("Could not read '{}': {}", path.display(), e))?;
+        let table = Arc::new(Table::new(name, bytes)?);
+        tables.insert(name.to_string(), table.clone());
+        Ok(table)
+    }
+
+    // It reads the value of the position from the table of its material. For the distance to zeroing
+    // None is returned when the table stores the other side to move.
+    fn probe_table(&self, gamestate: &GameState, dtz: bool, wdl: i32) -> Result<Option<i32>, String> {
+        if gamestate.piece_count() == 2 {
+            return Ok(Some(0));
+        }
+        let white = material(gamestate, Color::WHITE);
+        let black = material(gamestate, Color::BLACK);
+        let extension = if dtz { "rtbz" } else { "rtbw" };
+        let straight = format!("{}v{}.{}", white, black, extension);
+        let swapped = format!("{}v{}.{}", black, white, extension);
+        let (name, black_stronger) = if self.files.contains_key(&straight) {
+            (straight, false)
+        } else if self.files.contains_key(&swapped) {
+            (swapped, true)
+        } else {
+            return Err(format!("Missing table {}", straight));
+        };
+        let table = self.table(&name)?;
+        let maps = maps();
+
+        // Tables are stored with the stronger side as White, and for equal material only with White
+        // to move, so the board is mirrored vertically and the colors swapped when needed
+        let black_to_move = gamestate.current_player == Color::BLACK;
+        let flip = black_stronger || (table.symmetric && black_to_move);
+        let flip_color: u8 = if flip { 8 } else { 0 };
+        let flip_squares: usize = if flip { 56 } else { 0 };
+        let stm = (flip ^ black_to_move) as usize;
+
+        let mut squares: Vec<usize> = Vec::new();
+        let mut pieces: Vec<u8> = Vec::new();
+        let mut lead_pawns = 0;
+        let mut lead_pawn_code = 0;
+        let mut tb_file = 0;
+        if table.has_pawns {
+            // The leading pawns are the pawns of the color of the first piece of the table
+            lead_pawn_code = table.items[0][0].pieces[0] ^ flip_color;
+            for i in 0..64 {
+                if gamestate.board[i].map(piece_code) == Some(lead_pawn_code) {
+                    squares.push(i ^ flip_squares);
+                    pieces.push(lead_pawn_code ^ flip_color);
+                }
+            }
+            lead_pawns = squares.len();
+            let mut lead = 0;
+            for i in 1..lead_pawns {
+                if maps.map_pawns[squares[i]] > maps.map_pawns[squares[lead]] {
+                    lead = i;
+                }
+            }
+            squares.swap(0, lead);
+            let file = squares[0] % 8;
+            tb_file = file.min(7 - file);
+        }
+
+        let stored_side = (table.items[0][tb_file].flags & FLAG_STM) as usize;
+        if dtz && stored_side != stm && (!table.symmetric || table.has_pawns) {
+            return Ok(None);
+        }
+
+        for i in 0..64 {
+            if let Some(piece) = gamestate.board[i] {
+                let code = piece_code(piece);
+                if table.has_pawns && code == lead_pawn_code {
+                    continue;
+                }
+                squares.push(i ^ flip_squares);
+                pieces.push(code ^ flip_color);
+            }
+        }
+        let size = squares.len();
+        if size != table.piece_count {
+            return Err(format!("The position does not match table {}", name));
+        }
+        let d = &table.items[if dtz { 0 } else { stm }][tb_file];
+
+        // Put the pieces in the order the table was encoded with
+        for i in lead_pawns..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // Mirror the board so the leading piece is on files a-d
+        if squares[0] % 8 > 3 {
+            for square in squares.iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if table.has_pawns {
+            idx = maps.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&s| maps.map_pawns[s]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += maps.binomial[i][maps.map_pawns[square]];
+            }
+        } else {
+            // Without pawns the board is also mirrored so the leading piece is in the a1-d1-d4 triangle
+            if squares[0] / 8 > 3 {
+                for square in squares.iter_mut() {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for square in squares[i..].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if table.has_unique_pieces {
+                let s = [squares[0] as u64, squares[1] as u64, squares[2] as u64];
+                let rank = |square: u64| square / 8;
+                let adjust1 = (s[1] > s[0]) as u64;
+                let adjust2 = (s[2] > s[0]) as u64 + (s[2] > s[1]) as u64;
+                idx = if off_diagonal(squares[0]) != 0 {
+                    (maps.map_a1d1d4[squares[0]] as u64 * 63 + (s[1] - adjust1)) * 62 + s[2] - adjust2
+                } else if off_diagonal(squares[1]) != 0 {
+                    (6 * 63 + rank(s[0]) * 28 + maps.map_b1h1h7[squares[1]] as u64) * 62 + s[2] - adjust2
+                } else if off_diagonal(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(s[0]) * 7 * 28 + (rank(s[1]) - adjust1) * 28 + maps.map_b1h1h7[squares[2]] as u64
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s[0]) * 7 * 6 + (rank(s[1]) - adjust1) * 6 + (rank(s[2]) - adjust2)
+                };
+            } else {
+                idx = maps.map_kk[maps.map_a1d1d4[squares[0]]][squares[1]];
+            }
+        }
+
+        // The remaining groups of equal pieces, each encoded as a combination of free squares
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = table.has_pawns && table.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[group_start..group_start + len].sort();
+            let mut n: u64 = 0;
+            for i in 0..len {
+                let square = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|&&s| square > s).count();
+                n += maps.binomial[i + 1][square - adjust - 8 * remaining_pawns as usize];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start += len;
+            next += 1;
+        }
+
+        let value = d.decompress(&table.bytes, idx);
+        if !dtz {
+            return Ok(Some(value - 2));
+        }
+        Ok(Some(table.map_score(tb_file, value, wdl)))
+    }
+}
+
+fn is_pawn(gamestate: &GameState, pc: usize) -> bool {
+    matches!(gamestate.board[pc], Some(p) if matches!(p.piecetype, PieceType::PAWN(_)))
+}
+
+fn is_zeroing(gamestate: &GameState, pc: usize, tc: usize) -> bool {
+    is_pawn(gamestate, pc) || gamestate.is_capture(pc, tc)
+}
+
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0
+    }
+}
+
+// The material of one side the way Syzygy names its files, "KRP" for king, rook and pawn
+fn material(gamestate: &GameState, color: Color) -> String {
+    let mut material = String::new();
+    for (letter, code) in [('K', 6), ('Q', 5), ('R', 4), ('B', 3), ('N', 2), ('P', 1)] {
+        for piece in gamestate.board.iter().flatten() {
+            if piece.color == color && piece_code(*piece) & 7 == code {
+                material.push(letter);
+            }
+        }
+    }
+    material
+}
+
+// Pieces are numbered 1 to 6 from pawn to king, 8 is added for Black
+fn piece_code(piece: ChessPiece) -> u8 {
+    let code = match piece.piecetype {
+        PieceType::PAWN(_) => 1,
+        PieceType::KNIGHT => 2,
+        PieceType::BISHOP => 3,
+        PieceType::ROOK(_) => 4,
+        PieceType::QUEEN => 5,
+        PieceType::KING(_) => 6,
+        PieceType::NONE => 0
+    };
+    match piece.color {
+        Color::BLACK => code + 8,
+        _ => code
+    }
+}
+
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> u16 {
+    bytes.get(at..at + 2).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> u32 {
+    bytes.get(at..at + 4).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> u32 {
+    bytes.get(at..at + 4).map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn u64_be(bytes: &[u8], at: usize) -> u64 {
+    ((u32_be(bytes, at) as u64) << 32) | u32_be(bytes, at + 4) as u64
+}
+
+/// The lookup tables used to turn a position into an index of a table.
+///
+/// Properties:
+///
+/// * `binomial`: The number of ways to choose k of n squares.
+/// * `map_pawns`: The squares a2-h7 numbered by how close they are to the edge, lowest rank first.
+/// * `lead_pawn_idx`: The first index of the leading pawns with the leading one on a square.
+/// * `lead_pawns_size`: The number of indexes of the leading pawns per file.
+/// * `map_b1h1h7`: The squares below the a1-h8 diagonal numbered 0 to 27.
+/// * `map_a1d1d4`: The squares of the a1-d1-d4 triangle numbered 0 to 9, diagonal last.
+/// * `map_kk`: The 462 placements of two kings with the first in the a1-d1-d4 triangle.
+struct Maps {
+    binomial: [[u64; 64]; 7],
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[u64; 64]; 10],
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut maps = Maps {
+            binomial: [[0; 64]; 7],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+        };
+
+        let mut code = 0;
+        for s in 0..64 {
+            if off_diagonal(s) < 0 {
+                maps.map_b1h1h7[s] = code;
+                code += 1;
+            }
+        }
+
+        code = 0;
+        let mut diagonal: Vec<usize> = Vec::new();
+        for s in 0..28 {
+            if off_diagonal(s) < 0 && s % 8 <= 3 {
+                maps.map_a1d1d4[s] = code;
+                code += 1;
+            } else if off_diagonal(s) == 0 && s % 8 <= 3 {
+                diagonal.push(s);
+            }
+        }
+        for s in diagonal {
+            maps.map_a1d1d4[s] = code;
+            code += 1;
+        }
+
+        let mut kk_code = 0;
+        let mut both_on_diagonal: Vec<(usize, usize)> = Vec::new();
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if maps.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let touching = GameState::abs_diff_x(s1, s2) <= 1 && GameState::abs_diff_y(s1, s2) <= 1;
+                    if touching || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        maps.map_kk[idx][s2] = kk_code;
+                        kk_code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            maps.map_kk[idx][s2] = kk_code;
+            kk_code += 1;
+        }
+
+        maps.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7.min(n + 1) {
+                maps.binomial[k][n] = if k > 0 { maps.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { maps.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available: i32 = 47;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx: u64 = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        maps.map_pawns[square] = available as usize;
+                        maps.map_pawns[square ^ 7] = (available - 1) as usize;
+                        available -= 2;
+                    }
+                    maps.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += maps.binomial[lead_pawns - 1][maps.map_pawns[square]];
+                }
+                maps.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        maps
+    })
+}
+
+/// One compressed table, the positions of one side to move and, with pawns, one file of the leading
+/// pawn. Values are compressed by recursive pairing and Huffman coding in blocks of fixed size.
+///
+/// Properties:
+///
+/// * `flags`: The `FLAG_` bits of the table.
+/// * `pieces`: The piece codes in the order the position is encoded in.
+/// * `group_len`: The number of pieces of every group of equal pieces, zero terminated.
+/// * `group_idx`: The factor every group's index is multiplied with, the last one is the table size.
+/// * `block_size`: The size in bytes of a compressed block.
+/// * `span`: The number of positions between two entries of the sparse index.
+/// * `num_blocks`: The number of compressed blocks.
+/// * `min_sym_len`: The length in bits of the shortest Huffman code, or the value of a single value table.
+/// * `base64`: The lowest code of every code length, left aligned in 64 bits.
+/// * `symlen`: The number of values, minus one, every symbol expands to.
+/// * `lowest_sym`, `btree`, `sparse_index`, `block_length`, `data`: Where these parts start in the file.
+/// * `map_idx`: Where the value map of wins, losses, cursed wins and blessed losses start (DTZ only).
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; 7],
+    group_len: [usize; 8],
+    group_idx: [u64; 8],
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    block_length_size: usize,
+    sparse_index_size: usize,
+    min_sym_len: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    lowest_sym: usize,
+    btree: usize,
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    fn left(&self, bytes: &[u8], symbol: usize) -> usize {
+        let at = self.btree + 3 * symbol;
+        (((bytes.get(at + 1).copied().unwrap_or(0) as usize) & 0xF) << 8) | bytes.get(at).copied().unwrap_or(0) as usize
+    }
+
+    fn right(&self, bytes: &[u8], symbol: usize) -> usize {
+        let at = self.btree + 3 * symbol;
+        ((bytes.get(at + 2).copied().unwrap_or(0) as usize) << 4) | ((bytes.get(at + 1).copied().unwrap_or(0) as usize) >> 4)
+    }
+
+    fn set_symlen(&mut self, bytes: &[u8], symbol: usize, visited: &mut Vec<bool>) -> u8 {
+        visited[symbol] = true;
+        let right = self.right(bytes, symbol);
+        if right == 0xFFF {
+            return 0;
+        }
+        let left = self.left(bytes, symbol);
+        for s in [left, right] {
+            if s < visited.len() && !visited[s] {
+                self.symlen[s] = self.set_symlen(bytes, s, visited);
+            }
+        }
+        let length = |s: usize| self.symlen.get(s).copied().unwrap_or(0);
+        length(left).wrapping_add(length(right)).wrapping_add(1)
+    }
+
+    // It reads the sizes of the table and builds the Huffman decoding tables, returning where the
+    // next part of the file starts
+    fn set_sizes(&mut self, bytes: &[u8], mut at: usize) -> Result<usize, String> {
+        self.flags = *bytes.get(at).ok_or("The table is truncated")?;
+        at += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            self.min_sym_len = *bytes.get(at).ok_or("The table is truncated")? as usize;
+            return Ok(at + 1);
+        }
+        let header = bytes.get(at..at + 9).ok_or("The table is truncated")?;
+        let size = self.group_idx[self.group_len.iter().position(|&l| l == 0).unwrap_or(7)];
+        self.block_size = 1 << header[0];
+        self.span = 1 << header[1];
+        self.sparse_index_size = size.div_ceil(self.span) as usize;
+        let padding = header[2] as usize;
+        self.num_blocks = u32_le(bytes, at + 3) as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = header[7] as usize;
+        self.min_sym_len = header[8] as usize;
+        at += 9;
+        if max_sym_len < self.min_sym_len || max_sym_len > 64 {
+            return Err("The table has an invalid Huffman code".to_string());
+        }
+        self.lowest_sym = at;
+
+        let count = max_sym_len - self.min_sym_len + 1;
+        self.base64 = vec![0; count];
+        for i in (0..count - 1).rev() {
+            let lowest = u16_le(bytes, self.lowest_sym + 2 * i) as u64;
+            let lowest_next = u16_le(bytes, self.lowest_sym + 2 * i + 2) as u64;
+            self.base64[i] = self.base64[i + 1].wrapping_add(lowest).wrapping_sub(lowest_next) / 2;
+        }
+        for i in 0..count {
+            let shift = 64 - i - self.min_sym_len;
+            self.base64[i] = if shift >= 64 { 0 } else { self.base64[i] << shift };
+        }
+        at += count * 2;
+
+        let symbols = u16_le(bytes, at) as usize;
+        at += 2;
+        self.btree = at;
+        if bytes.len() < at + 3 * symbols {
+            return Err("The table is truncated".to_string());
+        }
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for symbol in 0..symbols {
+            if !visited[symbol] {
+                self.symlen[symbol] = self.set_symlen(bytes, symbol, &mut visited);
+            }
+        }
+        Ok(at + 3 * symbols + (symbols & 1))
+    }
+
+    // It decompresses the value stored at the given index
+    fn decompress(&self, bytes: &[u8], idx: u64) -> i32 {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return self.min_sym_len as i32;
+        }
+        let k = (idx / self.span) as usize;
+        let mut block = u32_le(bytes, self.sparse_index + 6 * k) as i64;
+        let mut offset = u16_le(bytes, self.sparse_index + 6 * k + 4) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+        let block_length = |block: i64| u16_le(bytes, self.block_length + 2 * block.max(0) as usize) as i64;
+        while offset < 0 && block > 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) && (block as usize) < self.block_length_size {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        let mut at = self.data + block as usize * self.block_size;
+        let mut buffer = u64_be(bytes, at);
+        at += 8;
+        let mut buffer_size = 64;
+        let symlen = |symbol: usize| self.symlen.get(symbol).copied().unwrap_or(0) as i64;
+        let mut symbol: usize;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buffer < self.base64[len] {
+                len += 1;
+            }
+            symbol = ((buffer - self.base64[len]) >> (64 - len - self.min_sym_len)) as usize;
+            symbol += u16_le(bytes, self.lowest_sym + 2 * len) as usize;
+            if offset < symlen(symbol) + 1 {
+                break;
+            }
+            offset -= symlen(symbol) + 1;
+            len += self.min_sym_len;
+            buffer <<= len;
+            buffer_size -= len;
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (u32_be(bytes, at) as u64) << (64 - buffer_size);
+                at += 4;
+            }
+        }
+
+        while symlen(symbol) != 0 {
+            let left = self.left(bytes, symbol);
+            if offset < symlen(left) + 1 {
+                symbol = left;
+            } else {
+                offset -= symlen(left) + 1;
+                symbol = self.right(bytes, symbol);
+            }
+        }
+        self.left(bytes, symbol) as i32
+    }
+}
+
+/// A table file, for pawnless material one table per side to move (one for DTZ files), with pawns
+/// one per side to move and file of the leading pawn.
+///
+/// Properties:
+///
+/// * `bytes`: The contents of the file.
+/// * `symmetric`: Whether both sides have the same material.
+/// * `has_pawns`: Whether there are pawns.
+/// * `has_unique_pieces`: Whether a side has a piece other than the king that it has only once.
+/// * `piece_count`: The number of pieces, kings included.
+/// * `pawn_count`: The pawns of the leading color, the one with fewer pawns, and of the other color.
+/// * `items`: The tables by side to move and file.
+/// * `map`: Where the value maps of a DTZ file start.
+struct Table {
+    bytes: Vec<u8>,
+    symmetric: bool,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    piece_count: usize,
+    pawn_count: [usize; 2],
+    items: [Vec<PairsData>; 2],
+    map: usize,
+}
+
+impl Table {
+    fn new(name: &str, bytes: Vec<u8>) -> Result<Table, String> {
+        let dtz = name.ends_with(".rtbz");
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.len() < 6 || bytes[0..4] != magic {
+            return Err(format!("{} is not a Syzygy table", name));
+        }
+        let material = &name[..name.len() - 5];
+        let (white, black) = material.split_once('v').ok_or(format!("{} is not a Syzygy table", name))?;
+        let count = |side: &str, letter: char| side.chars().filter(|&c| c == letter).count();
+        let white_pawns = count(white, 'P');
+        let black_pawns = count(black, 'P');
+        let leading_white = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let mut table = Table {
+            bytes: Vec::new(),
+            symmetric: white == black,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: "QRBNP".chars().any(|l| count(white, l) == 1 || count(black, l) == 1),
+            piece_count: white.len() + black.len(),
+            pawn_count: if leading_white { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] },
+            items: [Vec::new(), Vec::new()],
+            map: 0,
+        };
+        table.read(&bytes, dtz).map_err(|e| format!("{}: {}", name, e))?;
+        table.bytes = bytes;
+        Ok(table)
+    }
+
+    fn read(&mut self, bytes: &[u8], dtz: bool) -> Result<(), String> {
+        let mut at = 4;
+        let has_pawns_flag = bytes[at] & 2 != 0;
+        if has_pawns_flag != self.has_pawns {
+            return Err("The file does not match its material".to_string());
+        }
+        at += 1;
+        let sides = if !dtz && !self.symmetric { 2 } else { 1 };
+        let files = if self.has_pawns { 4 } else { 1 };
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        self.items = [vec![PairsData::default(); files], vec![PairsData::default(); files]];
+
+        for file in 0..files {
+            let first = *bytes.get(at).ok_or("The table is truncated")?;
+            let second = if both_pawns { *bytes.get(at + 1).ok_or("The table is truncated")? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            at += 1 + both_pawns as usize;
+            for k in 0..self.piece_count {
+                let byte = *bytes.get(at).ok_or("The table is truncated")?;
+                for side in 0..sides {
+                    self.items[side][file].pieces[k] = if side == 1 { byte >> 4 } else { byte & 0xF };
+                }
+                at += 1;
+            }
+            for (side, &order) in order.iter().enumerate().take(sides) {
+                self.set_groups(side, file, order);
+            }
+        }
+        at += at & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                at = self.items[side][file].set_sizes(bytes, at)?;
+            }
+        }
+        if dtz {
+            at = self.set_dtz_map(bytes, at, files);
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut self.items[side][file];
+                d.sparse_index = at;
+                at += d.sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut self.items[side][file];
+                d.block_length = at;
+                at += d.block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut self.items[side][file];
+                at = (at + 0x3F) & !0x3F;
+                d.data = at;
+                at += d.num_blocks * d.block_size;
+            }
+        }
+        if at > bytes.len() {
+            return Err("The table is truncated".to_string());
+        }
+        Ok(())
+    }
+
+    // The pieces are split into groups, the leading group first, then groups of equal pieces. The
+    // order the groups are multiplied in is stored in the file.
+    fn set_groups(&mut self, side: usize, file: usize, order: [u8; 2]) {
+        let maps = maps();
+        let (has_pawns, has_unique_pieces, piece_count) = (self.has_pawns, self.has_unique_pieces, self.piece_count);
+        let both_pawns = has_pawns && self.pawn_count[1] > 0;
+        let d = &mut self.items[side][file];
+
+        let mut n = 0;
+        let mut first_len: i32 = if has_pawns { 0 } else if has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+        for i in 1..piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                d.group_idx[0] = idx;
+                idx *= if has_pawns {
+                    maps.lead_pawns_size[d.group_len[0]][file]
+                } else if has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                d.group_idx[1] = idx;
+                idx *= maps.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= maps.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    // DTZ values can be stored through a map per outcome, to keep the number of symbols low
+    fn set_dtz_map(&mut self, bytes: &[u8], mut at: usize, files: usize) -> usize {
+        self.map = at;
+        for file in 0..files {
+            let flags = self.items[0][file].flags;
+            if flags & FLAG_MAPPED == 0 {
+                continue;
+            }
+            if flags & FLAG_WIDE != 0 {
+                at += at & 1;
+                for i in 0..4 {
+                    self.items[0][file].map_idx[i] = (at - self.map) / 2 + 1;
+                    at += 2 * u16_le(bytes, at) as usize + 2;
+                }
+            } else {
+                for i in 0..4 {
+                    self.items[0][file].map_idx[i] = at - self.map + 1;
+                    at += bytes.get(at).copied().unwrap_or(0) as usize + 1;
+                }
+            }
+        }
+        at + (at & 1)
+    }
+
+    // It turns a stored DTZ value into plies
+    fn map_score(&self, file: usize, value: i32, wdl: i32) -> i32 {
+        let d = &self.items[0][file];
+        let mut value = value;
+        if d.flags & FLAG_MAPPED != 0 {
+            let map_idx = d.map_idx[[1, 3, 0, 2, 0][(wdl + 2) as usize]];
+            value = if d.flags & FLAG_WIDE != 0 {
+                u16_le(&self.bytes, self.map + 2 * (map_idx + value as usize)) as i32
+            } else {
+                self.bytes.get(self.map + map_idx + value as usize).copied().unwrap_or(0) as i32
+            };
+        }
+        if (wdl == 2 && d.flags & FLAG_WIN_PLIES == 0) || (wdl == -2 && d.flags & FLAG_LOSS_PLIES == 0) || wdl.abs() == 1 {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tablebase() -> Tablebase {
+        Tablebase::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy")).unwrap()
+    }
+
+    fn position(fen: &str) -> GameState {
+        let mut gamestate = GameState::from_fen(fen).unwrap();
+        gamestate.debug_flag = false;
+        gamestate
+    }
+
+    #[test]
+    fn bundled_tables_are_found() {
+        let tablebase = tablebase();
+        assert_eq!(tablebase.table_count(), 12);
+        assert_eq!(tablebase.max_pieces, 4);
+    }
+
+    /// Positions whose outcome follows from chess itself, not from any table
+    const KNOWN_WDL: [(&str, Wdl); 11] = [
+        ("8/8/8/4k3/8/8/8/4K2Q w - - 0 1", Wdl::WIN),
+        ("8/8/8/4k3/8/8/8/4K2Q b - - 0 1", Wdl::LOSS),
+        // The queen is left next to the black king without protection
+        ("8/8/8/8/8/8/3kQ3/7K b - - 0 1", Wdl::DRAW),
+        ("k6R/8/1K6/8/8/8/8/8 b - - 0 1", Wdl::LOSS),
+        // Stalemate
+        ("k7/1R6/1K6/8/8/8/8/8 b - - 0 1", Wdl::DRAW),
+        ("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", Wdl::DRAW),
+        // The black king is outside the square of the pawn
+        ("8/8/8/8/8/8/4P3/K6k w - - 0 1", Wdl::WIN),
+        ("8/8/8/8/8/3k4/4P3/K7 b - - 0 1", Wdl::DRAW),
+        ("8/8/8/4k3/8/8/8/2B1K1N1 w - - 0 1", Wdl::WIN),
+        ("8/8/8/4k3/8/8/8/2B1K1N1 b - - 0 1", Wdl::LOSS),
+        ("8/8/8/3k4/3B4/8/8/K5N1 b - - 0 1", Wdl::DRAW),
+    ];
+
+    const KNOWN_DTZ: [(&str, i32); 5] = [
+        // Mate in one and the mate itself
+        ("k7/8/1K6/8/8/8/8/7R w - - 0 1", 1),
+        ("k6R/8/1K6/8/8/8/8/8 b - - 0 1", -1),
+        ("k7/1R6/1K6/8/8/8/8/8 b - - 0 1", 0),
+        // A pawn move zeroes the counter at once
+        ("8/8/8/8/8/8/4P3/K6k w - - 0 1", 1),
+        ("8/8/8/8/8/3k4/4P3/K7 b - - 0 1", 0),
+    ];
+
+    /// It checks the known positions, official tables may round a distance up by one ply
+    fn check_known_positions(tablebase: &Tablebase, slack: i32) {
+        for (fen, wdl) in KNOWN_WDL {
+            assert_eq!(tablebase.probe_wdl(&position(fen)).unwrap(), wdl, "{}", fen);
+        }
+        for (fen, dtz) in KNOWN_DTZ {
+            let probed = tablebase.probe_dtz(&position(fen)).unwrap();
+            assert!(probed.signum() == dtz.signum() && (probed - dtz).abs() <= slack, "{}: {} instead of {}", fen, probed, dtz);
+        }
+    }
+
+    #[test]
+    fn wdl_and_dtz_of_known_positions() {
+        let tablebase = tablebase();
+        check_known_positions(&tablebase, 0);
+        assert_eq!(tablebase.best_move(&position("k7/8/1K6/8/8/8/8/7R w - - 0 1")).unwrap(), Some(((7, 63), 1)));
+    }
+
+    /// The bundled tables were generated for this tree. With SYZYGY_OFFICIAL_PATH pointing at a
+    /// directory holding the official KQvK, KRvK, KPvK and KBNvK tables, they are checked against the
+    /// known positions and compared with the bundled ones on every placement of the pieces along a few
+    /// lines of the board.
+    #[test]
+    fn official_tables_agree_with_the_bundled_ones() {
+        let directory = match std::env::var("SYZYGY_OFFICIAL_PATH") {
+            Ok(directory) => directory,
+            Err(_) => return
+        };
+        let official = Tablebase::open(&directory).unwrap();
+        check_known_positions(&official, 1);
+        let bundled = tablebase();
+        for (piece, rows) in [('Q', 1..7), ('R', 1..7), ('P', 1..7)] {
+            for white_king in [0, 9, 27, 36] {
+                for black_king in [7, 18, 45, 63] {
+                    for square in (rows.start * 8..rows.end * 8).step_by(3) {
+                        if [white_king, black_king].contains(&square) || GameState::abs_diff_x(white_king, black_king) <= 1 && GameState::abs_diff_y(white_king, black_king) <= 1 {
+                            continue;
+                        }
+                        let mut board = ['1'; 64];
+                        board[white_king] = 'K';
+                        board[black_king] = 'k';
+                        board[square] = piece;
+                        for side in ["w", "b"] {
+                            let rows: Vec<String> = (0..8).rev().map(|row| board[row * 8..row * 8 + 8].iter().collect()).collect();
+                            let gamestate = match GameState::from_fen(&format!("{} {} - - 0 1", rows.join("/"), side)) {
+                                Ok(mut gamestate) => {
+                                    gamestate.debug_flag = false;
+                                    gamestate
+                                },
+                                Err(_) => continue
+                            };
+                            let fen = gamestate.to_fen();
+                            assert_eq!(official.probe_wdl(&gamestate).unwrap(), bundled.probe_wdl(&gamestate).unwrap(), "{}", fen);
+                            let (a, b) = (official.probe_dtz(&gamestate).unwrap(), bundled.probe_dtz(&gamestate).unwrap());
+                            assert!(a.signum() == b.signum() && (a - b).abs() <= 1, "{}: official {} bundled {}", fen, a, b);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Playing the best moves of both sides the distance goes down one ply at a time until the mate
+    fn play_out(fen: &str, longest: i32) {
+        let tablebase = tablebase();
+        let mut gamestate = position(fen);
+        let mut dtz = tablebase.probe_dtz(&gamestate).unwrap();
+        assert!(dtz > 0 && dtz <= longest, "{} has a distance of {}", fen, dtz);
+        while let Some(((pc, tc), move_dtz)) = tablebase.best_move(&gamestate).unwrap() {
+            assert_eq!(move_dtz, dtz, "{}", gamestate.to_fen());
+            gamestate.make_move(pc, tc);
+            let next = tablebase.probe_dtz(&gamestate).unwrap();
+            assert_eq!(next, -dtz.signum() * (dtz.abs() - 1).max(1), "{}", gamestate.to_fen());
+            dtz = next;
+        }
+        assert!(gamestate.checked_flag);
+        assert_eq!(tablebase.probe_wdl(&gamestate).unwrap(), Wdl::LOSS);
+    }
+
+    #[test]
+    fn dtz_counts_down_to_the_mate() {
+        play_out("8/8/8/4k3/8/8/8/4K2Q w - - 0 1", 19);
+        play_out("8/8/8/3k4/8/8/8/R3K3 w - - 0 1", 31);
+    }
+}
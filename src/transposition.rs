@@ -0,0 +1,271 @@
+use crate::gamestate::GameState;
+use crate::piece::{Color, PieceType};
+use crate::variant::VariantKind;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// The kind of score stored in the table.
+///
+/// * `EXACT`: The score is exact.
+/// * `LOWER`: The search failed high, the score is at least this.
+/// * `UPPER`: The search failed low, the score is at most this.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Bound {
+    EXACT,
+    LOWER,
+    UPPER
+}
+
+/// What the table remembers about a position.
+///
+/// Properties:
+///
+/// * `best_move`: The best move found as (piece coordinate, target coordinate), if any.
+/// * `depth`: The depth the position was searched to.
+/// * `score`: The score found by the search.
+/// * `bound`: Whether the score is exact or a bound.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct TableData {
+    pub best_move: Option<(usize, usize)>,
+    pub depth: u32,
+    pub score: i32,
+    pub bound: Bound,
+}
+
+impl TableData {
+    fn pack(&self) -> u64 {
+        let mut data: u64 = 0;
+        if let Some((pc, tc)) = self.best_move {
+            data |= (pc as u64) | ((tc as u64) << 6) | (1 << 12);
+        }
+        data |= (self.depth.min(255) as u64) << 13;
+        data |= match self.bound {
+            Bound::EXACT => 0,
+            Bound::LOWER => 1,
+            Bound::UPPER => 2
+        } << 21;
+        data |= (self.score as u32 as u64) << 32;
+        data
+    }
+
+    fn unpack(data: u64) -> TableData {
+        let best_move = if data & (1 << 12) != 0 {
+            Some(((data & 63) as usize, ((data >> 6) & 63) as usize))
+        } else {
+            None
+        };
+        let bound = match (data >> 21) & 3 {
+            0 => Bound::EXACT,
+            1 => Bound::LOWER,
+            _ => Bound::UPPER
+        };
+        TableData {
+            best_move,
+            depth: ((data >> 13) & 255) as u32,
+            score: (data >> 32) as u32 as i32,
+            bound,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TableEntry {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+/// A transposition table that can be shared between search threads without locking.
+///
+/// Every entry is stored as two atomic words, the data and the hash xor'ed with the data. An entry
+/// torn by two threads writing at the same time no longer matches its hash and is simply ignored.
+pub struct TranspositionTable {
+    entries: Vec<TableEntry>,
+}
+
+impl TranspositionTable {
+/// It creates an empty table using about `megabytes` of memory
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        let count = (megabytes.max(1) * 1024 * 1024 / std::mem::size_of::<TableEntry>()).max(1);
+        TranspositionTable {
+            entries: (0..count).map(|_| TableEntry::default()).collect(),
+        }
+    }
+
+/// It empties the table
+    pub fn clear(&self) {
+        for entry in self.entries.iter() {
+            entry.key.store(0, Ordering::Relaxed);
+            entry.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+/// It looks up the position with the given hash
+///
+/// Returns:
+///
+/// The stored data, None if the position is not in the table.
+    pub fn probe(&self, hash: u64) -> Option<TableData> {
+        let entry = &self.entries[(hash % self.entries.len() as u64) as usize];
+        let data = entry.data.load(Ordering::Relaxed);
+        let key = entry.key.load(Ordering::Relaxed);
+        if key ^ data == hash && data != 0 {
+            Some(TableData::unpack(data))
+        } else {
+            None
+        }
+    }
+
+/// It stores the data for the position with the given hash, keeping a deeper search of the same
+/// position
+    pub fn store(&self, hash: u64, data: TableData) {
+        let entry = &self.entries[(hash % self.entries.len() as u64) as usize];
+        if let Some(old) = self.probe(hash) {
+            if old.depth > data.depth && data.bound != Bound::EXACT {
+                return;
+            }
+        }
+        let packed = data.pack();
+        entry.key.store(hash ^ packed, Ordering::Relaxed);
+        entry.data.store(packed, Ordering::Relaxed);
+    }
+}
+
+const PIECE_KINDS: usize = 18;
+// A pocket holds at most the 16 pieces of the opponent
+const POCKET_COUNTS: usize = 17;
+const VARIANT_KEYS: usize = PIECE_KINDS * 64 + 9;
+const POCKET_KEYS: usize = VARIANT_KEYS + VariantKind::ALL.len();
+const CHECK_KEYS: usize = POCKET_KEYS + 2 * 5 * POCKET_COUNTS;
+const PROMOTED_KEYS: usize = CHECK_KEYS + 2 * 4;
+
+fn zobrist_keys() -> &'static Vec<u64> {
+    static KEYS: OnceLock<Vec<u64>> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        // splitmix64, the keys only have to be the same from one run to the next
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        (0..PROMOTED_KEYS + 64).map(|_| {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }).collect()
+    })
+}
+
+/// It computes the Zobrist hash of the position, including the side to move, the en passant file when
+/// the capture is possible and whether kings, rooks and pawns have moved, as these decide castling and
+/// double steps. The variant, the Crazyhouse pockets and promoted pieces and the checks given in
+/// Three-check are hashed too, so positions that only differ in those never share an entry
+///
+/// Arguments:
+///
+/// * `gamestate`: The position to hash.
+///
+/// Returns:
+///
+/// A 64 bit hash of the position.
+pub fn position_hash(gamestate: &GameState) -> u64 {
+    let keys = zobrist_keys();
+    let mut hash: u64 = 0;
+    for i in 0..64 {
+        if let Some(piece) = gamestate.board[i] {
+            let kind = match piece.piecetype {
+                PieceType::PAWN(moved) => moved as usize,
+                PieceType::KNIGHT => 2,
+                PieceType::BISHOP => 3,
+                PieceType::ROOK(moved) => 4 + moved as usize,
+                PieceType::QUEEN => 6,
+                PieceType::KING(moved) => 7 + moved as usize,
+                PieceType::NONE => continue
+            };
+            let color = match piece.color {
+                Color::BLACK => PIECE_KINDS / 2,
+                _ => 0
+            };
+            hash ^= keys[(kind + color) * 64 + i];
+        }
+    }
+    if gamestate.current_player == Color::BLACK {
+        hash ^= keys[PIECE_KINDS * 64];
+    }
+    if let Some(file) = gamestate.en_passant_file() {
+        hash ^= keys[PIECE_KINDS * 64 + 1 + file];
+    }
+    if gamestate.variant != VariantKind::STANDARD {
+        let index = VariantKind::ALL.iter().position(|variant| *variant == gamestate.variant).unwrap_or(0);
+        hash ^= keys[VARIANT_KEYS + index];
+    }
+    for color in 0..2 {
+        for kind in 0..5 {
+            let count = gamestate.pockets[color][kind] as usize;
+            if count > 0 {
+                hash ^= keys[POCKET_KEYS + (color * 5 + kind) * POCKET_COUNTS + count.min(POCKET_COUNTS - 1)];
+            }
+        }
+        let checks = gamestate.checks_given[color] as usize;
+        if checks > 0 {
+            hash ^= keys[CHECK_KEYS + color * 4 + checks.min(3)];
+        }
+    }
+    let mut promoted = gamestate.promoted;
+    while promoted != 0 {
+        hash ^= keys[PROMOTED_KEYS + promoted.trailing_zeros() as usize];
+        promoted &= promoted - 1;
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn en_passant_file_is_hashed_only_when_the_capture_is_possible() {
+        let capturable = GameState::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+        let mut without = capturable;
+        without.en_passant = None;
+        assert_eq!(capturable.en_passant_file(), Some(3));
+        assert_ne!(position_hash(&capturable), position_hash(&without));
+
+        let not_capturable = GameState::from_fen("4k3/8/8/3p4/8/8/8/4K3 w - d6 0 2").unwrap();
+        let mut without = not_capturable;
+        without.en_passant = None;
+        assert_eq!(not_capturable.en_passant_file(), None);
+        assert_eq!(position_hash(&not_capturable), position_hash(&without));
+    }
+
+    #[test]
+    fn pockets_checks_promotions_and_variant_are_hashed() {
+        let position = GameState::from_fen("r3k3/8/8/8/8/8/8/4K2Q w - - 0 1").unwrap();
+        let base = position_hash(&position);
+
+        let mut pocket = position;
+        pocket.variant = VariantKind::CRAZYHOUSE;
+        let crazyhouse = position_hash(&pocket);
+        assert_ne!(base, crazyhouse);
+        pocket.pockets[0][0] = 1;
+        assert_ne!(crazyhouse, position_hash(&pocket));
+        let mut other_pocket = pocket;
+        other_pocket.pockets[0][0] = 2;
+        assert_ne!(position_hash(&pocket), position_hash(&other_pocket));
+        let mut black_pocket = position;
+        black_pocket.variant = VariantKind::CRAZYHOUSE;
+        black_pocket.pockets[1][0] = 1;
+        assert_ne!(position_hash(&pocket), position_hash(&black_pocket));
+
+        let mut promoted = pocket;
+        promoted.promoted = 1 << 7;
+        assert_ne!(position_hash(&pocket), position_hash(&promoted));
+
+        let mut checks = position;
+        checks.variant = VariantKind::THREECHECK;
+        let three_check = position_hash(&checks);
+        assert_ne!(base, three_check);
+        checks.checks_given[0] = 1;
+        assert_ne!(three_check, position_hash(&checks));
+        let mut black_checks = checks;
+        black_checks.checks_given = [0, 1];
+        assert_ne!(position_hash(&checks), position_hash(&black_checks));
+    }
+}
@@ -0,0 +1,533 @@
+use crate::clock::Clock;
+use crate::eco::EcoDatabase;
+use crate::game::{format_move, Game};
+use crate::gamestate::{GameState, MoveKind};
+use crate::piece::{Color, PieceType};
+use crate::search::Engine;
+use crate::variant::Outcome;
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, MouseButton, MouseEventKind};
+use ratatui::crossterm::execute;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color as TermColor, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Widget};
+use std::cell::Cell;
+use std::time::Duration;
+
+const WHITE: Color = Color::WHITE;
+const BLACK: Color = Color::BLACK;
+
+/// Width of a tile in terminal columns
+const TILE_WIDTH: u16 = 3;
+/// Columns left of the tiles for the row numbers
+const LABEL_WIDTH: u16 = 2;
+
+const LIGHT_TILE: TermColor = TermColor::Rgb(240, 217, 181);
+const DARK_TILE: TermColor = TermColor::Rgb(181, 136, 99);
+const CURSOR_TILE: TermColor = TermColor::Rgb(90, 140, 220);
+const SELECTED_TILE: TermColor = TermColor::Rgb(230, 200, 60);
+const TARGET_TILE: TermColor = TermColor::Rgb(120, 190, 90);
+const CHECKED_TILE: TermColor = TermColor::Rgb(220, 70, 60);
+
+/// The state of the full-screen interface: the game being played and where the user is pointing.
+///
+/// Properties:
+///
+/// * `gamestate`: The current position.
+/// * `game`: The moves played so far, for the move list and the captured pieces.
+/// * `clock`: The clock, if the game is played with one.
+/// * `cursor`: The tile the arrow keys point at.
+/// * `selected`: The tile of the piece picked up to be moved.
+/// * `targets`: The tiles the selected piece may move to.
+/// * `message`: The line shown below the status, such as the last move or why a move was refused.
+/// * `finished`: True once the game has ended, no more moves are accepted.
+/// * `openings`: Names the opening of the game after every move, if loaded.
+/// * `board_area`: Where the tiles were drawn last, to find the tile under the mouse.
+pub struct Tui {
+    pub gamestate: GameState,
+    pub game: Game,
+    pub clock: Option<Clock>,
+    pub cursor: usize,
+    pub selected: Option<usize>,
+    pub targets: Vec<usize>,
+    pub message: String,
+    pub finished: bool,
+    pub openings: Option<EcoDatabase>,
+    board_area: Cell<Rect>,
+}
+
+impl Tui {
+/// It creates the interface for a game, with the cursor on the king of the player in turn
+    pub fn new(gamestate: GameState, game: Game, clock: Option<Clock>) -> Tui {
+        let king = if gamestate.current_player == WHITE { gamestate.wkc } else { gamestate.bkc };
+        Tui {
+            gamestate,
+            game,
+            clock,
+            cursor: if king < 64 { king } else { 4 },
+            selected: None,
+            targets: Vec::new(),
+            message: "Enter or click to move, g: engine move, q: leave".to_string(),
+            finished: false,
+            openings: None,
+            board_area: Cell::new(Rect::default()),
+        }
+    }
+
+/// It reacts to a key press
+///
+/// Arguments:
+///
+/// * `key`: The key that was pressed.
+/// * `engine`: The engine that answers 'g'.
+///
+/// Returns:
+///
+/// False when the user wants to leave the interface.
+    pub fn handle_key(&mut self, key: KeyCode, engine: &Engine) -> bool {
+        let (x, y) = (GameState::coord_x(self.cursor), GameState::coord_y(self.cursor));
+        match key {
+            KeyCode::Up if y < 7 => self.cursor += 8,
+            KeyCode::Down if y > 0 => self.cursor -= 8,
+            KeyCode::Right if x < 7 => self.cursor += 1,
+            KeyCode::Left if x > 0 => self.cursor -= 1,
+            KeyCode::Enter | KeyCode::Char(' ') => self.activate(self.cursor),
+            KeyCode::Esc => {
+                self.selected = None;
+                self.targets.clear();
+            },
+            KeyCode::Char('g') => self.engine_move(engine),
+            KeyCode::Char('q') => return false,
+            _ => {}
+        }
+        true
+    }
+
+/// It reacts to a click of the left mouse button on the given terminal cell, picking up or moving a
+/// piece if the cell is part of a tile
+    pub fn handle_click(&mut self, column: u16, row: u16) {
+        if let Some(tile) = self.tile_at(column, row) {
+            self.cursor = tile;
+            self.activate(tile);
+        }
+    }
+
+/// It finds the tile drawn at the given terminal cell in the last frame
+    pub fn tile_at(&self, column: u16, row: u16) -> Option<usize> {
+        let area = self.board_area.get();
+        if column < area.x || row < area.y || column >= area.x + 8 * TILE_WIDTH || row >= area.y + 8 {
+            return None;
+        }
+        let x = ((column - area.x) / TILE_WIDTH) as usize;
+        let y = 7 - (row - area.y) as usize;
+        Some(y * 8 + x)
+    }
+
+/// It selects the piece on the tile, or moves the selected piece there if the tile is one of its
+/// targets
+    fn activate(&mut self, tile: usize) {
+        if self.finished {
+            return;
+        }
+        if let Some(pc) = self.selected.take() {
+            let targets = std::mem::take(&mut self.targets);
+            if targets.contains(&tile) {
+                self.play(pc, tile);
+                return;
+            }
+            if pc == tile {
+                return;
+            }
+        }
+        match self.gamestate.board[tile] {
+            Some(piece) if piece.color == self.gamestate.current_player => {
+                let legal = self.gamestate.legal_moves();
+                self.targets = self.gamestate.get_moves_from_tile(tile).into_iter().filter(|&tc| legal.contains(&(tile, tc))).collect();
+                if self.targets.is_empty() {
+                    self.message = format!("The piece on {} can not move", GameState::coordinate_translator_usize(tile));
+                } else {
+                    self.selected = Some(tile);
+                }
+            },
+            _ => {}
+        }
+    }
+
+/// It plays a legal move, records it, presses the clock and checks whether the game is over
+    fn play(&mut self, pc: usize, tc: usize) {
+        let text = format_move(&self.gamestate, MoveKind::BOARD(pc, tc));
+        let player = self.gamestate.current_player;
+        self.gamestate.make_move(pc, tc);
+        self.game.record(MoveKind::BOARD(pc, tc));
+        if let Some(openings) = &self.openings {
+            openings.tag(&mut self.game);
+        }
+        self.message = format!("{:?} played {}", player, text);
+        self.cursor = tc;
+        if let Some(clock) = self.clock.as_mut() {
+            let flagged = clock.press();
+            if flagged != Color::UNCOLORED {
+                self.message = format!("{:?} ran out of time, {:?} wins!", flagged, flagged.opposite());
+                self.finished = true;
+                return;
+            }
+        }
+        match self.gamestate.outcome() {
+            Some(Outcome::WIN(winner)) => {
+                self.message = format!("{} - {:?} wins!", self.message, winner);
+                self.finished = true;
+            },
+            Some(Outcome::DRAW) => {
+                self.message = format!("{} - Draw!", self.message);
+                self.finished = true;
+            },
+            None => {}
+        }
+    }
+
+/// It lets the engine search the position and plays its move, thinking as long as the clock allows
+/// or 3 plies without a clock
+    fn engine_move(&mut self, engine: &Engine) {
+        if self.finished {
+            return;
+        }
+        self.selected = None;
+        self.targets.clear();
+        let result = match &self.clock {
+            Some(clock) => engine.search_timed(&self.gamestate, clock.think_time(self.gamestate.current_player), 64),
+            None => engine.search(&self.gamestate, 3)
+        };
+        match result.best_move {
+            Some((pc, tc)) => self.play(pc, tc),
+            None => self.message = "No legal moves!".to_string()
+        }
+    }
+
+/// It checks the clock of the player in turn, ending the game when their time ran out
+    pub fn tick(&mut self) {
+        if let Some(clock) = self.clock.as_mut() {
+            let flagged = clock.check_flag();
+            if flagged != Color::UNCOLORED && !self.finished {
+                self.message = format!("{:?} ran out of time, {:?} wins!", flagged, flagged.opposite());
+                self.finished = true;
+            }
+        }
+    }
+
+/// It replays the game to find the pieces each player has captured
+///
+/// Returns:
+///
+/// The pieces taken by White and by Black, in the order they were taken.
+    pub fn captured(&self) -> [Vec<PieceType>; 2] {
+        let mut captured: [Vec<PieceType>; 2] = [Vec::new(), Vec::new()];
+        let mut gamestate = self.game.start;
+        for &mv in &self.game.moves {
+            let player = gamestate.current_player;
+            let before = piece_counts(&gamestate, player.opposite());
+            gamestate.make_move_kind(mv);
+            let after = piece_counts(&gamestate, player.opposite());
+            for (i, piecetype) in PIECE_ORDER.iter().enumerate() {
+                for _ in after[i]..before[i] {
+                    captured[GameState::pocket_owner(player)].push(*piecetype);
+                }
+            }
+        }
+        captured
+    }
+
+/// It lists the moves of the game numbered in pairs, such as "1. e2e4 e7e5"
+    pub fn move_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        let mut gamestate = self.game.start;
+        for &mv in &self.game.moves {
+            let text = format_move(&gamestate, mv);
+            if gamestate.current_player == WHITE || lines.is_empty() {
+                let dots = if gamestate.current_player == WHITE { "." } else { "..." };
+                lines.push(format!("{:>3}{} {}", gamestate.fullmove_number, dots, text));
+            } else if let Some(line) = lines.last_mut() {
+                line.push(' ');
+                line.push_str(&text);
+            }
+            gamestate.make_move_kind(mv);
+        }
+        lines
+    }
+
+/// It draws the interface into a buffer of the given size, which is how a frame can be inspected
+/// without a terminal
+    pub fn render_to_buffer(&self, width: u16, height: u16) -> Buffer {
+        let mut buffer = Buffer::empty(Rect::new(0, 0, width, height));
+        self.render(buffer.area, &mut buffer);
+        buffer
+    }
+
+/// It draws the tiles with their pieces, the row numbers on the left and the file letters below
+    fn render_board(&self, area: Rect, buf: &mut Buffer) {
+        let tiles = Rect::new(area.x + LABEL_WIDTH, area.y, (8 * TILE_WIDTH).min(area.width.saturating_sub(LABEL_WIDTH)), 8.min(area.height));
+        self.board_area.set(tiles);
+        let checked_king = match (self.gamestate.checked_flag, self.gamestate.checked_player) {
+            (true, WHITE) => self.gamestate.wkc,
+            (true, BLACK) => self.gamestate.bkc,
+            _ => 64
+        };
+        for row in 0..8u16 {
+            if row >= area.height {
+                break;
+            }
+            let y = 7 - row as usize;
+            buf.set_string(area.x, area.y + row, format!("{} ", y + 1), Style::default());
+            for x in 0..8usize {
+                let tile = y * 8 + x;
+                let background = if tile == self.cursor {
+                    CURSOR_TILE
+                } else if Some(tile) == self.selected {
+                    SELECTED_TILE
+                } else if self.targets.contains(&tile) {
+                    TARGET_TILE
+                } else if tile == checked_king {
+                    CHECKED_TILE
+                } else if (x + y) % 2 == 1 {
+                    LIGHT_TILE
+                } else {
+                    DARK_TILE
+                };
+                let (glyph, foreground) = match self.gamestate.board[tile] {
+                    Some(piece) => (glyph(piece.piecetype), if piece.color == WHITE { TermColor::White } else { TermColor::Black }),
+                    None if self.targets.contains(&tile) => ('·', TermColor::Black),
+                    None => (' ', TermColor::Black)
+                };
+                let column = tiles.x + x as u16 * TILE_WIDTH;
+                if column + TILE_WIDTH <= area.x + area.width {
+                    let style = Style::default().fg(foreground).bg(background).add_modifier(Modifier::BOLD);
+                    buf.set_string(column, area.y + row, format!(" {} ", glyph), style);
+                }
+            }
+        }
+        if area.height > 8 {
+            let files: String = "abcdefgh".chars().map(|file| format!(" {} ", file)).collect();
+            buf.set_stringn(tiles.x, area.y + 8, files, tiles.width as usize, Style::default());
+        }
+    }
+
+/// It describes the game for the status panel: the variant, the opening, the player in turn, check and
+/// the clocks
+    fn status_lines(&self) -> Vec<Line<'static>> {
+        let mut lines = vec![
+            Line::from(format!("Variant: {}{}", self.gamestate.variant.rules().name(), if self.gamestate.chess960 { " (Chess960)" } else { "" })),
+            Line::from(format!("{} vs {}", self.game.white, self.game.black)),
+        ];
+        if let (Some(eco), Some(name)) = (self.game.metadata.get("ECO"), self.game.metadata.get("Opening")) {
+            lines.push(Line::from(format!("Opening: {} {}", eco, name)));
+        }
+        let turn = if self.finished { "Game over".to_string() } else { format!("{:?} to move", self.gamestate.current_player) };
+        if self.gamestate.checked_flag && !self.finished {
+            lines.push(Line::styled(format!("{}, check!", turn), Style::default().fg(CHECKED_TILE)));
+        } else {
+            lines.push(Line::from(turn));
+        }
+        if let Some(clock) = &self.clock {
+            lines.push(Line::from(format!("White {}  Black {}", clock.format_remaining(WHITE), clock.format_remaining(BLACK))));
+        }
+        if self.gamestate.variant.rules().has_pockets() {
+            let pocket = |color: Color| -> String {
+                self.gamestate.pockets[GameState::pocket_owner(color)].iter().zip("PNBRQ".chars())
+                    .flat_map(|(count, letter)| std::iter::repeat_n(letter, *count as usize)).collect()
+            };
+            lines.push(Line::from(format!("Pockets: White [{}]  Black [{}]", pocket(WHITE), pocket(BLACK))));
+        }
+        lines.push(Line::from(self.message.clone()));
+        lines
+    }
+}
+
+impl Widget for &Tui {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let [board_area, side_area] = Layout::horizontal([Constraint::Length(LABEL_WIDTH + 8 * TILE_WIDTH + 2), Constraint::Min(20)]).areas(area);
+        let board_block = Block::bordered().title(" Board ");
+        let inner = board_block.inner(board_area);
+        board_block.render(board_area, buf);
+        self.render_board(inner, buf);
+
+        let status = self.status_lines();
+        let [status_area, captured_area, moves_area] = Layout::vertical([
+            Constraint::Length(status.len() as u16 + 2),
+            Constraint::Length(4),
+            Constraint::Min(3)
+        ]).areas(side_area);
+        Paragraph::new(status).block(Block::bordered().title(" Status ")).render(status_area, buf);
+
+        let captured = self.captured();
+        let pieces = |pieces: &Vec<PieceType>| -> String { pieces.iter().map(|piecetype| glyph(*piecetype)).collect() };
+        Paragraph::new(vec![
+            Line::from(format!("White: {}", pieces(&captured[0]))),
+            Line::from(format!("Black: {}", pieces(&captured[1]))),
+        ]).block(Block::bordered().title(" Captured ")).render(captured_area, buf);
+
+        let moves = self.move_lines();
+        let visible = moves_area.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = moves.into_iter().rev().take(visible).rev().map(Line::from).collect();
+        Paragraph::new(lines).block(Block::bordered().title(" Moves ")).render(moves_area, buf);
+    }
+}
+
+/// The piece types in the order the captured pieces are counted
+const PIECE_ORDER: [PieceType; 6] = [PieceType::PAWN(true), PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK(true), PieceType::QUEEN, PieceType::KING(true)];
+
+/// It counts the pieces of a color on the board, in the order of PIECE_ORDER
+fn piece_counts(gamestate: &GameState, color: Color) -> [usize; 6] {
+    let mut counts = [0; 6];
+    for piece in gamestate.board.iter().flatten().filter(|piece| piece.color == color) {
+        let index = match piece.piecetype {
+            PieceType::PAWN(_) => 0,
+            PieceType::KNIGHT => 1,
+            PieceType::BISHOP => 2,
+            PieceType::ROOK(_) => 3,
+            PieceType::QUEEN => 4,
+            PieceType::KING(_) => 5,
+            PieceType::NONE => continue
+        };
+        counts[index] += 1;
+    }
+    counts
+}
+
+/// The filled chess symbol of a piece type, the color comes from the text color
+fn glyph(piecetype: PieceType) -> char {
+    match piecetype {
+        PieceType::PAWN(_) => '♟',
+        PieceType::KNIGHT => '♞',
+        PieceType::BISHOP => '♝',
+        PieceType::ROOK(_) => '♜',
+        PieceType::QUEEN => '♛',
+        PieceType::KING(_) => '♚',
+        PieceType::NONE => '?'
+    }
+}
+
+/// It takes over the terminal and lets the user play the game until they press 'q', then hands the
+/// game back to the line-based client
+///
+/// Arguments:
+///
+/// * `gamestate`: The position, updated with the moves played in the interface.
+/// * `game`: The game record, updated the same way.
+/// * `clock`: The clock, if any.
+/// * `engine`: The engine that plays when 'g' is pressed.
+/// * `openings`: Names the opening in the status panel, if loaded.
+pub fn run(gamestate: &mut GameState, game: &mut Game, clock: &mut Option<Clock>, engine: &Engine, openings: Option<&EcoDatabase>) -> Result<(), String> {
+    let mut terminal = ratatui::try_init().map_err(|e| format!("Could not start the interface: {}", e))?;
+    let _ = execute!(std::io::stdout(), EnableMouseCapture);
+    let mut tui = Tui::new(*gamestate, game.clone(), clock.take());
+    tui.openings = openings.cloned();
+    let result = event_loop(&mut terminal, &mut tui, engine);
+    let _ = execute!(std::io::stdout(), DisableMouseCapture);
+    ratatui::restore();
+    *gamestate = tui.gamestate;
+    *game = tui.game;
+    *clock = tui.clock;
+    result
+}
+
+/// It draws a frame and waits for input, redrawing at least every 200 ms so the clocks keep running
+fn event_loop(terminal: &mut ratatui::DefaultTerminal, tui: &mut Tui, engine: &Engine) -> Result<(), String> {
+    loop {
+        tui.tick();
+        terminal.draw(|frame| frame.render_widget(&*tui, frame.area())).map_err(|e| e.to_string())?;
+        if !event::poll(Duration::from_millis(200)).map_err(|e| e.to_string())? {
+            continue;
+        }
+        match event::read().map_err(|e| e.to_string())? {
+            Event::Key(key) if key.kind == KeyEventKind::Press && !tui.handle_key(key.code, engine) => return Ok(()),
+            Event::Mouse(mouse) if mouse.kind == MouseEventKind::Down(MouseButton::Left) => tui.handle_click(mouse.column, mouse.row),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_text(buffer: &Buffer, y: u16) -> String {
+        (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect()
+    }
+
+    fn screen_text(buffer: &Buffer) -> String {
+        (0..buffer.area.height).map(|y| row_text(buffer, y)).collect::<Vec<String>>().join("\n")
+    }
+
+    /// The terminal cell showing the piece of a tile, with the board drawn at the top left
+    fn tile_cell(tile: usize) -> (u16, u16) {
+        let (x, y) = (GameState::coord_x(tile) as u16, GameState::coord_y(tile) as u16);
+        (1 + LABEL_WIDTH + x * TILE_WIDTH + 1, 1 + 7 - y)
+    }
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn board_cells_show_the_pieces_and_labels() {
+        let tui = Tui::new(GameState::new(), Game::new(GameState::new()), None);
+        let buffer = tui.render_to_buffer(80, 24);
+
+        let a8 = &buffer[tile_cell(square("a8"))];
+        assert_eq!(a8.symbol(), "♜");
+        assert_eq!(a8.fg, TermColor::Black);
+        assert_eq!(a8.bg, LIGHT_TILE);
+        let d1 = &buffer[tile_cell(square("d1"))];
+        assert_eq!(d1.symbol(), "♛");
+        assert_eq!(d1.fg, TermColor::White);
+        assert_eq!(buffer[tile_cell(square("a1"))].bg, DARK_TILE);
+        assert_eq!(buffer[tile_cell(square("e4"))].symbol(), " ");
+        assert!(row_text(&buffer, 1).starts_with("│8 "));
+        assert!(row_text(&buffer, 9).starts_with("│   a  b  c  d  e  f  g  h"));
+    }
+
+    #[test]
+    fn cursor_selection_and_targets_are_highlighted() {
+        let mut tui = Tui::new(GameState::new(), Game::new(GameState::new()), None);
+        let buffer = tui.render_to_buffer(80, 24);
+        let e1 = &buffer[tile_cell(square("e1"))];
+        assert_eq!(e1.symbol(), "♚");
+        assert_eq!(e1.bg, CURSOR_TILE);
+
+        let (column, row) = tile_cell(square("e2"));
+        tui.handle_click(column, row);
+        let buffer = tui.render_to_buffer(80, 24);
+        assert_eq!(buffer[tile_cell(square("e2"))].bg, CURSOR_TILE);
+        for target in ["e3", "e4"] {
+            assert_eq!(buffer[tile_cell(square(target))].bg, TARGET_TILE, "{}", target);
+            assert_eq!(buffer[tile_cell(square(target))].symbol(), "·");
+        }
+        assert_eq!(buffer[tile_cell(square("e5"))].symbol(), " ");
+
+        tui.cursor = square("h8");
+        let buffer = tui.render_to_buffer(80, 24);
+        assert_eq!(buffer[tile_cell(square("e2"))].bg, SELECTED_TILE);
+        assert_eq!(buffer[tile_cell(square("h8"))].bg, CURSOR_TILE);
+    }
+
+    #[test]
+    fn status_panel_follows_the_game() {
+        let mut tui = Tui::new(GameState::new(), Game::new(GameState::new()), None);
+        let text = screen_text(&tui.render_to_buffer(80, 24));
+        assert!(text.contains("Variant: standard"));
+        assert!(text.contains("White vs Black"));
+        assert!(text.contains("WHITE to move"));
+
+        for tile in ["e2", "e4"] {
+            let (column, row) = tile_cell(square(tile));
+            tui.handle_click(column, row);
+        }
+        let buffer = tui.render_to_buffer(80, 24);
+        let text = screen_text(&buffer);
+        assert!(text.contains("BLACK to move"));
+        assert!(text.contains("WHITE played e2e4"));
+        assert!(text.contains("1. e2e4"));
+        assert_eq!(buffer[tile_cell(square("e4"))].symbol(), "♟");
+        assert_eq!(buffer[tile_cell(square("e4"))].bg, CURSOR_TILE);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::clock::{Clock, Increment, Session, TimeControl};
+use crate::game::parse_move;
+use crate::gamestate::{GameState, MoveKind};
+use crate::piece::{Color, PieceType};
+use crate::search::{Engine, SearchResult, MATE_SCORE};
+use crate::syzygy::Tablebase;
+use std::io::BufRead;
+use std::time::{Duration, Instant};
+
+/// It translates a move in UCI notation such as `e2e4` or `e7e8n` to a move on the board
+///
+/// Arguments:
+///
+/// * `arg`: The move to translate, a promotion ends with the letter of the piece, `q`, `r`, `b` or `n`.
+///
+/// Returns:
+///
+/// The move, None if it is not written as two coordinates and an optional promotion piece.
+pub fn parse_uci_move(arg: &str) -> Option<MoveKind> {
+    let chars: Vec<char> = arg.chars().collect();
+    if chars.len() != 4 && chars.len() != 5 {
+        return None;
+    }
+    let square = |file: char, rank: char| -> Option<usize> {
+        let x = "abcdefgh".find(file.to_ascii_lowercase())?;
+        let y = "12345678".find(rank)?;
+        Some(y * 8 + x)
+    };
+    let (pc, tc) = (square(chars[0], chars[1])?, square(chars[2], chars[3])?);
+    match chars.get(4) {
+        Some(&letter) => Some(MoveKind::promotion(pc, tc, MoveKind::promotion_piece(letter)?)),
+        None => Some(MoveKind::BOARD(pc, tc))
+    }
+}
+
+/// It writes a move of the position in UCI notation, for example `e2e4` or `e7e8q`
+pub fn format_uci_move(gamestate: &GameState, pc: usize, tc: usize) -> String {
+    let promotion = match gamestate.board[pc] {
+        Some(piece) if matches!(piece.piecetype, PieceType::PAWN(_)) && (GameState::coord_y(tc) == 0 || GameState::coord_y(tc) == 7) => "q",
+        _ => ""
+    };
+    format!("{}{}{}", GameState::coordinate_translator_usize(pc), GameState::coordinate_translator_usize(tc), promotion).to_lowercase()
+}
+
+/// It runs the Universal Chess Interface on the input and stdout until the GUI sends `quit`. The
+/// `Threads` and `Hash` options set the threads and transposition table size of the engine.
+///
+/// Arguments:
+///
+/// * `engine`: The engine to search with.
+/// * `input`: Where the GUI's commands are read from, usually the standard input.
+pub fn uci_loop(engine: &mut Engine, input: &mut dyn BufRead) {
+    println!("id name chess");
+    println!("id author David Schalin");
+    println!("option name Threads type spin default 1 min 1 max 256");
+    println!("option name Hash type spin default {} min 1 max 4096", crate::search::DEFAULT_HASH_MB);
+    println!("option name SyzygyPath type string default <empty>");
+    println!("option name UCI_Chess960 type check default false");
+    println!("uciok");
+
+    let mut gamestate = GameState::new();
+    let mut chess960 = false;
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            Some(&"uci") => println!("uciok"),
+            Some(&"isready") => println!("readyok"),
+            Some(&"ucinewgame") => {
+                engine.clear();
+                gamestate = GameState::new();
+            },
+            Some(&"setoption") => set_option(engine, &mut chess960, &words),
+            Some(&"position") => {
+                match parse_position(&words, chess960) {
+                    Ok(position) => gamestate = position,
+                    Err(e) => println!("info string {}", e)
+                }
+            },
+            Some(&"go") => {
+                let result = go(engine, &gamestate, &words);
+                match result.best_move {
+                    Some((pc, tc)) => println!("bestmove {}", format_uci_move(&gamestate, pc, tc)),
+                    None => println!("bestmove 0000")
+                }
+            },
+            Some(&"quit") => break,
+            _ => {}
+        }
+    }
+}
+
+fn set_option(engine: &mut Engine, chess960: &mut bool, words: &[&str]) {
+    let name_start = words.iter().position(|w| *w == "name");
+    let value_start = words.iter().position(|w| *w == "value");
+    let (name_start, value_start) = match (name_start, value_start) {
+        (Some(n), Some(v)) if n < v => (n, v),
+        _ => return
+    };
+    let name = words[name_start + 1..value_start].join(" ");
+    let value = words[value_start + 1..].join(" ");
+    if name.to_lowercase() == "uci_chess960" {
+        *chess960 = value == "true";
+        return;
+    }
+    if name.to_lowercase() == "syzygypath" {
+        engine.tablebase = None;
+        if !value.is_empty() && value != "<empty>" {
+            match Tablebase::open(&value) {
+                Ok(tablebase) => engine.tablebase = Some(tablebase),
+                Err(e) => println!("info string {}", e)
+            }
+        }
+        return;
+    }
+    let value: usize = match value.parse() {
+        Ok(value) => value,
+        Err(_) => {
+            println!("info string invalid value for {}", name);
+            return;
+        }
+    };
+    match name.to_lowercase().as_str() {
+        "threads" => engine.threads = value.clamp(1, 256),
+        "hash" => engine.set_hash_size(value.clamp(1, 4096)),
+        _ => println!("info string unknown option {}", name)
+    }
+}
+
+fn parse_position(words: &[&str], chess960: bool) -> Result<GameState, String> {
+    let moves_start = words.iter().position(|w| *w == "moves");
+    let mut gamestate = match words.get(1) {
+        Some(&"startpos") => GameState::new(),
+        Some(&"fen") => GameState::from_fen(&words[2..moves_start.unwrap_or(words.len())].join(" "))?,
+        Some(other) => return Err(format!("unsupported position '{}'", other)),
+        None => return Err("missing position".to_string())
+    };
+    gamestate.debug_flag = false;
+    gamestate.chess960 |= chess960;
+    if let Some(moves_start) = moves_start {
+        for word in &words[moves_start + 1..] {
+            match parse_move(&gamestate, word) {
+                Some(mv) if gamestate.is_legal_move_kind(mv) => gamestate.make_move_kind(mv),
+                _ => return Err(format!("illegal move {}", word))
+            }
+        }
+    }
+    Ok(gamestate)
+}
+
+fn go(engine: &Engine, gamestate: &GameState, words: &[&str]) -> SearchResult {
+    let value = |key: &str| -> Option<u64> {
+        let i = words.iter().position(|w| *w == key)?;
+        words.get(i + 1)?.parse().ok()
+    };
+    let started = Instant::now();
+    let depth = value("depth").map(|d| d as u32);
+
+    let (time, increment) = match gamestate.current_player {
+        Color::BLACK => (value("btime"), value("binc")),
+        _ => (value("wtime"), value("winc"))
+    };
+    let think_time = match (value("movetime"), time) {
+        (Some(movetime), _) => Some(Duration::from_millis(movetime)),
+        (None, Some(time)) => {
+            let session = Session {
+                moves: value("movestogo").map(|m| m as u32),
+                time: Duration::from_millis(time),
+                increment: Increment::FISCHER(Duration::from_millis(increment.unwrap_or(0))),
+            };
+            let clock = Clock::new(TimeControl { sessions: vec![session] });
+            Some(clock.think_time(Color::WHITE))
+        },
+        (None, None) => None
+    };
+
+    let result = match (think_time, depth) {
+        (Some(time), depth) => engine.search_timed(gamestate, time, depth.unwrap_or(64)),
+        (None, Some(depth)) => engine.search(gamestate, depth),
+        (None, None) => engine.search(gamestate, 4)
+    };
+
+    let score = if result.score.abs() > MATE_SCORE - 1000 {
+        let plies = MATE_SCORE - result.score.abs();
+        format!("mate {}", (plies + 1) / 2 * result.score.signum())
+    } else {
+        format!("cp {}", result.score)
+    };
+    let pv = match result.best_move {
+        Some((pc, tc)) => format!(" pv {}", format_uci_move(gamestate, pc, tc)),
+        None => String::new()
+    };
+    println!("info score {} nodes {} time {}{}", score, result.nodes, started.elapsed().as_millis(), pv);
+    result
+}
@@ -0,0 +1,502 @@
+use crate::gamestate::GameState;
+use crate::piece::{ChessPiece, Color, PieceType};
+use serde::{Serialize, Deserialize};
+
+const WHITE: Color = Color::WHITE;
+const BLACK: Color = Color::BLACK;
+
+/// How a game ended.
+///
+/// * `WIN`: The given color won.
+/// * `DRAW`: Nobody won.
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum Outcome {
+    WIN(Color),
+    DRAW
+}
+
+/// A rule set played on the normal board. Every rule has a default that follows standard chess, a
+/// variant only overrides the rules it changes.
+pub trait Variant: Sync {
+/// The name used to select the variant in the client
+    fn name(&self) -> &'static str;
+
+/// It creates the position the game starts from
+    fn start_position(&self) -> GameState {
+        GameState::new()
+    }
+
+/// It tells whether a position may have the given number of kings of a color, by default exactly one
+    fn allows_king_count(&self, _color: Color, kings: usize) -> bool {
+        kings == 1
+    }
+
+/// It tells whether pawns may stand on the first row of their player, by default they may not
+    fn allows_first_row_pawns(&self) -> bool {
+        false
+    }
+
+/// It tells whether the king of the given color can be checked at all, by default always. A king that
+/// can not be checked does not have to be kept out of check.
+    fn can_be_checked(&self, _gamestate: &GameState, _color: Color) -> bool {
+        true
+    }
+
+/// It decides whether a move that follows the movement rules of the piece may be played
+///
+/// Arguments:
+///
+/// * `gamestate`: The position before the move.
+/// * `pc`: The piece's current coordinate
+/// * `tc`: Target Coordinate
+///
+/// Returns:
+///
+/// True if the move is allowed, by default when it does not leave the own king checked.
+    fn allows_move(&self, gamestate: &GameState, pc: usize, tc: usize) -> bool {
+        !gamestate.leaves_king_checked(pc, tc)
+    }
+
+/// It removes moves from the list of allowed moves of the current player, for rules that depend on
+/// all the moves at once such as compulsory captures
+    fn filter_moves(&self, _gamestate: &mut GameState, moves: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        moves
+    }
+
+/// It tells whether captured pieces go to the pocket of the capturing player, to be dropped back on
+/// the board later, by default they do not
+    fn has_pockets(&self) -> bool {
+        false
+    }
+
+/// It tells whether filter_moves may remove a move, otherwise a single move can be checked without
+/// generating all the others
+    fn filters_moves(&self) -> bool {
+        false
+    }
+
+/// It changes the position after a move has been applied to the board
+///
+/// Arguments:
+///
+/// * `gamestate`: The position after the move, still with the moving player in turn.
+/// * `color`: The color of the player that moved.
+/// * `tc`: The coordinate the piece landed on.
+/// * `capture`: True if the move captured a piece.
+    fn after_move(&self, _gamestate: &mut GameState, _color: Color, _tc: usize, _capture: bool) {}
+
+/// It returns the player that has won by a rule of the variant, before looking at the moves left
+    fn winner(&self, _gamestate: &GameState) -> Option<Color> {
+        None
+    }
+
+/// It decides the game when the current player has no moves left, by default checkmate wins for the
+/// other player and stalemate is a draw
+    fn no_moves_outcome(&self, gamestate: &GameState) -> Outcome {
+        if gamestate.checked_flag && gamestate.checked_player == gamestate.current_player {
+            Outcome::WIN(gamestate.current_player.opposite())
+        } else {
+            Outcome::DRAW
+        }
+    }
+
+/// It tells whether the game is drawn even though moves are left, by default by the fifty move rule
+/// or when only the kings are left
+    fn is_draw(&self, gamestate: &GameState) -> bool {
+        gamestate.halfmove_clock >= 100 || gamestate.piece_count() <= 2
+    }
+}
+
+/// The rule sets that ship with the game, stored in the GameState so a saved game keeps its rules.
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Default)]
+pub enum VariantKind {
+    #[default]
+    STANDARD,
+    KINGOFTHEHILL,
+    THREECHECK,
+    ANTICHESS,
+    ATOMIC,
+    HORDE,
+    CRAZYHOUSE
+}
+
+impl VariantKind {
+    pub const ALL: [VariantKind; 7] = [VariantKind::STANDARD, VariantKind::KINGOFTHEHILL, VariantKind::THREECHECK, VariantKind::ANTICHESS, VariantKind::ATOMIC, VariantKind::HORDE, VariantKind::CRAZYHOUSE];
+
+/// It returns the rules of the variant
+    pub fn rules(&self) -> &'static dyn Variant {
+        match self {
+            VariantKind::STANDARD => &Standard,
+            VariantKind::KINGOFTHEHILL => &KingOfTheHill,
+            VariantKind::THREECHECK => &ThreeCheck,
+            VariantKind::ANTICHESS => &Antichess,
+            VariantKind::ATOMIC => &Atomic,
+            VariantKind::HORDE => &Horde,
+            VariantKind::CRAZYHOUSE => &Crazyhouse
+        }
+    }
+
+/// It finds a variant by its name, ignoring case
+    pub fn from_name(name: &str) -> Option<VariantKind> {
+        VariantKind::ALL.into_iter().find(|variant| variant.rules().name().eq_ignore_ascii_case(name.trim()))
+    }
+
+/// It creates the start position of the variant, with the variant stored in it
+    pub fn start_position(&self) -> GameState {
+        let mut gamestate = self.rules().start_position();
+        gamestate.variant = *self;
+        gamestate
+    }
+}
+
+/// Standard chess, every rule keeps its default.
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "standard"
+    }
+}
+
+/// A player also wins by bringing their king to one of the four center tiles.
+pub struct KingOfTheHill;
+
+/// d4, e4, d5 and e5
+const CENTER: [usize; 4] = [27, 28, 35, 36];
+
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &'static str {
+        "kingofthehill"
+    }
+
+    fn winner(&self, gamestate: &GameState) -> Option<Color> {
+        CENTER.iter().find_map(|&coord| match gamestate.board[coord] {
+            Some(piece) if matches!(piece.piecetype, PieceType::KING(_)) => Some(piece.color),
+            _ => None
+        })
+    }
+}
+
+/// A player also wins by checking the other king three times.
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn name(&self) -> &'static str {
+        "threecheck"
+    }
+
+    fn after_move(&self, gamestate: &mut GameState, color: Color, _tc: usize, _capture: bool) {
+        if gamestate.is_checked(color.opposite()) {
+            gamestate.checks_given[GameState::pocket_owner(color)] += 1;
+        }
+    }
+
+    fn winner(&self, gamestate: &GameState) -> Option<Color> {
+        [WHITE, BLACK].into_iter().find(|&color| gamestate.checks_given[GameState::pocket_owner(color)] >= 3)
+    }
+}
+
+/// Captures are compulsory, the king is an ordinary piece, and a player wins by losing all their
+/// pieces or by having no moves left.
+pub struct Antichess;
+
+impl Variant for Antichess {
+    fn name(&self) -> &'static str {
+        "antichess"
+    }
+
+    fn allows_king_count(&self, _color: Color, _kings: usize) -> bool {
+        true
+    }
+
+    fn can_be_checked(&self, _gamestate: &GameState, _color: Color) -> bool {
+        false
+    }
+
+    fn allows_move(&self, gamestate: &GameState, pc: usize, tc: usize) -> bool {
+        !gamestate.is_castling(pc, tc)
+    }
+
+    fn filter_moves(&self, gamestate: &mut GameState, moves: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        if moves.iter().any(|&(pc, tc)| gamestate.is_capture(pc, tc)) {
+            moves.into_iter().filter(|&(pc, tc)| gamestate.is_capture(pc, tc)).collect()
+        } else {
+            moves
+        }
+    }
+
+    fn filters_moves(&self) -> bool {
+        true
+    }
+
+    fn winner(&self, gamestate: &GameState) -> Option<Color> {
+        [WHITE, BLACK].into_iter().find(|&color| !gamestate.board.iter().flatten().any(|piece| piece.color == color))
+    }
+
+    fn no_moves_outcome(&self, gamestate: &GameState) -> Outcome {
+        Outcome::WIN(gamestate.current_player)
+    }
+
+    fn is_draw(&self, gamestate: &GameState) -> bool {
+        gamestate.halfmove_clock >= 100
+    }
+}
+
+/// Every capture explodes the capturing piece and all pieces other than pawns around the target tile,
+/// and a player wins by blowing up the other king. Kings can not capture, since they would explode
+/// themselves, and a move may not blow up the own king. Blowing up the other king wins even when the
+/// own king is left attacked, so a king next to the other king can not be checked.
+pub struct Atomic;
+
+impl Variant for Atomic {
+    fn name(&self) -> &'static str {
+        "atomic"
+    }
+
+    fn can_be_checked(&self, gamestate: &GameState, color: Color) -> bool {
+        let (own, other) = if color == WHITE { (gamestate.wkc, gamestate.bkc) } else { (gamestate.bkc, gamestate.wkc) };
+        own > 63 || other > 63 || GameState::abs_diff_x(own, other) > 1 || GameState::abs_diff_y(own, other) > 1
+    }
+
+    fn allows_move(&self, gamestate: &GameState, pc: usize, tc: usize) -> bool {
+        let piece = match gamestate.board[pc] {
+            Some(piece) => piece,
+            None => return false
+        };
+        if matches!(piece.piecetype, PieceType::KING(_)) && gamestate.is_capture(pc, tc) {
+            return false;
+        }
+        let mut next_state = gamestate.preview_move(pc, tc);
+        let (own_king, other_king) = if piece.color == WHITE { (next_state.wkc, next_state.bkc) } else { (next_state.bkc, next_state.wkc) };
+        if own_king > 63 {
+            return false;
+        }
+        other_king > 63 || !next_state.is_checked(piece.color)
+    }
+
+    fn after_move(&self, gamestate: &mut GameState, _color: Color, tc: usize, capture: bool) {
+        if capture {
+            gamestate.explode(tc);
+        }
+    }
+
+    fn winner(&self, gamestate: &GameState) -> Option<Color> {
+        let has_king = |color: Color| gamestate.board.iter().flatten().any(|piece| piece.color == color && matches!(piece.piecetype, PieceType::KING(_)));
+        [WHITE, BLACK].into_iter().find(|&color| !has_king(color.opposite()))
+    }
+
+    fn is_draw(&self, gamestate: &GameState) -> bool {
+        gamestate.halfmove_clock >= 100
+    }
+}
+
+/// White has 36 pawns and no king against the normal black army. Black wins by capturing every white
+/// piece, White by checkmating Black.
+pub struct Horde;
+
+impl Variant for Horde {
+    fn name(&self) -> &'static str {
+        "horde"
+    }
+
+    fn start_position(&self) -> GameState {
+        let mut gamestate = GameState::new();
+        for coord in 0..40 {
+            gamestate.board[coord] = None;
+            if coord < 32 || matches!(GameState::coord_x(coord), 1 | 2 | 5 | 6) {
+                gamestate.board[coord] = Some(ChessPiece::new(PieceType::PAWN(GameState::coord_y(coord) > 1), WHITE));
+            }
+        }
+        gamestate.prev_board = gamestate.board;
+        gamestate.wkc = 64;
+        gamestate.old_wkc = 64;
+        gamestate
+    }
+
+    fn allows_king_count(&self, color: Color, kings: usize) -> bool {
+        kings == if color == WHITE { 0 } else { 1 }
+    }
+
+    fn allows_first_row_pawns(&self) -> bool {
+        true
+    }
+
+    fn winner(&self, gamestate: &GameState) -> Option<Color> {
+        if gamestate.board.iter().flatten().any(|piece| piece.color == WHITE) {
+            None
+        } else {
+            Some(BLACK)
+        }
+    }
+
+    fn is_draw(&self, gamestate: &GameState) -> bool {
+        gamestate.halfmove_clock >= 100
+    }
+}
+
+/// Captured pieces change sides and go to the pocket of the capturing player, who may drop them back
+/// on an empty tile instead of moving. Promoted pieces go back to the pocket as pawns.
+pub struct Crazyhouse;
+
+impl Variant for Crazyhouse {
+    fn name(&self) -> &'static str {
+        "crazyhouse"
+    }
+
+    fn has_pockets(&self) -> bool {
+        true
+    }
+
+    fn is_draw(&self, gamestate: &GameState) -> bool {
+        // Bare kings can still be mated by the pieces in the pockets
+        gamestate.halfmove_clock >= 100 || (gamestate.piece_count() <= 2 && gamestate.pockets == [[0; 5]; 2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> usize {
+        GameState::parse_square(name).unwrap()
+    }
+
+    fn atomic(fen: &str) -> GameState {
+        GameState::from_fen_variant(fen, VariantKind::ATOMIC).unwrap()
+    }
+
+    #[test]
+    fn atomic_king_cannot_capture() {
+        let fen = "4k3/8/8/8/8/8/3n4/4K3 w - - 0 1";
+        assert!(GameState::from_fen(fen).unwrap().is_legal_move(square("e1"), square("d2")));
+        let mut gamestate = atomic(fen);
+        assert!(!gamestate.is_legal_move(square("e1"), square("d2")));
+        assert!(gamestate.is_legal_move(square("e1"), square("f2")));
+    }
+
+    #[test]
+    fn atomic_capture_may_not_blow_up_the_own_king() {
+        let mut gamestate = atomic("4k3/8/8/8/8/3R4/3n4/4K3 w - - 0 1");
+        assert!(!gamestate.is_legal_move(square("d3"), square("d2")));
+        assert!(!gamestate.legal_moves().contains(&(square("d3"), square("d2"))));
+    }
+
+    #[test]
+    fn atomic_capture_next_to_the_other_king_wins() {
+        // The white king stays attacked by the rook on h1, blowing up the black king wins anyway
+        let mut gamestate = atomic("4k3/3n4/8/8/8/8/8/3RK2r w - - 0 1");
+        assert!(gamestate.is_legal_move(square("d1"), square("d7")));
+        gamestate.make_move(square("d1"), square("d7"));
+        assert!(gamestate.board[square("e8")].is_none());
+        assert!(gamestate.board[square("d7")].is_none());
+        assert_eq!(gamestate.bkc, 64);
+        assert_eq!(gamestate.outcome(), Some(Outcome::WIN(WHITE)));
+    }
+
+    #[test]
+    fn atomic_kings_next_to_each_other_are_not_checked() {
+        let mut gamestate = atomic("8/8/8/8/8/3kK2r/P7/8 w - - 0 1");
+        assert!(!Atomic.can_be_checked(&gamestate, WHITE));
+        gamestate.checked_checker();
+        assert!(!gamestate.checked_flag);
+        assert!(gamestate.is_legal_move(square("a2"), square("a3")));
+        // Stepping away from the black king walks into the rook
+        assert!(!gamestate.is_legal_move(square("e3"), square("f3")));
+    }
+
+    #[test]
+    fn atomic_en_passant_explodes_around_the_target_tile() {
+        let mut gamestate = atomic("4k3/2npp3/8/4P3/8/8/8/4K3 b - - 0 1");
+        gamestate.make_move(square("d7"), square("d5"));
+        assert!(gamestate.is_legal_move(square("e5"), square("d6")));
+        gamestate.make_move(square("e5"), square("d6"));
+        for name in ["d6", "d5", "e5", "c7"] {
+            assert!(gamestate.board[square(name)].is_none(), "{} should be empty", name);
+        }
+        assert!(matches!(gamestate.board[square("e7")], Some(p) if p.color == BLACK && matches!(p.piecetype, PieceType::PAWN(_))));
+        assert!(matches!(gamestate.board[square("e8")], Some(p) if matches!(p.piecetype, PieceType::KING(_))));
+    }
+
+    #[test]
+    fn king_of_the_hill_king_on_a_center_tile_wins() {
+        let mut gamestate = GameState::from_fen_variant("4k3/p7/8/8/8/4K3/8/8 w - - 0 1", VariantKind::KINGOFTHEHILL).unwrap();
+        assert_eq!(gamestate.outcome(), None);
+        gamestate.make_move(square("e3"), square("e4"));
+        assert_eq!(gamestate.outcome(), Some(Outcome::WIN(WHITE)));
+
+        // Only kings claim the hill
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/8/8/3Q4/8/4K3 w - - 0 1", VariantKind::KINGOFTHEHILL).unwrap();
+        gamestate.make_move(square("d3"), square("d4"));
+        assert_eq!(gamestate.outcome(), None);
+    }
+
+    #[test]
+    fn three_check_third_check_wins() {
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", VariantKind::THREECHECK).unwrap();
+        gamestate.checks_given = [2, 0];
+        gamestate.make_move(square("a1"), square("a2"));
+        assert_eq!(gamestate.checks_given, [2, 0]);
+        assert_eq!(gamestate.outcome(), None);
+        gamestate.make_move(square("e8"), square("e7"));
+        gamestate.make_move(square("a2"), square("e2"));
+        assert_eq!(gamestate.checks_given, [3, 0]);
+        assert_eq!(gamestate.outcome(), Some(Outcome::WIN(WHITE)));
+    }
+
+    #[test]
+    fn antichess_captures_are_compulsory() {
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/3p4/4P3/8/P7/4K3 w - - 0 1", VariantKind::ANTICHESS).unwrap();
+        assert_eq!(gamestate.legal_moves(), vec![(square("e4"), square("d5"))]);
+        // Without a capture to make every move is allowed
+        gamestate.make_move(square("e4"), square("d5"));
+        assert!(gamestate.legal_moves().contains(&(square("e8"), square("d8"))));
+    }
+
+    #[test]
+    fn antichess_losing_every_piece_wins() {
+        let mut gamestate = GameState::from_fen_variant("8/8/8/8/8/8/1p6/R7 b - - 0 1", VariantKind::ANTICHESS).unwrap();
+        assert_eq!(gamestate.outcome(), None);
+        gamestate.make_move(square("b2"), square("a1"));
+        assert_eq!(gamestate.outcome(), Some(Outcome::WIN(WHITE)));
+    }
+
+    #[test]
+    fn horde_starts_with_36_pawns_and_black_wins_by_taking_them_all() {
+        let mut gamestate = VariantKind::HORDE.start_position();
+        let white: Vec<ChessPiece> = gamestate.board.iter().flatten().filter(|piece| piece.color == WHITE).copied().collect();
+        assert_eq!(white.len(), 36);
+        assert!(white.iter().all(|piece| matches!(piece.piecetype, PieceType::PAWN(_))));
+        assert_eq!(gamestate.wkc, 64);
+        assert_eq!(gamestate.outcome(), None);
+        assert_eq!(GameState::from_fen_variant(&gamestate.to_fen(), VariantKind::HORDE).unwrap().to_fen(), gamestate.to_fen());
+        // The pawns on the first row may not jump over the second
+        assert!(!gamestate.is_legal_move(square("a1"), square("a3")));
+
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/8/8/8/8/3qP3 b - - 0 1", VariantKind::HORDE).unwrap();
+        assert_eq!(gamestate.outcome(), None);
+        gamestate.make_move(square("d1"), square("e1"));
+        assert_eq!(gamestate.outcome(), Some(Outcome::WIN(BLACK)));
+    }
+
+    #[test]
+    fn crazyhouse_is_a_variant_with_pockets() {
+        assert_eq!(VariantKind::from_name("Crazyhouse"), Some(VariantKind::CRAZYHOUSE));
+        let start = VariantKind::CRAZYHOUSE.start_position();
+        assert!(start.to_fen().starts_with("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w "));
+        // A standard FEN with pockets is read as Crazyhouse, the other variants have no pockets
+        assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/8/4K3[Qq] w - - 0 1").unwrap().variant, VariantKind::CRAZYHOUSE);
+        assert!(GameState::from_fen_variant("4k3/8/8/8/8/8/8/4K3[Qq] w - - 0 1", VariantKind::ATOMIC).is_err());
+
+        let mut gamestate = GameState::from_fen_variant("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", VariantKind::CRAZYHOUSE).unwrap();
+        gamestate.make_move(square("e4"), square("d5"));
+        assert_eq!(gamestate.pockets[0], [1, 0, 0, 0, 0]);
+        assert!(gamestate.legal_drops().is_empty());
+        gamestate.make_move(square("e8"), square("e7"));
+        assert!(gamestate.legal_drops().contains(&(PieceType::PAWN(true), square("e4"))));
+        // Bare kings are no draw while a pocket holds a piece
+        let mut bare = GameState::from_fen_variant("4k3/8/8/8/8/8/8/4K3[Q] w - - 0 1", VariantKind::CRAZYHOUSE).unwrap();
+        assert_eq!(bare.outcome(), None);
+        bare.pockets = [[0; 5]; 2];
+        assert_eq!(bare.outcome(), Some(Outcome::DRAW));
+    }
+}